@@ -15,8 +15,8 @@ async fn main() {
         .expect("Error occurred while initialization");
 
     // subscribe to pending transactions
-    let mut stream = env
-        .wss_provider
+    let wss_provider = env.ws[0].provider();
+    let mut stream = wss_provider
         .subscribe_pending_txs()
         .await
         .expect("Error while subscribing to pending transactions topic");
@@ -13,8 +13,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .expect("Error occurred while initialization");
 
     // subscribe to pending transactions
-    let mut stream = env
-        .wss_provider
+    let wss_provider = env.ws[0].provider();
+    let mut stream = wss_provider
         .subscribe_pending_txs()
         .await
         .expect("Error while subscribing to pending transactions topic");
@@ -24,7 +24,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let sender = Arc::new(sender);
 
     //
-    let arc_wss_provider = Arc::clone(&env.wss_provider);
+    let arc_wss_provider = Arc::clone(&wss_provider);
     let subscription_id = stream.id;
 
     // channel receiver tokio thread
@@ -1,139 +1,117 @@
-use std::str::FromStr;
 use std::sync::Arc;
 
+use ethers::abi::Detokenize;
 use ethers::contract::Contract;
 use ethers::prelude::{
-    Address, Http, LocalWallet, Middleware, Provider, SignerMiddleware, TransactionRequest, I256,
+    Address, Http, LocalWallet, Middleware, Provider, SignerMiddleware, TransactionReceipt,
+    TransactionRequest, I256,
 };
-use ethers::types::U256;
+use ethers::providers::RawCall;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{spoof, H256, U256};
 
 use tracing::instrument;
 
 use crate::util;
+use crate::util::error::BlockBotError;
+use crate::util::gas::{self, TxType};
+use crate::util::safety::{allowance_key, balance_key, u256_to_h256};
+use crate::util::submit::TxSubmitter;
+
+/// storage slots tried while locating token mappings
+const MAX_PROBE_SLOTS: u64 = 32;
+
+/// value written to probed slots, unlikely to be a real balance
+const PROBE_VALUE: u64 = 0x0b10_cb07;
 
 #[derive(Debug)]
 pub struct Bep20Token {
     token_contract_address: Address,
     token_contract: Contract<Provider<Http>>,
     signer: SignerMiddleware<Arc<Provider<Http>>, LocalWallet>,
+    submitter: Arc<TxSubmitter>,
 }
 
 // TODO check how can we reuse the common struct data members and associated ::new method
 impl Bep20Token {
-    #[instrument]
+    #[instrument(skip(provider, signer))]
     pub fn new(
         token_contract_address: Address,
         token_contract_abi_path: String,
         provider: Arc<Provider<Http>>,
         signer: LocalWallet,
-    ) -> Bep20Token {
-        Bep20Token {
-            token_contract_address: token_contract_address.clone(),
+        submitter: Arc<TxSubmitter>,
+    ) -> Result<Bep20Token, BlockBotError> {
+        Ok(Bep20Token {
+            token_contract_address,
             token_contract: util::Util::get_contract(
                 &token_contract_address,
                 &token_contract_abi_path,
                 Arc::clone(&provider),
-            ),
+            )?,
             signer: SignerMiddleware::new(provider, signer),
-        }
+            submitter,
+        })
     }
 
     #[instrument]
-    pub async fn get_name(&self) -> String {
-        self.token_contract
-            .method::<_, String>("name", ())
-            .unwrap()
-            .call()
-            .await
-            .expect("error while method call name")
+    pub async fn get_name(&self) -> Result<String, BlockBotError> {
+        self.call_method("name", ()).await
     }
 
     #[instrument]
-    pub async fn get_symbol(&self) -> String {
-        self.token_contract
-            .method::<_, String>("symbol", ())
-            .unwrap()
-            .call()
-            .await
-            .expect("error while method call symbol")
+    pub async fn get_symbol(&self) -> Result<String, BlockBotError> {
+        self.call_method("symbol", ()).await
     }
 
     #[instrument]
-    pub async fn get_balance(&self, address: &str) -> U256 {
-        self.token_contract
-            .method::<_, U256>("balanceOf", Address::from_str(address).unwrap())
-            .unwrap()
-            .call()
-            .await
-            .expect("error while method call balanceOf")
+    pub async fn get_balance(&self, address: Address) -> Result<U256, BlockBotError> {
+        self.call_method("balanceOf", address).await
     }
 
     #[instrument]
-    pub async fn get_decimals(&self) -> I256 {
-        self.token_contract
-            .method::<_, I256>("decimals", ())
-            .unwrap()
-            .call()
-            .await
-            .expect("error while method call decimals")
+    pub async fn get_decimals(&self) -> Result<I256, BlockBotError> {
+        self.call_method("decimals", ()).await
     }
 
     #[instrument]
-    pub async fn get_total_supply(&self) -> U256 {
-        self.token_contract
-            .method::<_, U256>("totalSupply", ())
-            .unwrap()
-            .call()
-            .await
-            .expect("error while method call totalSupply")
+    pub async fn get_total_supply(&self) -> Result<U256, BlockBotError> {
+        self.call_method("totalSupply", ()).await
     }
 
     #[instrument]
-    pub async fn get_spend_allowance(&self, owner: &str, spender: Address) -> U256 {
-        self.token_contract
-            .method::<_, U256>(
-                "allowance",
-                (
-                    Address::from_str(owner).expect("invalid owner address"),
-                    spender,
-                ),
-            )
-            .unwrap()
-            .call()
-            .await
-            .expect("error while method call allowance")
+    pub async fn get_spend_allowance(
+        &self,
+        owner: Address,
+        spender: Address,
+    ) -> Result<U256, BlockBotError> {
+        self.call_method("allowance", (owner, spender)).await
     }
 
     #[instrument]
-    pub async fn approve_spend_allowance(&self, spender: Address, amount: U256) {
-        let encoded_data = self
-            .token_contract
-            .encode("approve", (spender, amount))
-            .expect("encoding error");
+    pub async fn approve_spend_allowance(
+        &self,
+        spender: Address,
+        amount: U256,
+        tx_type: TxType,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        let encoded_data = self.token_contract.encode("approve", (spender, amount))?;
 
         let tx_req = TransactionRequest::new()
             .from(self.signer.address())
             .to(self.token_contract.address())
             .data(encoded_data);
+        let tx_req = gas::typed_transaction(tx_req, tx_type);
 
         tracing::info!("submitting tx");
-
-        let pending_tx = self
-            .signer
-            .send_transaction(tx_req, None)
-            .await
-            .expect("problem while tx exec");
-
         println!("{}: tx submitted", chrono::Utc::now());
 
-        let receipt_opt = pending_tx
-            .confirmations(1)
-            .await
-            .expect("pending tx exec error");
+        let intent = format!("approve {:?} {:?}", self.token_contract_address, spender);
+        let receipt_opt = self.submitter.submit(&self.signer, tx_req, &intent).await?;
 
         println!("{}: got tx confirmation", chrono::Utc::now());
 
-        if let Some(reciept) = receipt_opt {
+        if let Some(reciept) = &receipt_opt {
             println!(
                 "\n## executed transaction {:#?}\n",
                 reciept.transaction_hash
@@ -141,10 +119,71 @@ impl Bep20Token {
         } else {
             println!("Reciept nt found")
         }
+        Ok(receipt_opt)
+    }
+
+    /// storage slot of the `balanceOf` mapping, None when it is not a plain solidity mapping
+    #[instrument]
+    pub async fn find_balance_slot(&self, holder: Address) -> Result<Option<U256>, BlockBotError> {
+        self.probe_slot("balanceOf", holder, |slot| balance_key(slot, holder))
+            .await
+    }
+
+    /// storage slot of the `allowance` mapping, None when it is not a plain solidity mapping
+    #[instrument]
+    pub async fn find_allowance_slot(
+        &self,
+        owner: Address,
+        spender: Address,
+    ) -> Result<Option<U256>, BlockBotError> {
+        self.probe_slot("allowance", (owner, spender), |slot| {
+            allowance_key(slot, owner, spender)
+        })
+        .await
     }
 
     #[instrument]
     pub fn get_token_address(&self) -> &Address {
         &self.token_contract_address
     }
+
+    /// override candidate slots in `eth_call` until the getter returns the probe value
+    async fn probe_slot<T: ethers::abi::Tokenize, F: Fn(U256) -> H256>(
+        &self,
+        method: &'static str,
+        args: T,
+        storage_key: F,
+    ) -> Result<Option<U256>, BlockBotError> {
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(self.token_contract_address)
+            .data(self.token_contract.encode(method, args)?)
+            .into();
+        let probe_value = U256::from(PROBE_VALUE);
+
+        for slot in (0..MAX_PROBE_SLOTS).map(U256::from) {
+            let state = spoof::storage(
+                self.token_contract_address,
+                storage_key(slot),
+                u256_to_h256(probe_value),
+            );
+            let output = self.signer.inner().call_raw(&tx).state(&state).await?;
+            let value: U256 = self.token_contract.decode_output(method, output)?;
+            if value == probe_value {
+                return Ok(Some(slot));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn call_method<T: ethers::abi::Tokenize, D: Detokenize>(
+        &self,
+        method: &'static str,
+        args: T,
+    ) -> Result<D, BlockBotError> {
+        self.token_contract
+            .method::<_, D>(method, args)?
+            .call()
+            .await
+            .map_err(|e| BlockBotError::ContractCall(method, e))
+    }
 }
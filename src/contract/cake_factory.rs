@@ -1 +1,89 @@
+use std::sync::Arc;
 
+use ethers::contract::{AbiError, Contract};
+use ethers::prelude::{Address, Http, Log, Provider, H256, U256};
+use tracing::instrument;
+
+use crate::util;
+use crate::util::error::BlockBotError;
+
+const PAIR_CREATED: &str = "PairCreated";
+
+/// `PairCreated` event of the factory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairCreated {
+    pub token0: Address,
+    pub token1: Address,
+    pub pair: Address,
+}
+
+impl PairCreated {
+    /// the other token of the pair, None when `token` is not in it
+    pub fn other(&self, token: &Address) -> Option<Address> {
+        if *token == self.token0 {
+            Some(self.token1)
+        } else if *token == self.token1 {
+            Some(self.token0)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CakeFactory {
+    factory_contract: Contract<Provider<Http>>,
+}
+
+impl CakeFactory {
+    #[instrument(skip(provider))]
+    pub fn new(
+        factory_contract_address: Address,
+        factory_contract_abi_path: String,
+        provider: Arc<Provider<Http>>,
+    ) -> Result<CakeFactory, BlockBotError> {
+        Ok(CakeFactory {
+            factory_contract: util::Util::get_contract(
+                &factory_contract_address,
+                &factory_contract_abi_path,
+                provider,
+            )?,
+        })
+    }
+
+    pub fn address(&self) -> Address {
+        self.factory_contract.address()
+    }
+
+    /// pair of `token_a` and `token_b`, None when it is not created yet
+    #[instrument]
+    pub async fn get_pair(
+        &self,
+        token_a: Address,
+        token_b: Address,
+    ) -> Result<Option<Address>, BlockBotError> {
+        let pair: Address = self
+            .factory_contract
+            .method::<_, Address>("getPair", (token_a, token_b))?
+            .call()
+            .await
+            .map_err(|e| BlockBotError::ContractCall("getPair", e))?;
+        Ok(Some(pair).filter(|pair| !pair.is_zero()))
+    }
+
+    /// topic0 of `PairCreated`
+    pub fn pair_created_topic(&self) -> Result<H256, AbiError> {
+        Ok(self.factory_contract.abi().event(PAIR_CREATED)?.signature())
+    }
+
+    pub fn decode_pair_created(&self, log: &Log) -> Result<PairCreated, AbiError> {
+        let (token0, token1, pair, _): (Address, Address, Address, U256) = self
+            .factory_contract
+            .decode_event(PAIR_CREATED, log.topics.clone(), log.data.clone())?;
+        Ok(PairCreated {
+            token0,
+            token1,
+            pair,
+        })
+    }
+}
@@ -1,12 +1,19 @@
+use std::fmt;
 use std::sync::Arc;
 
 use ethers::prelude::{
-    Address, Http, LocalWallet, Middleware, Provider, Selector, SignerMiddleware,
-    TransactionRequest,
+    Address, BlockId, BlockNumber, Http, LocalWallet, Middleware, Provider, Selector,
+    SignerMiddleware, TransactionReceipt, TransactionRequest,
 };
-use ethers::types::U256;
+use ethers::providers::RawCall;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{spoof, U256};
 
 use crate::util;
+use crate::util::error::BlockBotError;
+use crate::util::gas::{GasFees, TxGas, TxType};
+use crate::util::submit::TxSubmitter;
+use crate::util::trade::{apply_slippage, OutputLimits, SwapVariant, TradeSettings};
 use chrono::Duration;
 use ethers::abi::Detokenize;
 use ethers::contract::{AbiError, Contract};
@@ -14,10 +21,65 @@ use std::ops::Add;
 
 use tracing::instrument;
 
+const SWAP_ETH_FOR_TOKENS: &str = "swapExactETHForTokens";
+const SWAP_ETH_FOR_TOKENS_FEE_ON_TRANSFER: &str =
+    "swapExactETHForTokensSupportingFeeOnTransferTokens";
+const SWAP_TOKENS_FOR_ETH_FEE_ON_TRANSFER: &str =
+    "swapExactTokensForETHSupportingFeeOnTransferTokens";
+
+/// Swap outcome simulated against the pending block, nothing is signed or sent
+#[derive(Debug, Clone)]
+pub struct SimulationReport {
+    pub method: &'static str,
+    /// revert error, None when the swap would succeed
+    pub revert: Option<String>,
+    /// token output returned by the call
+    /// fee-on-transfer variants return nothing so the router quote is used
+    pub expected_out: U256,
+    /// minimum output the swap accepts
+    pub min_out: U256,
+    /// estimated gas, None when estimation failed
+    pub gas_used: Option<U256>,
+}
+
+impl SimulationReport {
+    pub fn would_revert(&self) -> bool {
+        self.revert.is_some()
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.revert {
+            Some(revert) => write!(f, "{} would revert: {}", self.method, revert)?,
+            None => write!(f, "{} would succeed", self.method)?,
+        }
+        write!(
+            f,
+            ", expected out: {}, min out: {}, gas used: ",
+            self.expected_out, self.min_out
+        )?;
+        match self.gas_used {
+            Some(gas_used) => write!(f, "{}", gas_used),
+            None => f.write_str("unknown"),
+        }
+    }
+}
+
+/// Swap request along with the router quote it was built from
+#[derive(Debug)]
+struct SwapTx {
+    method: &'static str,
+    request: TransactionRequest,
+    max_out: U256,
+    min_out: U256,
+}
+
 #[derive(Debug)]
 pub struct CakeRouter {
     token_contract: Contract<Provider<Http>>,
     signer: SignerMiddleware<Arc<Provider<Http>>, LocalWallet>,
+    submitter: Arc<TxSubmitter>,
 }
 // TODO check how can we reuse the common struct data members and associated ::new method
 impl CakeRouter {
@@ -27,16 +89,18 @@ impl CakeRouter {
         token_contract_abi_path: String,
         provider: Arc<Provider<Http>>,
         signer: LocalWallet,
-    ) -> CakeRouter {
+        submitter: Arc<TxSubmitter>,
+    ) -> Result<CakeRouter, BlockBotError> {
         tracing::info!("instantiating new cake_router");
-        CakeRouter {
+        Ok(CakeRouter {
             token_contract: util::Util::get_contract(
                 &token_contract_address,
                 &token_contract_abi_path,
                 provider.clone(),
-            ),
+            )?,
             signer: SignerMiddleware::new(provider, signer),
-        }
+            submitter,
+        })
     }
 
     #[instrument]
@@ -45,13 +109,19 @@ impl CakeRouter {
         amount_in: U256,
         token_a: Address,
         token_b: Address,
-    ) -> U256 {
-        self.token_contract
-            .method::<_, U256>("getAmountsOut", (amount_in, vec![token_a, token_b]))
-            .expect("method_creation")
+    ) -> Result<U256, BlockBotError> {
+        let amounts = self
+            .token_contract
+            .method::<_, Vec<U256>>("getAmountsOut", (amount_in, vec![token_a, token_b]))?
             .call()
             .await
-            .expect("method call except")
+            .map_err(|e| BlockBotError::ContractCall("getAmountsOut", e))?;
+
+        // last amount is the output of the whole path
+        amounts
+            .last()
+            .copied()
+            .ok_or(BlockBotError::UnexpectedResponse("getAmountsOut"))
     }
 
     #[instrument]
@@ -60,120 +130,408 @@ impl CakeRouter {
         spend_amount: U256,
         wbnb: Address,
         token: Address,
-        slippage: u8,
-        gas: U256,
-        gas_price: U256,
-    ) {
-        let max_out = self.get_amounts_out(spend_amount, wbnb, token).await;
-        let u256 = max_out
-            .checked_mul(U256::from(100 - slippage))
-            .expect("mul_error");
-        let min_amount = u256.checked_div(U256::from(100u8)).expect("div error");
-
-        let encoded_data = self
-            .token_contract
-            .encode(
-                "swapExactETHForTokens",
-                (
-                    min_amount,
-                    vec![wbnb, token],
-                    self.signer.address(),
-                    U256::from(
-                        chrono::Utc::now()
-                            .add(Duration::seconds(10))
-                            .timestamp_millis(),
-                    ),
-                ),
-            )
-            .expect("encoding error");
+        slippage_bps: u16,
+        tx_gas: TxGas,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        let limits = OutputLimits {
+            slippage_bps,
+            token_tax_bps: 0,
+        };
+        let swap = self
+            .eth_for_tokens_swap(SWAP_ETH_FOR_TOKENS, spend_amount, wbnb, token, limits)
+            .await?;
 
-        let tx_req = TransactionRequest::new()
-            .from(self.signer.address())
-            .to(self.token_contract.address())
-            .value(spend_amount)
-            .data(encoded_data)
-            .gas(gas)
-            .gas_price(gas_price);
+        self.send_monitor_tx(tx_gas.apply(swap.request), swap.method, token)
+            .await
+    }
 
-        self.send_monitor_tx(tx_req).await;
+    /// buy variant which does not revert when the token takes a transfer tax
+    #[instrument]
+    pub async fn swap_exact_eth_for_tokens_supporting_fee_on_transfer_tokens(
+        &self,
+        spend_amount: U256,
+        wbnb: Address,
+        token: Address,
+        limits: OutputLimits,
+        tx_gas: TxGas,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        let swap = self
+            .eth_for_tokens_swap(
+                SWAP_ETH_FOR_TOKENS_FEE_ON_TRANSFER,
+                spend_amount,
+                wbnb,
+                token,
+                limits,
+            )
+            .await?;
+
+        self.send_monitor_tx(tx_gas.apply(swap.request), swap.method, token)
+            .await
     }
 
     #[instrument]
     pub async fn swap_exact_tokens_for_eth(
         &self,
         spend_amount: U256,
-        slippage: u8,
+        slippage_bps: u16,
         wbnb: Address,
         token: Address,
-        gas: U256,
-        gas_price: U256,
-    ) {
-        let max_out = self.get_amounts_out(spend_amount, token, wbnb).await;
-        let u256 = max_out
-            .checked_mul(U256::from(100 - slippage))
-            .expect("mul_error");
-        let min_amount = u256.checked_div(U256::from(100u8)).expect("div error");
+        tx_gas: TxGas,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        let max_out = self.get_amounts_out(spend_amount, token, wbnb).await?;
+        let min_amount = apply_slippage(max_out, slippage_bps);
 
         println!(
             "After swap we can get Max: {}, Min: {} Eth",
             ethers::utils::format_ether(max_out),
             ethers::utils::format_ether(min_amount)
         );
-        let encoded_data = self
-            .token_contract
-            .encode(
-                "swapExactTokensForETH",
-                (
-                    spend_amount,
-                    min_amount,
-                    vec![token, wbnb],
-                    self.signer.address(),
-                    U256::from(
-                        chrono::Utc::now()
-                            .add(Duration::seconds(10))
-                            .timestamp_millis(),
-                    ),
-                ),
-            )
-            .expect("encoding error");
-
-        let tx_req = TransactionRequest::new()
-            .from(self.signer.address())
-            .to(self.token_contract.address())
-            .value(0)
-            .data(encoded_data)
-            .gas(gas)
-            .gas_price(gas_price);
+        let tx_req = self.tokens_for_eth_tx(
+            "swapExactTokensForETH",
+            spend_amount,
+            min_amount,
+            wbnb,
+            token,
+        )?;
 
-        self.send_monitor_tx(tx_req).await;
+        self.send_monitor_tx(tx_gas.apply(tx_req), "swapExactTokensForETH", token)
+            .await
     }
 
+    /// sell variant which does not revert when the token takes a transfer tax
     #[instrument]
-    async fn send_monitor_tx(&self, tx_req: TransactionRequest) {
-        tracing::info!("submitting tx");
+    pub async fn swap_exact_tokens_for_eth_supporting_fee_on_transfer_tokens(
+        &self,
+        spend_amount: U256,
+        limits: OutputLimits,
+        wbnb: Address,
+        token: Address,
+        tx_gas: TxGas,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        let max_out = self.get_amounts_out(spend_amount, token, wbnb).await?;
+        let min_amount = limits.min_amount_out(max_out);
+
+        println!(
+            "After swap we can get Max: {}, Min: {} Eth",
+            ethers::utils::format_ether(max_out),
+            ethers::utils::format_ether(min_amount)
+        );
+        let tx_req = self.tokens_for_eth_tx(
+            SWAP_TOKENS_FOR_ETH_FEE_ON_TRANSFER,
+            spend_amount,
+            min_amount,
+            wbnb,
+            token,
+        )?;
 
-        let pending_tx = self
-            .signer
-            .send_transaction(tx_req, None)
+        self.send_monitor_tx(
+            tx_gas.apply(tx_req),
+            SWAP_TOKENS_FOR_ETH_FEE_ON_TRANSFER,
+            token,
+        )
+        .await
+    }
+
+    /// buy `trade.buy_amount` worth of token with the router method picked by trade settings
+    #[instrument]
+    pub async fn buy_tokens(
+        &self,
+        trade: &TradeSettings,
+        wbnb: Address,
+        token: Address,
+        tx_gas: TxGas,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        let swap = self.buy_swap(trade, wbnb, token).await?;
+        self.send_monitor_tx(tx_gas.apply(swap.request), swap.method, token)
             .await
-            .expect("problem while tx exec");
+    }
 
-        tracing::info!("Transaction submitted");
+    /** Dry run of `buy_tokens`
+     * same tx request is run through `eth_call` and `estimate_gas` against the pending block
+     * without signing or broadcasting it
+     **/
+    #[instrument]
+    pub async fn simulate_buy(
+        &self,
+        trade: &TradeSettings,
+        wbnb: Address,
+        token: Address,
+        tx_gas: TxGas,
+    ) -> Result<SimulationReport, BlockBotError> {
+        let swap = self.buy_swap(trade, wbnb, token).await?;
+        let tx = tx_gas.apply(swap.request);
+        let block = Some(BlockId::Number(BlockNumber::Pending));
+        let provider = self.signer.inner();
+
+        let (revert, expected_out) = match provider.call(&tx, block).await {
+            // fee-on-transfer variants have no return value to decode
+            Ok(output) => (
+                None,
+                self.token_contract
+                    .decode_output::<Vec<U256>, _>(swap.method, output)
+                    .ok()
+                    .and_then(|amounts| amounts.last().copied())
+                    .unwrap_or(swap.max_out),
+            ),
+            Err(err) => (Some(err.to_string()), swap.max_out),
+        };
 
-        let receipt_opt = pending_tx
-            .confirmations(1)
+        let gas_used = match provider.estimate_gas(&tx, block).await {
+            Ok(gas_used) => Some(gas_used),
+            Err(err) => {
+                tracing::warn!("gas estimation failed: {}", err);
+                None
+            }
+        };
+
+        let report = SimulationReport {
+            method: swap.method,
+            revert,
+            expected_out,
+            min_out: swap.min_out,
+            gas_used,
+        };
+        tracing::info!("simulated swap: {}", report);
+        Ok(report)
+    }
+
+    /// buy request with the router method picked by trade settings
+    async fn buy_swap(
+        &self,
+        trade: &TradeSettings,
+        wbnb: Address,
+        token: Address,
+    ) -> Result<SwapTx, BlockBotError> {
+        let (method, limits) = match trade.swap_variant() {
+            SwapVariant::Standard => (
+                SWAP_ETH_FOR_TOKENS,
+                OutputLimits {
+                    slippage_bps: trade.slippage_bps,
+                    token_tax_bps: 0,
+                },
+            ),
+            SwapVariant::Auto | SwapVariant::FeeOnTransfer => {
+                (SWAP_ETH_FOR_TOKENS_FEE_ON_TRANSFER, trade.output_limits())
+            }
+        };
+        self.eth_for_tokens_swap(method, trade.buy_amount, wbnb, token, limits)
             .await
-            .expect("pending tx exec error");
-
-        if let Some(receipt) = receipt_opt {
-            tracing::info!(
-                "tx: {:?} confirmed, execution successful?: {:?}",
-                receipt.transaction_hash,
-                receipt.status
-            );
+    }
+
+    /// quote the router and build native token -> token swap request without gas settings
+    async fn eth_for_tokens_swap(
+        &self,
+        method: &'static str,
+        spend_amount: U256,
+        wbnb: Address,
+        token: Address,
+        limits: OutputLimits,
+    ) -> Result<SwapTx, BlockBotError> {
+        let max_out = self.get_amounts_out(spend_amount, wbnb, token).await?;
+        let min_out = limits.min_amount_out(max_out);
+        let request = self.eth_for_tokens_tx(method, spend_amount, min_out, wbnb, token)?;
+        Ok(SwapTx {
+            method,
+            request,
+            max_out,
+            min_out,
+        })
+    }
+
+    /// sell `amount` of token with the router method picked by trade settings
+    #[instrument]
+    pub async fn sell_tokens(
+        &self,
+        trade: &TradeSettings,
+        amount: U256,
+        wbnb: Address,
+        token: Address,
+        tx_gas: TxGas,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        match trade.swap_variant() {
+            SwapVariant::Standard => {
+                self.swap_exact_tokens_for_eth(amount, trade.slippage_bps, wbnb, token, tx_gas)
+                    .await
+            }
+            SwapVariant::Auto | SwapVariant::FeeOnTransfer => {
+                self.swap_exact_tokens_for_eth_supporting_fee_on_transfer_tokens(
+                    amount,
+                    trade.output_limits(),
+                    wbnb,
+                    token,
+                    tx_gas,
+                )
+                .await
+            }
         }
     }
 
+    /// current network fees in the form of `tx_type`
+    pub async fn current_fees(&self, tx_type: TxType) -> Result<GasFees, BlockBotError> {
+        Ok(match tx_type {
+            TxType::Legacy => GasFees::Legacy {
+                gas_price: self.signer.get_gas_price().await?,
+            },
+            TxType::Eip1559 => {
+                let (max_fee_per_gas, max_priority_fee_per_gas) =
+                    self.signer.estimate_eip1559_fees(None).await?;
+                GasFees::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                }
+            }
+        })
+    }
+
+    /** Simulated fee-on-transfer buy against the pending block
+     * true when at least `min_out` tokens would reach the wallet,
+     * wallet native balance is overridden so only the token behaviour is checked
+     **/
+    pub async fn simulate_buy_min_out(
+        &self,
+        spend_amount: U256,
+        wbnb: Address,
+        token: Address,
+        min_out: U256,
+    ) -> bool {
+        let state = spoof::balance(self.signer.address(), spend_amount);
+        match self.eth_for_tokens_tx(
+            SWAP_ETH_FOR_TOKENS_FEE_ON_TRANSFER,
+            spend_amount,
+            min_out,
+            wbnb,
+            token,
+        ) {
+            Ok(tx_req) => self.simulate_call(tx_req, &state).await,
+            Err(err) => {
+                tracing::error!("unable to build buy simulation: {}", err);
+                false
+            }
+        }
+    }
+
+    /** Simulated fee-on-transfer sell against the pending block
+     * true when at least `min_out` native token would be received,
+     * `state` has to credit the wallet with the tokens and router allowance
+     **/
+    pub async fn simulate_sell_min_out(
+        &self,
+        amount: U256,
+        wbnb: Address,
+        token: Address,
+        min_out: U256,
+        state: &spoof::State,
+    ) -> bool {
+        match self.tokens_for_eth_tx(
+            SWAP_TOKENS_FOR_ETH_FEE_ON_TRANSFER,
+            amount,
+            min_out,
+            wbnb,
+            token,
+        ) {
+            Ok(tx_req) => self.simulate_call(tx_req, state).await,
+            Err(err) => {
+                tracing::error!("unable to build sell simulation: {}", err);
+                false
+            }
+        }
+    }
+
+    /// `eth_call` with state overrides, true when the call does not revert
+    async fn simulate_call(&self, tx_req: TransactionRequest, state: &spoof::State) -> bool {
+        let tx: TypedTransaction = tx_req.into();
+        self.signer
+            .inner()
+            .call_raw(&tx)
+            .block(BlockId::Number(BlockNumber::Pending))
+            .state(state)
+            .await
+            .is_ok()
+    }
+
+    pub fn address(&self) -> Address {
+        self.token_contract.address()
+    }
+
+    /// address of the wallet signing swaps
+    pub fn wallet_address(&self) -> Address {
+        self.signer.address()
+    }
+
+    /// native token -> token swap request without gas settings
+    fn eth_for_tokens_tx(
+        &self,
+        method: &str,
+        spend_amount: U256,
+        min_amount: U256,
+        wbnb: Address,
+        token: Address,
+    ) -> Result<TransactionRequest, BlockBotError> {
+        let encoded_data = self.token_contract.encode(
+            method,
+            (
+                min_amount,
+                vec![wbnb, token],
+                self.signer.address(),
+                Self::deadline(),
+            ),
+        )?;
+
+        Ok(TransactionRequest::new()
+            .from(self.signer.address())
+            .to(self.token_contract.address())
+            .value(spend_amount)
+            .data(encoded_data))
+    }
+
+    /// token -> native token swap request without gas settings
+    fn tokens_for_eth_tx(
+        &self,
+        method: &str,
+        spend_amount: U256,
+        min_amount: U256,
+        wbnb: Address,
+        token: Address,
+    ) -> Result<TransactionRequest, BlockBotError> {
+        let encoded_data = self.token_contract.encode(
+            method,
+            (
+                spend_amount,
+                min_amount,
+                vec![token, wbnb],
+                self.signer.address(),
+                Self::deadline(),
+            ),
+        )?;
+
+        Ok(TransactionRequest::new()
+            .from(self.signer.address())
+            .to(self.token_contract.address())
+            .value(0)
+            .data(encoded_data))
+    }
+
+    fn deadline() -> U256 {
+        U256::from(
+            chrono::Utc::now()
+                .add(Duration::seconds(10))
+                .timestamp_millis(),
+        )
+    }
+
+    /// submit swap `method` of `token`, stuck txs are replaced by the submitter
+    #[instrument]
+    async fn send_monitor_tx(
+        &self,
+        tx_req: TypedTransaction,
+        method: &str,
+        token: Address,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        tracing::info!("submitting tx");
+        let intent = format!("{} {:?}", method, token);
+        self.submitter.submit(&self.signer, tx_req, &intent).await
+    }
+
     #[instrument(skip(input))]
     pub fn decode_method_inputs<D: Detokenize, T: AsRef<[u8]>>(
         &self,
@@ -185,12 +543,10 @@ impl CakeRouter {
     }
 
     #[instrument]
-    pub fn get_method_name(&self, selector: Selector) -> String {
-        let (method_name, _) = self
-            .token_contract
+    pub fn get_method_name(&self, selector: Selector) -> Option<String> {
+        self.token_contract
             .methods
             .get(&selector)
-            .expect("method not found");
-        method_name.to_owned()
+            .map(|(method_name, _)| method_name.to_owned())
     }
 }
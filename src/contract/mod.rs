@@ -1,3 +1,3 @@
 pub mod bep20;
-mod cake_factory;
+pub mod cake_factory;
 pub mod cake_router;
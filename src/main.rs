@@ -1,16 +1,31 @@
 use std::sync::Arc;
 
 use block_bot::contract;
+use block_bot::contract::cake_factory::CakeFactory;
+use block_bot::contract::cake_router::CakeRouter;
 use block_bot::util;
+use block_bot::util::control;
+use block_bot::util::detect::{BlockWatcher, SnipeChannel};
 use block_bot::util::env_setup::Env;
-use block_bot::util::transaction::{check_tx, fetch_transaction};
-use block_bot::util::Util;
+use block_bot::util::feed::MempoolFeed;
+use block_bot::util::gas::{TxGas, TxType};
+use block_bot::util::mode::{BotMode, ModeSwitch};
+use block_bot::util::nonce::NonceManager;
+use block_bot::util::pending::{PendingTx, PendingTxMode};
+use block_bot::util::position::{Position, PositionManager};
+use block_bot::util::safety::{SafetyCheck, SafetyDecision};
+use block_bot::util::submit::{TxJournal, TxSubmitter};
+use block_bot::util::target::{Target, TargetBook};
+use block_bot::util::transaction::{check_tx, fetch_transaction, SnipeSignal};
+use block_bot::util::wallet::SplitPlan;
+use block_bot::util::watch::WatchRecorder;
 
-use ethers::prelude::{Middleware, StreamExt, U256};
-use ethers::types::H256;
-use ethers::utils::parse_units;
-use ethers::utils::Units;
+use ethers::prelude::{Address, Signer};
+use std::collections::HashMap;
 use std::error::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tracing::{Instrument, Level};
 
 #[tokio::main]
@@ -24,54 +39,187 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // env initialization
     let env = Env::new().await?;
 
-    let http_providers = &env.http_providers;
-
-    let cake_factory_contract = Arc::new(Util::get_contract(
-        &env.factory_contract,
-        "./abi/cake-factory.json",
-        Arc::clone(env.http_providers.get(0).unwrap()),
-    ));
-
-    let cake_router_contract = Arc::new(contract::cake_router::CakeRouter::new(
-        *Arc::clone(&env.router_contract),
-        "./abi/cake-router.json".to_owned(),
-        Arc::clone(env.http_providers.get(0).unwrap()),
-        env.local_wallet.clone(),
-    ));
-
-    // bep20 token prerequisites
-    let bep20token = contract::bep20::Bep20Token::new(
-        *Arc::clone(&env.desired_token),
-        "./abi/bep-20-token-abi.json".to_owned(),
-        Arc::clone(env.http_providers.get(0).unwrap()),
-        env.local_wallet.clone(),
+    // runtime mode switch, can be changed from the console while running
+    let mode_switch = ModeSwitch::new(env.mode);
+    control::spawn_stdin_listener(mode_switch.clone(), Arc::clone(&env.provider_pool));
+
+    // liquidity add recorder for watch mode
+    let watch_recorder = Arc::new(WatchRecorder::open(&env.watch_record)?);
+
+    // txs of every wallet are recorded to the same journal
+    let tx_journal = Arc::new(TxJournal::open(&env.tx_record)?);
+
+    // one router per wallet, each wallet tracks its own nonce
+    let mut submitters = Vec::new();
+    let mut cake_routers = Vec::new();
+    for wallet in env.wallets.wallets() {
+        let nonce_manager = Arc::new(NonceManager::new(
+            Arc::clone(env.http_providers.first().unwrap()),
+            wallet.address(),
+        ));
+        nonce_manager.sync().await?;
+
+        // sends our txs and replaces them when they get stuck
+        let submitter = Arc::new(TxSubmitter::new(
+            nonce_manager,
+            Arc::clone(&env.submission_backend),
+            env.replacement,
+            Some(Arc::clone(&tx_journal)),
+        ));
+
+        cake_routers.push(Arc::new(contract::cake_router::CakeRouter::new(
+            *Arc::clone(&env.router_contract),
+            "./abi/cake-router.json".to_owned(),
+            Arc::clone(env.http_providers.first().unwrap()),
+            wallet.clone(),
+            Arc::clone(&submitter),
+        )?));
+        submitters.push(submitter);
+    }
+    println!(
+        "Loaded {} wallets, sending txs with {} backend",
+        cake_routers.len(),
+        env.submission_backend.name()
     );
 
-    // do token spend approval and token info check
-    util::Util::do_prerequisites(
-        &bep20token,
-        env.local_wallet.clone(),
-        *Arc::clone(&env.factory_contract),
-    )
-    .await;
-
-    // subscribe to pending transactions
-    let mut stream = env
-        .wss_provider
-        .subscribe_pending_txs()
-        .await
-        .expect("Error while subscribing to pending transactions topic");
+    // router of the primary wallet, used for quotes and safety checks
+    let cake_router_contract = Arc::clone(&cake_routers[0]);
+    let cake_routers = Arc::new(cake_routers);
+
+    // targets along with their buy state
+    let targets = Arc::new(TargetBook::new(env.targets.clone()));
+
+    // honeypot checks of targets with safety limits
+    let mut safety_checks = HashMap::new();
+
+    for target in targets.targets() {
+        for (index, (wallet, submitter)) in env
+            .wallets
+            .wallets()
+            .iter()
+            .zip(submitters.iter())
+            .enumerate()
+        {
+            // bep20 token prerequisites
+            let bep20token = contract::bep20::Bep20Token::new(
+                target.token,
+                "./abi/bep-20-token-abi.json".to_owned(),
+                Arc::clone(env.http_providers.first().unwrap()),
+                wallet.clone(),
+                Arc::clone(submitter),
+            )?;
+
+            // storage slots do not depend on the wallet, the primary one is used
+            if index == 0 {
+                if let Some(limits) = target.safety {
+                    let safety_check = SafetyCheck::prepare(
+                        &bep20token,
+                        wallet.address(),
+                        *env.router_contract,
+                        limits,
+                    )
+                    .await;
+                    safety_checks.insert(target.token, safety_check);
+                }
+            }
+
+            // dry run sends nothing, token info check only
+            if env.dry_run {
+                util::Util::print_bep20_token_details(
+                    &bep20token,
+                    wallet.address(),
+                    *Arc::clone(&env.router_contract),
+                )
+                .await?;
+                continue;
+            }
+
+            // router spends the tokens when selling, approve it
+            util::Util::do_prerequisites(
+                &bep20token,
+                wallet.clone(),
+                *Arc::clone(&env.router_contract),
+                env.tx_type,
+            )
+            .await?;
+        }
+    }
+
+    // ws health on the console, the supervisors log it too
+    for ws in env.ws.iter() {
+        let mut ws_health = ws.health();
+        let url = ws.url().to_owned();
+        tokio::spawn(async move {
+            loop {
+                match ws_health.recv().await {
+                    Ok(event) => println!(
+                        "{} ws {} {}",
+                        chrono::Utc::now().format("%Y-%m-%dT%I:%M:%S%.6f %p %Z"),
+                        url,
+                        event
+                    ),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
 
-    // create mpsc channel
-    let (sender, mut receiver) = tokio::sync::mpsc::channel::<(H256, U256, U256)>(200);
-    let sender = Arc::new(sender);
+    // pending txs of every ws provider, each hash once. Resubscribed after reconnects
+    let (mempool_feed, mut pending_txs) =
+        MempoolFeed::start(&env.ws, env.pending_txs, env.seen_txs);
+
+    // create mpsc channel, mempool and block detections share it
+    let (sender, mut receiver) = tokio::sync::mpsc::channel::<SnipeSignal>(200);
+    let snipe_channel = Arc::new(SnipeChannel::new(sender));
+
+    // liquidity adds missed in the mempool are caught from block logs
+    let block_watcher = if env.block_detection {
+        let factory = CakeFactory::new(
+            *env.factory_contract,
+            "./abi/cake-factory.json".to_owned(),
+            Arc::clone(env.http_providers.first().unwrap()),
+        )?;
+        let block_watcher = BlockWatcher::new(
+            Arc::clone(&env.ws[0]),
+            Arc::clone(&env.provider_pool),
+            env.tx_fetch,
+            factory,
+            *env.bnb_address,
+            Arc::clone(&targets),
+            env.tx_type,
+        )
+        .await?;
+        let block_watcher_span = tracing::span!(Level::INFO, "block_watcher_task");
+        Some(tokio::spawn(
+            block_watcher
+                .run(
+                    Arc::clone(&snipe_channel),
+                    mode_switch.clone(),
+                    Arc::clone(&watch_recorder),
+                )
+                .instrument(block_watcher_span),
+        ))
+    } else {
+        None
+    };
 
     // clone movable inputs for receive thread
     let arc_cake = Arc::clone(&cake_router_contract);
+    let arc_routers = Arc::clone(&cake_routers);
+    let buy_split = Arc::new(env.buy_split.clone());
     let arc_bnb = Arc::clone(&env.bnb_address);
-    let arc_desired_token = Arc::clone(&env.desired_token);
-    let arc_wss_provider = Arc::clone(&env.wss_provider);
-    let subscription_id = stream.id;
+    let arc_targets = Arc::clone(&targets);
+    let arc_ws = env.ws.clone();
+    let receiver_mode = mode_switch.clone();
+    let receiver_channel = Arc::clone(&snipe_channel);
+    let dry_run = env.dry_run;
+    let tx_type = env.tx_type;
+    let safety_checks = Arc::new(safety_checks);
+
+    // open position tasks, awaited before exit so sells are not cut off
+    let position_tasks = Arc::new(Mutex::new(Vec::new()));
+    let receiver_position_tasks = Arc::clone(&position_tasks);
 
     // tracing span
     let tx_receiver_span = tracing::span!(Level::INFO, "tx_reciever_task");
@@ -80,63 +228,153 @@ async fn main() -> Result<(), Box<dyn Error>> {
     tokio::spawn(
         async move {
             // receive message sent by transmitter
-            while let Some((tx, gas, gas_price)) = receiver.recv().await {
-                // let the transaction spend 4 time gas of source transaction
-                let gas = gas.checked_mul(U256::from(2)).expect("multi_except");
-
-                tracing::info!("got liquidity add tx {:?}, going for swap", tx);
-
-                let amt = parse_units(U256::from(10000u32), &Units::Gwei.to_string())
-                    .expect("issue parsing units");
-                // execute transaction
-                arc_cake
-                    .swap_exact_eth_for_tokens(
-                        U256::from(amt),
-                        *arc_bnb,
-                        *arc_desired_token,
-                        50u8,
-                        gas,
-                        gas_price,
-                    )
-                    .await;
+            while let Some(signal) = receiver.recv().await {
+                let (token, tx) = (signal.token, signal.tx_hash);
 
-                // Close receiver as transaction is successful
-                tracing::info!("Closing receiver as tx successful");
-                receiver.close();
+                // mode could have been switched since the tx was matched
+                let mode = receiver_mode.get();
+                if mode != BotMode::Snipe {
+                    tracing::info!("bot is in {} mode, skipping swap for tx {:?}", mode, tx);
+                    receiver_channel.release(&token);
+                    continue;
+                }
 
-                // unsubscribe from the pending tx subscription
-                let is_operation_successful = arc_wss_provider
-                    .unsubscribe(subscription_id)
-                    .await
-                    .unwrap_or_else(|_| {
-                        eprintln!("Unsubscribing failed");
-                        false
-                    });
-
-                if is_operation_successful {
-                    println!(
-                        "Successfully unsubscribed from subscription: #{}",
-                        subscription_id
+                let target = match arc_targets.get(&token) {
+                    Some(target) => target.clone(),
+                    None => continue,
+                };
+
+                // every target is bought only once
+                if !arc_targets.start_buy(&token) {
+                    tracing::info!(
+                        "target {:?} is already {:?}, skipping tx {:?}",
+                        token,
+                        arc_targets.state(&token),
+                        tx
                     );
+                    continue;
                 }
+
+                // gas of our tx picked by the gas strategy of target
+                let tx_gas = target.trade.tx_gas(signal.source_gas);
+                tracing::info!(
+                    "{} gas strategy, liquidity add {}, ours {}",
+                    target.trade.gas_strategy,
+                    signal.source_gas,
+                    tx_gas
+                );
+
+                tracing::info!(
+                    "got liquidity add tx {:?} from {}, going for swap",
+                    tx,
+                    signal.source
+                );
+
+                let arc_cake = Arc::clone(&arc_cake);
+                let arc_routers = Arc::clone(&arc_routers);
+                let buy_split = Arc::clone(&buy_split);
+                let arc_bnb = Arc::clone(&arc_bnb);
+                let arc_targets = Arc::clone(&arc_targets);
+                let arc_ws = arc_ws.clone();
+                let position_tasks = Arc::clone(&receiver_position_tasks);
+                let safety_checks = Arc::clone(&safety_checks);
+                let snipe_channel = Arc::clone(&receiver_channel);
+                let swap_span = tracing::span!(Level::INFO, "swap_task");
+
+                // swap in its own task so other targets are not held up
+                tokio::spawn(
+                    async move {
+                        let safety_check = safety_checks.get(&token);
+                        let bought = match check_safety(&arc_cake, safety_check, &target, *arc_bnb)
+                            .await
+                        {
+                            SafetyDecision::Buy if dry_run => {
+                                simulate_buys(&arc_routers, &buy_split, &target, *arc_bnb, tx_gas)
+                                    .await
+                            }
+                            SafetyDecision::Buy => {
+                                buy_with_wallets(
+                                    &arc_routers,
+                                    &buy_split,
+                                    &target,
+                                    *arc_bnb,
+                                    tx_gas,
+                                    tx_type,
+                                    &position_tasks,
+                                )
+                                .await
+                            }
+                            SafetyDecision::Refuse => false,
+                            SafetyDecision::Recheck => {
+                                arc_targets.abort_buy(&token);
+                                snipe_channel.release(&token);
+                                return;
+                            }
+                        };
+                        arc_targets.finish_buy(&token, bought);
+
+                        if !arc_targets.all_handled() {
+                            return;
+                        }
+
+                        // end the pending tx and block subscriptions
+                        tracing::info!("all targets handled, unsubscribing");
+                        for ws in arc_ws.iter() {
+                            ws.shutdown();
+                        }
+                        println!("Successfully unsubscribed from ws subscriptions");
+                    }
+                    .instrument(swap_span),
+                );
             }
         }
         .instrument(tx_receiver_span),
     );
 
     println!(
-        "{} Started monitoring transactions\n",
-        chrono::Utc::now().format("%Y-%m-%dT%I:%M:%S%.6f %p %Z")
+        "{} Started monitoring transactions in {} mode{}\n",
+        chrono::Utc::now().format("%Y-%m-%dT%I:%M:%S%.6f %p %Z"),
+        mode_switch.get(),
+        if env.dry_run { " (dry run)" } else { "" }
     );
 
+    // node may send hashes even when asked for full txs
+    let mut fetching_hashes = env.pending_txs == PendingTxMode::Hashes;
+
     // process stream of processing pending tx
-    while let Some(tx_hash) = stream.next().await {
+    while let Some(pending_tx) = pending_txs.recv().await {
+        // in paused mode liquidity add events are not watched
+        if mode_switch.get() == BotMode::Paused {
+            continue;
+        }
+
+        let tx_hash = pending_tx.hash();
+        match &pending_tx {
+            // full txs are filtered before any task is spawned
+            PendingTx::Full(transaction) => {
+                if !env.tx_filter.accepts(transaction) {
+                    continue;
+                }
+            }
+            PendingTx::Hash(_) => {
+                if !fetching_hashes {
+                    tracing::warn!("node sends pending tx hashes only, fetching txs over http");
+                    fetching_hashes = true;
+                }
+            }
+        }
+
         // clone required arc instances to pass to tokio thread
-        let arc_contract_to_watch = Arc::clone(&env.factory_contract);
-        let arc_desired_token = Arc::clone(&env.desired_token);
-        let sender = Arc::clone(&sender);
-        let cake_factory_contract = Arc::clone(&cake_factory_contract);
-        let http_providers = http_providers.clone();
+        let watched_routers = Arc::clone(&env.watched_routers);
+        let extra_selectors = Arc::clone(&env.extra_selectors);
+        let arc_targets = Arc::clone(&targets);
+        let snipe_channel = Arc::clone(&snipe_channel);
+        let cake_router_contract = Arc::clone(&cake_router_contract);
+        let provider_pool = Arc::clone(&env.provider_pool);
+        let tx_fetch = env.tx_fetch;
+        let tx_filter = Arc::clone(&env.tx_filter);
+        let mode_switch = mode_switch.clone();
+        let watch_recorder = Arc::clone(&watch_recorder);
 
         // tracing span
         let tx_fetch_tx_span = tracing::span!(Level::INFO, "fetch_tx_1");
@@ -146,31 +384,286 @@ async fn main() -> Result<(), Box<dyn Error>> {
         // spawn a new tokio thread for fetching the details of received pending tx hash
         tokio::spawn(
             async move {
-                if let Some(transaction) = fetch_transaction(http_providers, tx_hash).await {
-                    if check_tx(
-                        &transaction,
-                        &*arc_contract_to_watch,
-                        &cake_factory_contract,
-                        arc_desired_token,
-                    )
-                    .await
-                    {
-                        sender
-                            .send((
-                                transaction.hash,
-                                transaction.gas,
-                                transaction.gas_price.unwrap_or_default(),
-                            ))
-                            .await
-                            .unwrap_or_else(|_| eprintln!("receiver is already closed"));
+                let transaction = match pending_tx {
+                    PendingTx::Full(transaction) => *transaction,
+                    PendingTx::Hash(tx_hash) => {
+                        match fetch_transaction(provider_pool, tx_fetch, tx_hash).await {
+                            // a hash tells nothing, fetched txs are filtered once known
+                            Some(transaction) if tx_filter.accepts(&transaction) => transaction,
+                            Some(_) => return,
+                            None => {
+                                tracing::error!(
+                                    "Alas! Eventually Unable to fetch tx {:?}",
+                                    tx_hash
+                                );
+                                return;
+                            }
+                        }
                     }
-                } else {
-                    tracing::error!("Alas! Eventually Unable to fetch tx {:?}", tx_hash);
+                };
+
+                let liquidity_match = check_tx(
+                    &transaction,
+                    &watched_routers,
+                    &extra_selectors,
+                    &cake_router_contract,
+                    &arc_targets,
+                )
+                .await;
+
+                if let Some(liquidity_match) = liquidity_match {
+                    match mode_switch.get() {
+                        BotMode::Paused => return,
+                        BotMode::Watch => {
+                            watch_recorder.record(&transaction, &liquidity_match);
+                            return;
+                        }
+                        BotMode::Snipe => {}
+                    }
+
+                    snipe_channel
+                        .send(SnipeSignal::new(&liquidity_match, &transaction, tx_type))
+                        .await;
                 }
             }
             .instrument(tx_fetch_tx_span),
         );
     }
-    println!("While let broken successfully!");
+    tracing::info!("pending tx feed closed, shutting down");
+    if env.ws.len() > 1 {
+        mempool_feed.log_stats();
+    }
+    env.provider_pool.log_status();
+    env.tx_filter.log_stats();
+
+    // no target is left for the block watcher either
+    if let Some(block_watcher) = block_watcher {
+        block_watcher.abort();
+    }
+
+    // wait for open positions to be sold
+    let position_tasks = std::mem::take(&mut *position_tasks.lock().await);
+    if !position_tasks.is_empty() {
+        println!("Waiting for {} open positions", position_tasks.len());
+    }
+    for task in position_tasks {
+        task.await
+            .unwrap_or_else(|e| tracing::error!("position task failed: {}", e));
+    }
     Ok(())
 }
+
+/// target with the buy amount share of every wallet, wallets without a share are left out
+fn wallet_shares(
+    cake_routers: &[Arc<CakeRouter>],
+    buy_split: &SplitPlan,
+    target: &Target,
+) -> Vec<(Arc<CakeRouter>, Target)> {
+    cake_routers
+        .iter()
+        .zip(buy_split.amounts(target.trade.buy_amount, cake_routers.len()))
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(cake_router, amount)| {
+            let mut share = target.clone();
+            share.trade.buy_amount = amount;
+            (Arc::clone(cake_router), share)
+        })
+        .collect()
+}
+
+/// buy the target with every wallet in parallel, each spending its share of the buy amount
+/// true when at least one wallet bought
+async fn buy_with_wallets(
+    cake_routers: &[Arc<CakeRouter>],
+    buy_split: &SplitPlan,
+    target: &Target,
+    native: Address,
+    tx_gas: TxGas,
+    tx_type: TxType,
+    position_tasks: &Arc<Mutex<Vec<JoinHandle<()>>>>,
+) -> bool {
+    let buys: Vec<JoinHandle<bool>> = wallet_shares(cake_routers, buy_split, target)
+        .into_iter()
+        .map(|(cake_router, share)| {
+            let position_tasks = Arc::clone(position_tasks);
+            let wallet_span =
+                tracing::span!(Level::INFO, "wallet_buy", wallet = ?cake_router.wallet_address());
+            tokio::spawn(
+                async move {
+                    buy(
+                        &cake_router,
+                        &share,
+                        native,
+                        tx_gas,
+                        tx_type,
+                        &position_tasks,
+                    )
+                    .await
+                }
+                .instrument(wallet_span),
+            )
+        })
+        .collect();
+
+    let wallets = buys.len();
+    let mut bought = 0;
+    for handle in buys {
+        match handle.await {
+            Ok(true) => bought += 1,
+            Ok(false) => {}
+            Err(err) => tracing::error!("wallet buy task failed: {}", err),
+        }
+    }
+    tracing::info!(
+        "{} of {} wallets bought {:?}",
+        bought,
+        wallets,
+        target.token
+    );
+    bought > 0
+}
+
+/// dry run of `buy_with_wallets`, true when the swap of every wallet would succeed
+async fn simulate_buys(
+    cake_routers: &[Arc<CakeRouter>],
+    buy_split: &SplitPlan,
+    target: &Target,
+    native: Address,
+    tx_gas: TxGas,
+) -> bool {
+    let mut would_succeed = true;
+    for (cake_router, share) in wallet_shares(cake_routers, buy_split, target) {
+        would_succeed &= simulate_buy(&cake_router, &share, native, tx_gas).await;
+    }
+    would_succeed
+}
+
+/// buy the target, bought tokens are handed over to the position manager when exit rules are set
+/// true when the buy is confirmed successfully
+async fn buy(
+    cake_router: &Arc<CakeRouter>,
+    target: &Target,
+    native: Address,
+    tx_gas: TxGas,
+    tx_type: TxType,
+    position_tasks: &Mutex<Vec<JoinHandle<()>>>,
+) -> bool {
+    let token = target.token;
+
+    // execute transaction
+    let receipt = match cake_router
+        .buy_tokens(&target.trade, native, token, tx_gas)
+        .await
+    {
+        Ok(Some(receipt)) if receipt.status == Some(1u64.into()) => receipt,
+        Ok(Some(receipt)) => {
+            tracing::error!(
+                "swap tx {:?} for {:?} reverted",
+                receipt.transaction_hash,
+                token
+            );
+            return false;
+        }
+        Ok(None) => {
+            tracing::error!("no receipt for swap of {:?}", token);
+            return false;
+        }
+        Err(err) => {
+            tracing::error!("swap for {:?} failed: {}", token, err);
+            return false;
+        }
+    };
+
+    let exit = match &target.exit {
+        Some(exit) => exit.clone(),
+        None => return true,
+    };
+
+    // hand the bought tokens over to the position manager
+    match Position::from_receipt(
+        token,
+        cake_router.wallet_address(),
+        target.trade.buy_amount,
+        &receipt,
+    ) {
+        Some(position) => {
+            let manager = PositionManager::new(
+                Arc::clone(cake_router),
+                native,
+                target.trade,
+                exit,
+                receipt.gas_used,
+                tx_type,
+            );
+            position_tasks
+                .lock()
+                .await
+                .push(tokio::spawn(manager.run(position)));
+        }
+        None => tracing::error!(
+            "no token transfer found in buy receipt of {:?}, position not managed",
+            token
+        ),
+    }
+    true
+}
+
+/// dry run of `buy`, true when the swap would succeed
+async fn simulate_buy(
+    cake_router: &CakeRouter,
+    target: &Target,
+    native: Address,
+    tx_gas: TxGas,
+) -> bool {
+    match cake_router
+        .simulate_buy(&target.trade, native, target.token, tx_gas)
+        .await
+    {
+        Ok(report) => {
+            println!(
+                "[dry run] {:?} by {:?}: {}",
+                target.token,
+                cake_router.wallet_address(),
+                report
+            );
+            !report.would_revert()
+        }
+        Err(err) => {
+            tracing::error!("swap simulation for {:?} failed: {}", target.token, err);
+            false
+        }
+    }
+}
+
+/// run the honeypot check of target, targets without one are bought
+async fn check_safety(
+    cake_router: &CakeRouter,
+    safety_check: Option<&SafetyCheck>,
+    target: &Target,
+    native: Address,
+) -> SafetyDecision {
+    let safety_check = match safety_check {
+        Some(safety_check) => safety_check,
+        None => return SafetyDecision::Buy,
+    };
+
+    let verdict = safety_check
+        .run(cake_router, &target.trade, native, target.token)
+        .await;
+    let decision = safety_check.decide(&verdict);
+    match decision {
+        SafetyDecision::Buy => {
+            tracing::info!("safety check of {:?}: {}", target.token, verdict)
+        }
+        SafetyDecision::Refuse => {
+            tracing::warn!("refusing to buy {:?}: {}", target.token, verdict);
+            println!("Refusing to buy {:?}: {}", target.token, verdict);
+        }
+        SafetyDecision::Recheck => tracing::info!(
+            "putting off buy of {:?} until next detection: {}",
+            target.token,
+            verdict
+        ),
+    }
+    decision
+}
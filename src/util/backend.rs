@@ -0,0 +1,446 @@
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use clap::ValueEnum;
+use ethers::prelude::{Bytes, Http, Middleware, Provider, ProviderError, H256, U64};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use super::error::BlockBotError;
+use super::supervisor::WsSupervisor;
+
+/// blocks a bundle is submitted for when none is configured
+pub const DEFAULT_TARGET_BLOCKS: u64 = 2;
+
+/// Submission backend names used in config and cli
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// every public rpc, tx goes through the public mempool
+    #[default]
+    Public,
+    /// private relay accepting `eth_sendBundle`
+    Bundle,
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::Public => f.write_str("public"),
+            BackendKind::Bundle => f.write_str("bundle"),
+        }
+    }
+}
+
+/** Where signed txs are sent to
+ * txs are signed by the submitter, backends only deliver the raw bytes
+**/
+#[async_trait]
+pub trait SubmissionBackend: fmt::Debug + Send + Sync {
+    /// name used in logs and the tx journal
+    fn name(&self) -> &'static str;
+
+    /// deliver signed `raw_tx` whose hash is `tx_hash`
+    async fn send_raw_transaction(&self, raw_tx: Bytes, tx_hash: H256)
+        -> Result<(), BlockBotError>;
+
+    /// whether txs not mined in time are dropped by the backend,
+    /// they need no cancel tx then
+    fn drops_stuck_txs(&self) -> bool {
+        false
+    }
+}
+
+/// Rpc endpoint a raw tx is broadcast to
+#[derive(Debug, Clone)]
+pub enum RpcEndpoint {
+    Http(Arc<Provider<Http>>),
+    /// provider of the current ws connection
+    Ws(Arc<WsSupervisor>),
+}
+
+impl RpcEndpoint {
+    async fn send_raw_transaction(&self, raw_tx: Bytes) -> Result<(), ProviderError> {
+        match self {
+            RpcEndpoint::Http(provider) => provider.send_raw_transaction(raw_tx).await.map(drop),
+            RpcEndpoint::Ws(ws) => ws.provider().send_raw_transaction(raw_tx).await.map(drop),
+        }
+    }
+}
+
+impl fmt::Display for RpcEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcEndpoint::Http(provider) => write!(f, "{}", provider.url()),
+            RpcEndpoint::Ws(ws) => f.write_str(ws.url()),
+        }
+    }
+}
+
+/** Broadcasts txs with `eth_sendRawTransaction` to every public rpc at once
+ * the first endpoint accepting the tx wins, slower endpoints keep sending in the
+ * background. "already known" counts as accepted and "nonce too low" from a slower
+ * node is ignored once the tx is accepted
+**/
+#[derive(Debug)]
+pub struct PublicRpc {
+    endpoints: Vec<RpcEndpoint>,
+}
+
+impl PublicRpc {
+    pub fn new(endpoints: Vec<RpcEndpoint>) -> PublicRpc {
+        PublicRpc { endpoints }
+    }
+}
+
+#[async_trait]
+impl SubmissionBackend for PublicRpc {
+    fn name(&self) -> &'static str {
+        "public"
+    }
+
+    async fn send_raw_transaction(
+        &self,
+        raw_tx: Bytes,
+        tx_hash: H256,
+    ) -> Result<(), BlockBotError> {
+        let (sender, mut receiver) = mpsc::channel(self.endpoints.len().max(1));
+        for endpoint in self.endpoints.iter() {
+            let (endpoint, raw_tx, sender) = (endpoint.clone(), raw_tx.clone(), sender.clone());
+            tokio::spawn(async move {
+                let result = endpoint.send_raw_transaction(raw_tx).await;
+                if sender.send((endpoint.to_string(), result)).await.is_err() {
+                    tracing::debug!(
+                        "tx {:?} already accepted, {} answered late",
+                        tx_hash,
+                        endpoint
+                    );
+                }
+            });
+        }
+        drop(sender);
+
+        let mut first_err = None;
+        while let Some((endpoint, result)) = receiver.recv().await {
+            match result {
+                Ok(()) => {
+                    tracing::info!("tx {:?} accepted by {}", tx_hash, endpoint);
+                    return Ok(());
+                }
+                Err(err) if is_already_known(&err) => {
+                    tracing::info!("tx {:?} already known to {}", tx_hash, endpoint);
+                    return Ok(());
+                }
+                Err(err) => {
+                    if is_nonce_too_low(&err) {
+                        tracing::debug!("{} rejected tx {:?}: {}", endpoint, tx_hash, err);
+                    } else {
+                        tracing::warn!("{} rejected tx {:?}: {}", endpoint, tx_hash, err);
+                    }
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        // nonce too low is only ignored when another endpoint accepted the tx
+        Err(first_err
+            .map(BlockBotError::Rpc)
+            .unwrap_or(BlockBotError::UnexpectedResponse("eth_sendRawTransaction")))
+    }
+}
+
+/// node already has the tx in its mempool
+fn is_already_known(err: &ProviderError) -> bool {
+    let message = err.to_string().to_lowercase();
+    message.contains("already known") || message.contains("known transaction")
+}
+
+/// nonce of the tx is already mined, e.g. the tx reached a faster node first
+fn is_nonce_too_low(err: &ProviderError) -> bool {
+    err.to_string().to_lowercase().contains("nonce too low")
+}
+
+/** Sends txs as single tx bundles to a private relay
+ * each tx is bundled for every one of the next `target_blocks` blocks with
+ * `eth_sendBundle`, bundles not included by then are dropped by the relay
+**/
+#[derive(Debug)]
+pub struct BundleRelay {
+    relay: Provider<Http>,
+    relay_url: String,
+    /// chain rpc the target block is picked with
+    chain: Arc<Provider<Http>>,
+    target_blocks: u64,
+}
+
+impl BundleRelay {
+    pub fn new(
+        relay: Provider<Http>,
+        relay_url: String,
+        chain: Arc<Provider<Http>>,
+        target_blocks: u64,
+    ) -> BundleRelay {
+        BundleRelay {
+            relay,
+            relay_url,
+            chain,
+            target_blocks,
+        }
+    }
+}
+
+#[async_trait]
+impl SubmissionBackend for BundleRelay {
+    fn name(&self) -> &'static str {
+        "bundle"
+    }
+
+    async fn send_raw_transaction(
+        &self,
+        raw_tx: Bytes,
+        tx_hash: H256,
+    ) -> Result<(), BlockBotError> {
+        let block = self.chain.get_block_number().await?;
+        for offset in 1..=self.target_blocks {
+            let target_block = block + U64::from(offset);
+            let bundle = json!({
+                "txs": [raw_tx],
+                "blockNumber": target_block,
+            });
+            let response: serde_json::Value = self
+                .relay
+                .request("eth_sendBundle", [bundle])
+                .await
+                .map_err(|e| BlockBotError::BundleRejected(self.relay_url.clone(), e))?;
+            tracing::info!(
+                "bundle of {:?} for block {} sent to {}: {}",
+                tx_hash,
+                target_block,
+                self.relay_url,
+                response
+            );
+        }
+        Ok(())
+    }
+
+    fn drops_stuck_txs(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::mock_rpc::{MockRpc, Reply};
+    use crate::util::nonce::NonceManager;
+    use crate::util::submit::{ReplacementPolicy, TxSubmitter};
+    use ethers::prelude::{
+        Address, LocalWallet, Signer, SignerMiddleware, TransactionRequest, U256,
+    };
+    use ethers::types::transaction::eip2718::TypedTransaction;
+    use ethers::utils::rlp::Rlp;
+    use serde_json::Value;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    /// node answering `eth_sendRawTransaction` with `reply`
+    async fn node(reply: Reply) -> MockRpc {
+        MockRpc::start(move |method, _| match method {
+            "eth_sendRawTransaction" => reply.clone(),
+            _ => Reply::Error(-32601, "method not found".to_owned()),
+        })
+        .await
+    }
+
+    fn accepted() -> Reply {
+        Reply::Result(json!(format!("{:?}", H256::repeat_byte(1))))
+    }
+
+    fn rejected(message: &str) -> Reply {
+        Reply::Error(-32000, message.to_owned())
+    }
+
+    fn public_rpc(nodes: &[&MockRpc]) -> PublicRpc {
+        PublicRpc::new(
+            nodes
+                .iter()
+                .map(|node| RpcEndpoint::Http(Arc::new(node.provider())))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn bundle_is_sent_for_each_target_block() {
+        let chain = MockRpc::start(|method, _| match method {
+            "eth_blockNumber" => Reply::Result(json!("0x64")),
+            _ => Reply::Error(-32601, "method not found".to_owned()),
+        })
+        .await;
+        let relay = MockRpc::start(|method, _| match method {
+            "eth_sendBundle" => Reply::Result(json!({"bundleHash": "0x01"})),
+            _ => Reply::Error(-32601, "method not found".to_owned()),
+        })
+        .await;
+        let backend = BundleRelay::new(
+            relay.provider(),
+            relay.url().to_owned(),
+            Arc::new(chain.provider()),
+            3,
+        );
+
+        let raw_tx = Bytes::from(vec![0xde, 0xad]);
+        backend
+            .send_raw_transaction(raw_tx, H256::zero())
+            .await
+            .unwrap();
+
+        let bundles = relay.requests("eth_sendBundle");
+        let blocks = ["0x65", "0x66", "0x67"];
+        assert_eq!(bundles.len(), blocks.len());
+        for (bundle, block) in bundles.iter().zip(blocks.iter()) {
+            assert_eq!(bundle, &json!([{"txs": ["0xdead"], "blockNumber": block}]));
+        }
+        assert!(backend.drops_stuck_txs());
+    }
+
+    #[tokio::test]
+    async fn nonce_of_a_missed_bundle_is_used_again() {
+        // the bundle never lands, the node keeps counting 5 txs of ours
+        let chain = MockRpc::start(|method, _| match method {
+            "eth_getTransactionCount" => Reply::Result(json!("0x5")),
+            "eth_getTransactionReceipt" => Reply::Result(Value::Null),
+            "eth_blockNumber" => Reply::Result(json!("0x64")),
+            _ => Reply::Error(-32601, "method not found".to_owned()),
+        })
+        .await;
+        let relay = MockRpc::start(|_, _| Reply::Result(json!({"bundleHash": "0x01"}))).await;
+        let provider = Arc::new(chain.provider());
+        let wallet = LocalWallet::new(&mut rand::thread_rng()).with_chain_id(56u64);
+        let backend = BundleRelay::new(
+            relay.provider(),
+            relay.url().to_owned(),
+            Arc::clone(&provider),
+            1,
+        );
+        let submitter = TxSubmitter::new(
+            Arc::new(NonceManager::new(Arc::clone(&provider), wallet.address())),
+            Arc::new(backend),
+            ReplacementPolicy {
+                stuck_after_blocks: None,
+                stuck_after: Some(Duration::ZERO),
+                max_replacements: 0,
+                ..Default::default()
+            },
+            None,
+        );
+        let signer = SignerMiddleware::new(provider, wallet);
+
+        for _ in 0..2 {
+            let tx: TypedTransaction = TransactionRequest::new()
+                .to(Address::repeat_byte(1))
+                .gas(21_000)
+                .gas_price(100)
+                .into();
+            assert!(submitter
+                .submit(&signer, tx, "buy")
+                .await
+                .unwrap()
+                .is_none());
+        }
+
+        let nonces: Vec<U256> = relay
+            .requests("eth_sendBundle")
+            .iter()
+            .map(|params| {
+                let raw_tx: Bytes = serde_json::from_value(params[0]["txs"][0].clone()).unwrap();
+                let (tx, _) = TypedTransaction::decode_signed(&Rlp::new(&raw_tx)).unwrap();
+                *tx.nonce().unwrap()
+            })
+            .collect();
+        // abandoned without a cancel, the next tx takes the nonce over
+        assert_eq!(nonces, vec![U256::from(5); 2]);
+    }
+
+    #[tokio::test]
+    async fn rejected_bundle_is_reported() {
+        let chain = MockRpc::start(|_, _| Reply::Result(json!("0x64"))).await;
+        let relay = MockRpc::start(|_, _| rejected("bundle too late")).await;
+        let backend = BundleRelay::new(
+            relay.provider(),
+            relay.url().to_owned(),
+            Arc::new(chain.provider()),
+            DEFAULT_TARGET_BLOCKS,
+        );
+
+        let result = backend
+            .send_raw_transaction(Bytes::from(vec![1]), H256::zero())
+            .await;
+        assert!(matches!(result, Err(BlockBotError::BundleRejected(_, _))));
+        // later blocks are not tried once the relay rejects a bundle
+        assert_eq!(relay.requests("eth_sendBundle").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn already_known_tx_is_accepted() {
+        let known = node(rejected("already known")).await;
+        let backend = public_rpc(&[&known]);
+        backend
+            .send_raw_transaction(Bytes::from(vec![1, 2]), H256::zero())
+            .await
+            .unwrap();
+        assert_eq!(
+            known.requests("eth_sendRawTransaction"),
+            vec![json!(["0x0102"])]
+        );
+    }
+
+    #[tokio::test]
+    async fn nonce_too_low_is_ignored_only_when_another_node_accepts() {
+        let stale = node(rejected("nonce too low")).await;
+        let healthy = node(accepted()).await;
+        let unreachable = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+
+        let mut backend = public_rpc(&[&stale, &healthy]);
+        backend
+            .endpoints
+            .push(RpcEndpoint::Http(Arc::new(unreachable)));
+        assert!(backend
+            .send_raw_transaction(Bytes::from(vec![1]), H256::zero())
+            .await
+            .is_ok());
+
+        let backend = public_rpc(&[&stale]);
+        let result = backend
+            .send_raw_transaction(Bytes::from(vec![1]), H256::zero())
+            .await;
+        assert!(matches!(result, Err(BlockBotError::Rpc(err)) if is_nonce_too_low(&err)));
+    }
+
+    #[tokio::test]
+    async fn tx_is_broadcast_to_every_endpoint() {
+        let nodes = [
+            node(accepted()).await,
+            node(rejected("already known")).await,
+            node(rejected("insufficient funds")).await,
+        ];
+        let backend = public_rpc(&nodes.iter().collect::<Vec<_>>());
+        backend
+            .send_raw_transaction(Bytes::from(vec![7]), H256::zero())
+            .await
+            .unwrap();
+
+        // slower nodes keep sending after the first accept
+        for node in nodes.iter() {
+            let mut sent = node.requests("eth_sendRawTransaction");
+            for _ in 0..100 {
+                if !sent.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                sent = node.requests("eth_sendRawTransaction");
+            }
+            assert_eq!(sent, vec![json!(["0x07"])]);
+        }
+    }
+}
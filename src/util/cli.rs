@@ -1,23 +1,211 @@
 use clap::{ArgAction, Parser};
 
+use super::backend::BackendKind;
+use super::gas::{GasStrategyKind, TxType};
+use super::mode::BotMode;
+use super::pending::PendingTxMode;
+use super::submit::GiveUpAction;
+use super::trade::SwapVariant;
+use super::wallet::BuySplit;
+
+/// cli flags, every flag overrides the matching config file value
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub(super) struct Args {
+    #[arg(short, long, help = "config file (.toml, .yaml or .yml)")]
+    pub config: Option<String>,
+
+    #[arg(short, long, help = "network name from config file")]
+    pub network: Option<String>,
+
     // pub local_wallet: LocalWallet,
-    #[arg(short, long, help = "wss provider url")]
-    pub wss: String,
-    #[arg(short, long, action=ArgAction::Append, help = "http provider url")]
+    #[arg(short, long, action=ArgAction::Append, help = "wss provider url, pass several times to merge their mempools")]
+    pub wss: Vec<String>,
+    #[arg(short = 'H', long, action=ArgAction::Append, help = "http provider url")]
     pub http: Vec<String>,
 
     #[arg(short, long, help = "factory contract where liquidity add happens")]
-    pub factory: String,
+    pub factory: Option<String>,
 
     #[arg(short, long, help = "router contract from where we buy token")]
-    pub router: String,
+    pub router: Option<String>,
 
     #[arg(long, help = "native token address. It'll be spent for buying")]
-    pub native: String,
+    pub native: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "transaction type of our transactions [default: legacy]"
+    )]
+    pub tx_type: Option<TxType>,
+
+    #[arg(long, action=ArgAction::Append, help = "token address. This token will be bought")]
+    pub token: Vec<String>,
+
+    #[arg(
+        long,
+        help = "native token amount spent per buy e.g. 0.05 [default: 0.00001]"
+    )]
+    pub buy_amount: Option<String>,
+
+    #[arg(
+        long,
+        help = "accepted slippage in basis points, 100 = 1% [default: 5000]"
+    )]
+    pub slippage_bps: Option<u16>,
+
+    #[arg(
+        long,
+        help = "multiplier applied to liquidity add tx gas limit [default: 2]"
+    )]
+    pub gas_limit_multiplier: Option<f64>,
+
+    #[arg(
+        long,
+        help = "multiplier applied to liquidity add tx gas price [default: 1]"
+    )]
+    pub gas_price_multiplier: Option<f64>,
+
+    #[arg(long, help = "gas price cap in gwei")]
+    pub max_gas_price: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "how our gas price relates to the liquidity add tx [default: backrun]"
+    )]
+    pub gas_strategy: Option<GasStrategyKind>,
+
+    #[arg(
+        long,
+        help = "gwei added to liquidity add gas price by priority strategy"
+    )]
+    pub priority_premium: Option<String>,
+
+    #[arg(long, help = "gas price in gwei used by fixed strategy")]
+    pub fixed_gas_price: Option<String>,
+
+    #[arg(long, value_enum, help = "router swap method family [default: auto]")]
+    pub swap_variant: Option<SwapVariant>,
+
+    #[arg(long, help = "known token transfer tax in basis points")]
+    pub token_tax_bps: Option<u16>,
+
+    #[arg(long, value_enum, help = "bot run mode [default: snipe]")]
+    pub mode: Option<BotMode>,
+
+    #[arg(
+        long,
+        help = "file where liquidity add events are recorded in watch mode [default: ./logs/watch-record.jsonl]"
+    )]
+    pub watch_record: Option<String>,
+
+    #[arg(
+        long,
+        help = "simulate swaps with eth_call against the pending block instead of sending them"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        help = "blocks without inclusion after which a tx is replaced [default: 3]"
+    )]
+    pub stuck_after_blocks: Option<u64>,
+
+    #[arg(long, help = "seconds without inclusion after which a tx is replaced")]
+    pub stuck_after_secs: Option<u64>,
+
+    #[arg(
+        long,
+        help = "percent added to the fees of a replacement tx [default: 12]"
+    )]
+    pub gas_bump_pct: Option<u16>,
+
+    #[arg(long, help = "speed-ups of a stuck tx before giving up [default: 2]")]
+    pub max_replacements: Option<u32>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "what to do with a tx still stuck after every speed-up [default: cancel]"
+    )]
+    pub give_up: Option<GiveUpAction>,
+
+    #[arg(
+        long,
+        help = "file where submitted txs and their replacements are recorded [default: ./logs/tx-record.jsonl]"
+    )]
+    pub tx_record: Option<String>,
+
+    #[arg(
+        long,
+        action = ArgAction::Append,
+        help = "env var holding a wallet private key, pass several times for more wallets [default: BB_PRIVATE_KEY]"
+    )]
+    pub private_key_env: Vec<String>,
+
+    #[arg(
+        long,
+        action = ArgAction::Append,
+        help = "encrypted json keystore file, pass several times for more wallets"
+    )]
+    pub keystore: Vec<String>,
+
+    #[arg(
+        long,
+        help = "directory of encrypted json keystores, every file is loaded"
+    )]
+    pub keystore_dir: Option<String>,
+
+    #[arg(long, help = "file holding the keystore password")]
+    pub keystore_password_file: Option<String>,
+
+    #[arg(
+        long,
+        help = "env var holding the keystore password [default: BB_KEYSTORE_PASSWORD, prompted for when it is not set]"
+    )]
+    pub keystore_password_env: Option<String>,
+
+    #[arg(long, help = "prompt for the keystore password")]
+    pub keystore_password_prompt: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "how the buy amount is split between wallets [default: even]"
+    )]
+    pub buy_split: Option<BuySplit>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "where signed txs are sent, bundle uses a private relay [default: public]"
+    )]
+    pub submission: Option<BackendKind>,
+
+    #[arg(
+        long,
+        help = "eth_sendBundle endpoint of the bundle submission backend"
+    )]
+    pub relay_url: Option<String>,
+
+    #[arg(
+        long,
+        help = "detect liquidity adds from pending txs only, not from block logs"
+    )]
+    pub no_block_detection: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "pending tx subscription, full falls back to hashes when the node does not send tx bodies [default: full]"
+    )]
+    pub pending_txs: Option<PendingTxMode>,
 
-    #[arg(long, help = "token address. This token will be bought")]
-    pub token: String,
+    #[arg(
+        long,
+        help = "seconds without a new pending tx or block after which the ws connection is replaced [default: 30]"
+    )]
+    pub ws_stall_secs: Option<u64>,
 }
@@ -0,0 +1,999 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use ethers::prelude::{Address, Selector};
+use ethers::utils::{hex, parse_ether};
+use serde::{Deserialize, Deserializer};
+
+use super::backend::{BackendKind, DEFAULT_TARGET_BLOCKS};
+use super::cli::Args;
+use super::error::EnvSetUpError;
+use super::feed::DEFAULT_SEEN_TXS;
+use super::filter::FilterSettings;
+use super::gas::{parse_gwei, GasStrategy, GasStrategyKind, TxType};
+use super::mode::BotMode;
+use super::pending::PendingTxMode;
+use super::pool::PoolPolicy;
+use super::position::ExitRules;
+use super::retry::RetryPolicy;
+use super::safety::SafetyLimits;
+use super::submit::{GiveUpAction, ReplacementPolicy};
+use super::supervisor::WsPolicy;
+use super::target::Target;
+use super::trade::{self, SwapVariant, TradeSettings};
+use super::wallet::{BuySplit, PasswordSource, SplitPlan, WalletSources, DEFAULT_PRIVATE_KEY_ENV};
+
+/// network used when neither the config file nor the cli names one
+pub const DEFAULT_NETWORK: &str = "default";
+
+/// watch mode record file used when none is configured
+pub const DEFAULT_WATCH_RECORD: &str = "./logs/watch-record.jsonl";
+
+/// tx journal file used when none is configured
+pub const DEFAULT_TX_RECORD: &str = "./logs/tx-record.jsonl";
+
+/** Bot configuration
+ * parsed from a toml or yaml file and overridden by cli flags
+**/
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// name of the network to run on
+    pub network: Option<String>,
+    /// named networks
+    #[serde(default)]
+    pub networks: HashMap<String, NetworkConfig>,
+    /// trade settings used when the target does not set them
+    #[serde(default)]
+    pub defaults: TradeConfig,
+    /// tokens to buy
+    #[serde(default)]
+    pub targets: Vec<TargetConfig>,
+    pub mode: Option<BotMode>,
+    pub watch_record: Option<String>,
+    /// simulate swaps instead of sending them
+    pub dry_run: Option<bool>,
+    /// stuck tx detection and replacement
+    #[serde(default)]
+    pub replacement: ReplacementConfig,
+    /// file where submitted txs and their replacements are recorded
+    pub tx_record: Option<String>,
+    /// bot wallets and how buys are split between them
+    #[serde(default)]
+    pub wallets: WalletConfig,
+    /// where signed txs are sent to
+    #[serde(default)]
+    pub submission: SubmissionConfig,
+    /// detect liquidity adds from block logs too [default: true]
+    pub block_detection: Option<bool>,
+    /// pending tx subscription kind [default: full]
+    pub pending_txs: Option<PendingTxMode>,
+    /// ws stall detection and reconnect
+    #[serde(default)]
+    pub ws: WsConfig,
+    /// http provider scoring and circuit breaker
+    #[serde(default)]
+    pub provider_pool: ProviderPoolConfig,
+    /// tx fetch retries
+    #[serde(default)]
+    pub tx_fetch: TxFetchConfig,
+    /// pending tx pre-filters ahead of the liquidity add check
+    #[serde(default)]
+    pub filter: FilterConfig,
+}
+
+/// rpc endpoints and exchange contracts of one network
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// one url or a list, pending txs of every provider are merged
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub wss: Vec<String>,
+    #[serde(default)]
+    pub http: Vec<String>,
+    pub factory: Option<String>,
+    pub router: Option<String>,
+    pub native: Option<String>,
+    /// transaction type of our transactions [default: legacy]
+    pub tx_type: Option<TxType>,
+}
+
+/// trade settings, unset values fall back to the next level
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TradeConfig {
+    pub buy_amount: Option<String>,
+    pub slippage_bps: Option<u16>,
+    pub gas_limit_multiplier: Option<f64>,
+    pub gas_price_multiplier: Option<f64>,
+    /// gas price cap in gwei
+    pub max_gas_price: Option<String>,
+    pub gas_strategy: Option<GasStrategyKind>,
+    /// gwei added to the liquidity add gas price by the priority strategy
+    pub priority_premium: Option<String>,
+    /// gwei used by the fixed strategy
+    pub fixed_gas_price: Option<String>,
+    pub swap_variant: Option<SwapVariant>,
+    /// known token transfer tax in basis points
+    pub token_tax_bps: Option<u16>,
+    /// auto-sell rules after a successful buy
+    pub exit: Option<ExitRules>,
+    /// honeypot check run before the buy
+    pub safety: Option<SafetyLimits>,
+}
+
+/// target token with its own trade settings
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig {
+    pub token: Option<String>,
+    #[serde(flatten)]
+    pub trade: TradeConfig,
+}
+
+/// stuck tx handling, unset values take built-in defaults
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReplacementConfig {
+    pub stuck_after_blocks: Option<u64>,
+    pub stuck_after_secs: Option<u64>,
+    /// percent added to the fees of each replacement
+    pub gas_bump_pct: Option<u16>,
+    /// speed-ups before giving up on the tx
+    pub max_replacements: Option<u32>,
+    /// gwei cap of speed-up fees
+    pub max_gas_price: Option<String>,
+    pub give_up: Option<GiveUpAction>,
+}
+
+impl ReplacementConfig {
+    /// validate into replacement policy
+    pub fn resolve(&self) -> Result<ReplacementPolicy, EnvSetUpError> {
+        let defaults = ReplacementPolicy::default();
+        // a time limit alone turns off the default block limit
+        let stuck_after_blocks = match (self.stuck_after_blocks, self.stuck_after_secs) {
+            (None, Some(_)) => None,
+            (blocks, _) => blocks.or(defaults.stuck_after_blocks),
+        };
+        ReplacementPolicy {
+            stuck_after_blocks,
+            stuck_after: self.stuck_after_secs.map(Duration::from_secs),
+            gas_bump_pct: self.gas_bump_pct.unwrap_or(defaults.gas_bump_pct),
+            max_replacements: self.max_replacements.unwrap_or(defaults.max_replacements),
+            max_gas_price: self.max_gas_price.as_deref().map(parse_gwei).transpose()?,
+            give_up: self.give_up.unwrap_or(defaults.give_up),
+        }
+        .validate()
+    }
+}
+
+/// wallet sources and buy split
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WalletConfig {
+    /// env vars holding hex private keys [default: BB_PRIVATE_KEY unless a keystore is set]
+    #[serde(default)]
+    pub private_key_envs: Vec<String>,
+    /// encrypted json keystore files
+    #[serde(default)]
+    pub keystores: Vec<String>,
+    /// directory of encrypted json keystores, every file is loaded
+    pub keystore_dir: Option<String>,
+    /// file holding the keystore password
+    pub keystore_password_file: Option<String>,
+    /// env var holding the keystore password
+    /// [default: BB_KEYSTORE_PASSWORD, prompted for when it is not set]
+    pub keystore_password_env: Option<String>,
+    /// always prompt for the keystore password
+    #[serde(default)]
+    pub keystore_password_prompt: bool,
+    pub split: Option<BuySplit>,
+    /// weight of every wallet used by the weighted split, in wallet order
+    #[serde(default)]
+    pub weights: Vec<u16>,
+}
+
+impl WalletConfig {
+    pub fn sources(&self) -> WalletSources {
+        let no_keystore = self.keystores.is_empty() && self.keystore_dir.is_none();
+        let private_key_envs = if self.private_key_envs.is_empty() && no_keystore {
+            vec![DEFAULT_PRIVATE_KEY_ENV.to_owned()]
+        } else {
+            self.private_key_envs.clone()
+        };
+
+        let password = if self.keystore_password_prompt {
+            PasswordSource::Prompt
+        } else if let Some(path) = &self.keystore_password_file {
+            PasswordSource::File(path.clone())
+        } else if let Some(name) = &self.keystore_password_env {
+            PasswordSource::Env(name.clone())
+        } else {
+            PasswordSource::default()
+        };
+
+        WalletSources {
+            private_key_envs,
+            keystores: self.keystores.clone(),
+            keystore_dir: self.keystore_dir.clone(),
+            password,
+        }
+    }
+
+    /// buy split, checked against the wallet count once wallets are loaded
+    pub fn split_plan(&self) -> SplitPlan {
+        SplitPlan {
+            split: self.split.unwrap_or_default(),
+            weights: self.weights.clone(),
+        }
+    }
+}
+
+/// submission backend
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SubmissionConfig {
+    pub backend: Option<BackendKind>,
+    /// `eth_sendBundle` endpoint used by the bundle backend
+    pub relay_url: Option<String>,
+    /// bundles are sent for this many upcoming blocks
+    pub target_blocks: Option<u64>,
+}
+
+/// ws stall detection and reconnect backoff, unset values take built-in defaults
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WsConfig {
+    /// seconds without a new pending tx or block after which the connection is replaced
+    pub stall_secs: Option<u64>,
+    /// wait before the first reconnect attempt, doubled after every failed one
+    pub reconnect_backoff_ms: Option<u64>,
+    pub max_reconnect_backoff_secs: Option<u64>,
+    /// pending tx hashes remembered to drop the ones seen by another provider
+    pub seen_txs: Option<usize>,
+}
+
+impl WsConfig {
+    pub fn resolve(&self) -> Result<WsPolicy, EnvSetUpError> {
+        let defaults = WsPolicy::default();
+        WsPolicy {
+            stall_after: self
+                .stall_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.stall_after),
+            reconnect_backoff: self
+                .reconnect_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.reconnect_backoff),
+            max_reconnect_backoff: self
+                .max_reconnect_backoff_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.max_reconnect_backoff),
+        }
+        .validate()
+    }
+
+    pub fn seen_txs(&self) -> Result<usize, EnvSetUpError> {
+        match self.seen_txs.unwrap_or(DEFAULT_SEEN_TXS) {
+            0 => Err(EnvSetUpError::InvalidWsSetting(
+                "seen_txs must be greater than 0".to_owned(),
+            )),
+            seen_txs => Ok(seen_txs),
+        }
+    }
+}
+
+/// http provider circuit breaker, unset values take built-in defaults
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderPoolConfig {
+    /// failures in a row after which a provider is taken out
+    pub failure_threshold: Option<u32>,
+    /// seconds a failing provider stays out
+    pub cooldown_secs: Option<u64>,
+}
+
+impl ProviderPoolConfig {
+    pub fn resolve(&self) -> Result<PoolPolicy, EnvSetUpError> {
+        let defaults = PoolPolicy::default();
+        PoolPolicy {
+            failure_threshold: self.failure_threshold.unwrap_or(defaults.failure_threshold),
+            cooldown: self
+                .cooldown_secs
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.cooldown),
+        }
+        .validate()
+    }
+}
+
+/// tx fetch retry policy, unset values take built-in defaults
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TxFetchConfig {
+    /// fetch rounds before a tx is given up
+    pub max_attempts: Option<u32>,
+    /// wait before the second round, doubled after every round
+    pub backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+    /// time a fetch may take in total
+    pub deadline_ms: Option<u64>,
+}
+
+impl TxFetchConfig {
+    pub fn resolve(&self) -> Result<RetryPolicy, EnvSetUpError> {
+        let defaults = RetryPolicy::default();
+        RetryPolicy {
+            max_attempts: self.max_attempts.unwrap_or(defaults.max_attempts),
+            backoff: self
+                .backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.backoff),
+            max_backoff: self
+                .max_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.max_backoff),
+            deadline: self
+                .deadline_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.deadline),
+        }
+        .validate()
+    }
+}
+
+/// pending tx pre-filters, the router `to` and liquidity add selectors always apply
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    /// other routers of the same factory, watched for liquidity adds too
+    #[serde(default)]
+    pub to: Vec<String>,
+    /// 4 byte hex selectors accepted besides `addLiquidity` and `addLiquidityETH`
+    #[serde(default)]
+    pub extra_selectors: Vec<String>,
+    /// minimum tx value in native token
+    pub min_value: Option<String>,
+    /// accepted senders, e.g. a known deployer, every sender when empty
+    #[serde(default)]
+    pub senders: Vec<String>,
+}
+
+impl FilterConfig {
+    pub fn resolve(&self) -> Result<FilterSettings, EnvSetUpError> {
+        let min_value = match self.min_value.as_deref() {
+            Some(min_value) => {
+                let invalid = || EnvSetUpError::InvalidFilter(format!("min_value {}", min_value));
+                // negative amounts parse into wrapped values
+                if min_value.trim().starts_with('-') {
+                    return Err(invalid());
+                }
+                Some(parse_ether(min_value.trim()).map_err(|_| invalid())?)
+            }
+            None => None,
+        };
+        Ok(FilterSettings {
+            to: parse_addresses(&self.to, "to")?,
+            extra_selectors: self
+                .extra_selectors
+                .iter()
+                .map(|selector| parse_selector(selector))
+                .collect::<Result<_, _>>()?,
+            min_value,
+            senders: parse_addresses(&self.senders, "senders")?,
+        })
+    }
+}
+
+/// validated submission backend settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmissionSettings {
+    pub backend: BackendKind,
+    pub relay_url: Option<String>,
+    pub target_blocks: u64,
+}
+
+impl SubmissionConfig {
+    pub fn resolve(&self) -> Result<SubmissionSettings, EnvSetUpError> {
+        let backend = self.backend.unwrap_or_default();
+        if backend == BackendKind::Bundle && self.relay_url.is_none() {
+            return Err(EnvSetUpError::MissingSetting("relay_url"));
+        }
+        let target_blocks = self.target_blocks.unwrap_or(DEFAULT_TARGET_BLOCKS);
+        if target_blocks == 0 {
+            return Err(EnvSetUpError::InvalidSubmission(
+                "target_blocks must be greater than 0".to_owned(),
+            ));
+        }
+        Ok(SubmissionSettings {
+            backend,
+            relay_url: self.relay_url.clone(),
+            target_blocks,
+        })
+    }
+}
+
+/// fully resolved and validated settings
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub wss: Vec<String>,
+    pub http: Vec<String>,
+    pub factory: Address,
+    pub router: Address,
+    pub native: Address,
+    pub tx_type: TxType,
+    pub targets: Vec<Target>,
+    pub mode: BotMode,
+    pub watch_record: String,
+    pub dry_run: bool,
+    pub replacement: ReplacementPolicy,
+    pub tx_record: String,
+    pub wallet_sources: WalletSources,
+    pub buy_split: SplitPlan,
+    pub submission: SubmissionSettings,
+    pub block_detection: bool,
+    pub pending_txs: PendingTxMode,
+    pub ws: WsPolicy,
+    pub seen_txs: usize,
+    pub provider_pool: PoolPolicy,
+    pub tx_fetch: RetryPolicy,
+    pub filter: FilterSettings,
+}
+
+impl TradeConfig {
+    /// fill unset values from `fallback`
+    pub fn or(self, fallback: &TradeConfig) -> TradeConfig {
+        TradeConfig {
+            buy_amount: self.buy_amount.or_else(|| fallback.buy_amount.clone()),
+            slippage_bps: self.slippage_bps.or(fallback.slippage_bps),
+            gas_limit_multiplier: self.gas_limit_multiplier.or(fallback.gas_limit_multiplier),
+            gas_price_multiplier: self.gas_price_multiplier.or(fallback.gas_price_multiplier),
+            max_gas_price: self
+                .max_gas_price
+                .or_else(|| fallback.max_gas_price.clone()),
+            gas_strategy: self.gas_strategy.or(fallback.gas_strategy),
+            priority_premium: self
+                .priority_premium
+                .or_else(|| fallback.priority_premium.clone()),
+            fixed_gas_price: self
+                .fixed_gas_price
+                .or_else(|| fallback.fixed_gas_price.clone()),
+            swap_variant: self.swap_variant.or(fallback.swap_variant),
+            token_tax_bps: self.token_tax_bps.or(fallback.token_tax_bps),
+            exit: self.exit.or_else(|| fallback.exit.clone()),
+            safety: self.safety.or(fallback.safety),
+        }
+    }
+
+    /// validate into trade settings, unset values take built-in defaults
+    pub fn resolve(&self) -> Result<TradeSettings, EnvSetUpError> {
+        TradeSettings::new(
+            self.buy_amount
+                .as_deref()
+                .unwrap_or(trade::DEFAULT_BUY_AMOUNT),
+            self.slippage_bps.unwrap_or(trade::DEFAULT_SLIPPAGE_BPS),
+            self.gas_limit_multiplier
+                .unwrap_or(trade::DEFAULT_GAS_LIMIT_MULTIPLIER),
+            self.gas_price_multiplier
+                .unwrap_or(trade::DEFAULT_GAS_PRICE_MULTIPLIER),
+            self.max_gas_price.as_deref(),
+            self.swap_variant.unwrap_or(SwapVariant::Auto),
+            self.token_tax_bps,
+        )?
+        .with_gas_strategy(GasStrategy::new(
+            self.gas_strategy.unwrap_or_default(),
+            self.priority_premium.as_deref(),
+            self.fixed_gas_price.as_deref(),
+        )?)
+    }
+}
+
+impl Config {
+    /// load config file, format is picked by the file extension
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, EnvSetUpError> {
+        let path = path.as_ref();
+        let display_path = path.display().to_string();
+        let content = fs::read_to_string(path)
+            .map_err(|e| EnvSetUpError::ConfigRead(display_path.clone(), e))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content)
+                .map_err(|e| EnvSetUpError::ConfigParse(display_path, e.to_string())),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content)
+                .map_err(|e| EnvSetUpError::ConfigParse(display_path, e.to_string())),
+            _ => Err(EnvSetUpError::UnsupportedConfigFormat(display_path)),
+        }
+    }
+
+    /// override file values with the values passed on cli
+    pub(super) fn apply_args(&mut self, args: Args) {
+        if args.network.is_some() {
+            self.network = args.network;
+        }
+
+        // network is created only when cli defines it, so unknown names are reported
+        let overrides_network = !args.wss.is_empty()
+            || !args.http.is_empty()
+            || args.factory.is_some()
+            || args.router.is_some()
+            || args.native.is_some()
+            || args.tx_type.is_some();
+        if overrides_network {
+            let network_name = self.network_name().to_owned();
+            let network = self.networks.entry(network_name).or_default();
+            if !args.wss.is_empty() {
+                network.wss = args.wss;
+            }
+            if !args.http.is_empty() {
+                network.http = args.http;
+            }
+            if args.factory.is_some() {
+                network.factory = args.factory;
+            }
+            if args.router.is_some() {
+                network.router = args.router;
+            }
+            if args.native.is_some() {
+                network.native = args.native;
+            }
+            if args.tx_type.is_some() {
+                network.tx_type = args.tx_type;
+            }
+        }
+
+        // cli tokens replace the targets of config file
+        if !args.token.is_empty() {
+            self.targets = args
+                .token
+                .into_iter()
+                .map(|token| TargetConfig {
+                    token: Some(token),
+                    trade: TradeConfig::default(),
+                })
+                .collect();
+        }
+
+        // cli trade flags override the settings of every target
+        let cli_trade = TradeConfig {
+            buy_amount: args.buy_amount,
+            slippage_bps: args.slippage_bps,
+            gas_limit_multiplier: args.gas_limit_multiplier,
+            gas_price_multiplier: args.gas_price_multiplier,
+            max_gas_price: args.max_gas_price,
+            gas_strategy: args.gas_strategy,
+            priority_premium: args.priority_premium,
+            fixed_gas_price: args.fixed_gas_price,
+            swap_variant: args.swap_variant,
+            token_tax_bps: args.token_tax_bps,
+            exit: None,
+            safety: None,
+        };
+        for target in self.targets.iter_mut() {
+            target.trade = cli_trade.clone().or(&target.trade);
+        }
+
+        if args.mode.is_some() {
+            self.mode = args.mode;
+        }
+        if args.watch_record.is_some() {
+            self.watch_record = args.watch_record;
+        }
+        if args.dry_run {
+            self.dry_run = Some(true);
+        }
+
+        if args.stuck_after_blocks.is_some() {
+            self.replacement.stuck_after_blocks = args.stuck_after_blocks;
+        }
+        if args.stuck_after_secs.is_some() {
+            self.replacement.stuck_after_secs = args.stuck_after_secs;
+        }
+        if args.gas_bump_pct.is_some() {
+            self.replacement.gas_bump_pct = args.gas_bump_pct;
+        }
+        if args.max_replacements.is_some() {
+            self.replacement.max_replacements = args.max_replacements;
+        }
+        if args.give_up.is_some() {
+            self.replacement.give_up = args.give_up;
+        }
+        if args.tx_record.is_some() {
+            self.tx_record = args.tx_record;
+        }
+
+        // cli wallet sources replace the ones of config file
+        if !args.private_key_env.is_empty()
+            || !args.keystore.is_empty()
+            || args.keystore_dir.is_some()
+        {
+            self.wallets.private_key_envs = args.private_key_env;
+            self.wallets.keystores = args.keystore;
+            self.wallets.keystore_dir = args.keystore_dir;
+        }
+        if args.keystore_password_file.is_some() {
+            self.wallets.keystore_password_file = args.keystore_password_file;
+        }
+        if args.keystore_password_env.is_some() {
+            self.wallets.keystore_password_env = args.keystore_password_env;
+        }
+        if args.keystore_password_prompt {
+            self.wallets.keystore_password_prompt = true;
+        }
+        if args.buy_split.is_some() {
+            self.wallets.split = args.buy_split;
+        }
+
+        if args.submission.is_some() {
+            self.submission.backend = args.submission;
+        }
+        if args.relay_url.is_some() {
+            self.submission.relay_url = args.relay_url;
+        }
+        if args.no_block_detection {
+            self.block_detection = Some(false);
+        }
+        if args.pending_txs.is_some() {
+            self.pending_txs = args.pending_txs;
+        }
+        if args.ws_stall_secs.is_some() {
+            self.ws.stall_secs = args.ws_stall_secs;
+        }
+    }
+
+    pub fn network_name(&self) -> &str {
+        self.network.as_deref().unwrap_or(DEFAULT_NETWORK)
+    }
+
+    /// resolve the selected network and target into validated settings
+    pub fn resolve(&self) -> Result<Settings, EnvSetUpError> {
+        let network_name = self.network_name();
+        let network = self
+            .networks
+            .get(network_name)
+            .ok_or_else(|| EnvSetUpError::UnknownNetwork(network_name.to_owned()))?;
+
+        if network.wss.is_empty() {
+            return Err(EnvSetUpError::MissingSetting("wss"));
+        }
+        if network.http.is_empty() {
+            return Err(EnvSetUpError::MissingSetting("http"));
+        }
+
+        if self.targets.is_empty() {
+            return Err(EnvSetUpError::MissingSetting("token"));
+        }
+        let targets = self
+            .targets
+            .iter()
+            .map(|target| {
+                let trade = target.trade.clone().or(&self.defaults);
+                Ok(Target {
+                    token: parse_address(&target.token, "token")?,
+                    trade: trade.resolve()?,
+                    exit: trade.exit.map(ExitRules::validate).transpose()?,
+                    safety: trade.safety.map(SafetyLimits::validate).transpose()?,
+                })
+            })
+            .collect::<Result<Vec<Target>, EnvSetUpError>>()?;
+
+        Ok(Settings {
+            wss: network.wss.clone(),
+            http: network.http.clone(),
+            factory: parse_address(&network.factory, "factory")?,
+            router: parse_address(&network.router, "router")?,
+            native: parse_address(&network.native, "native")?,
+            tx_type: network.tx_type.unwrap_or_default(),
+            targets,
+            mode: self.mode.unwrap_or(BotMode::Snipe),
+            watch_record: self
+                .watch_record
+                .clone()
+                .unwrap_or_else(|| DEFAULT_WATCH_RECORD.to_owned()),
+            dry_run: self.dry_run.unwrap_or(false),
+            replacement: self.replacement.resolve()?,
+            tx_record: self
+                .tx_record
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TX_RECORD.to_owned()),
+            wallet_sources: self.wallets.sources(),
+            buy_split: self.wallets.split_plan(),
+            submission: self.submission.resolve()?,
+            block_detection: self.block_detection.unwrap_or(true),
+            pending_txs: self.pending_txs.unwrap_or_default(),
+            ws: self.ws.resolve()?,
+            seen_txs: self.ws.seen_txs()?,
+            provider_pool: self.provider_pool.resolve()?,
+            tx_fetch: self.tx_fetch.resolve()?,
+            filter: self.filter.resolve()?,
+        })
+    }
+}
+
+/// a single string or a list of strings
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+fn parse_addresses(values: &[String], name: &'static str) -> Result<Vec<Address>, EnvSetUpError> {
+    values
+        .iter()
+        .map(|value| {
+            value
+                .trim()
+                .parse::<Address>()
+                .map_err(|_| EnvSetUpError::InvalidFilter(format!("{} address {}", name, value)))
+        })
+        .collect()
+}
+
+/// `0x` prefixed 4 byte method selector
+fn parse_selector(value: &str) -> Result<Selector, EnvSetUpError> {
+    let invalid = || EnvSetUpError::InvalidFilter(format!("selector {}", value));
+    let bytes = hex::decode(value.trim().trim_start_matches("0x")).map_err(|_| invalid())?;
+    bytes.as_slice().try_into().map_err(|_| invalid())
+}
+
+fn parse_address(value: &Option<String>, name: &'static str) -> Result<Address, EnvSetUpError> {
+    Ok(value
+        .as_ref()
+        .ok_or(EnvSetUpError::MissingSetting(name))?
+        .parse::<Address>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use ethers::utils::parse_ether;
+
+    const TOKEN_A: &str = "0x0000000000000000000000000000000000000001";
+    const TOKEN_B: &str = "0x0000000000000000000000000000000000000002";
+
+    const NETWORK: &str = r#"
+[networks.default]
+wss = "ws://127.0.0.1:8546"
+http = ["http://127.0.0.1:8545"]
+factory = "0x00000000000000000000000000000000000000f1"
+router = "0x00000000000000000000000000000000000000f2"
+native = "0x00000000000000000000000000000000000000f3"
+"#;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn args(flags: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("block-bot").chain(flags.iter().copied())).unwrap()
+    }
+
+    fn targets_config() -> Config {
+        config(&targets_config_toml())
+    }
+
+    fn targets_config_toml() -> String {
+        format!(
+            r#"{}
+[defaults]
+buy_amount = "0.1"
+slippage_bps = 100
+
+[[targets]]
+token = "{}"
+buy_amount = "0.2"
+
+[[targets]]
+token = "{}"
+"#,
+            NETWORK, TOKEN_A, TOKEN_B
+        )
+    }
+
+    #[test]
+    fn target_values_override_defaults() {
+        let settings = targets_config().resolve().unwrap();
+        let (a, b) = (&settings.targets[0].trade, &settings.targets[1].trade);
+        assert_eq!(a.buy_amount, parse_ether("0.2").unwrap());
+        assert_eq!(b.buy_amount, parse_ether("0.1").unwrap());
+        assert_eq!(a.slippage_bps, 100);
+        // neither sets it, built-in default applies
+        assert_eq!(a.gas_limit_multiplier, trade::DEFAULT_GAS_LIMIT_MULTIPLIER);
+    }
+
+    #[test]
+    fn cli_overrides_targets_and_defaults() {
+        let mut config = targets_config();
+        config.apply_args(args(&["--buy-amount", "0.5", "--mode", "watch"]));
+        let settings = config.resolve().unwrap();
+        for target in settings.targets.iter() {
+            assert_eq!(target.trade.buy_amount, parse_ether("0.5").unwrap());
+            assert_eq!(target.trade.slippage_bps, 100);
+        }
+        assert_eq!(settings.mode, BotMode::Watch);
+    }
+
+    #[test]
+    fn cli_tokens_replace_targets() {
+        let mut config = targets_config();
+        config.apply_args(args(&["--token", TOKEN_B]));
+        let settings = config.resolve().unwrap();
+        assert_eq!(settings.targets.len(), 1);
+        assert_eq!(settings.targets[0].token, TOKEN_B.parse().unwrap());
+        // defaults still apply to cli tokens
+        assert_eq!(
+            settings.targets[0].trade.buy_amount,
+            parse_ether("0.1").unwrap()
+        );
+    }
+
+    #[test]
+    fn built_in_defaults_apply_without_settings() {
+        let settings = config(&format!(
+            "{}\n[[targets]]\ntoken = \"{}\"\n",
+            NETWORK, TOKEN_A
+        ))
+        .resolve()
+        .unwrap();
+        let trade = &settings.targets[0].trade;
+        assert_eq!(
+            trade.buy_amount,
+            parse_ether(trade::DEFAULT_BUY_AMOUNT).unwrap()
+        );
+        assert_eq!(trade.slippage_bps, trade::DEFAULT_SLIPPAGE_BPS);
+        assert_eq!(settings.mode, BotMode::Snipe);
+        assert_eq!(settings.wss, vec!["ws://127.0.0.1:8546".to_owned()]);
+        assert!(settings.block_detection);
+    }
+
+    #[test]
+    fn dry_run_is_set_by_file_or_cli() {
+        assert!(!targets_config().resolve().unwrap().dry_run);
+
+        let mut config = config(&format!(
+            "dry_run = true\n{}\n[[targets]]\ntoken = \"{}\"\n",
+            NETWORK, TOKEN_A
+        ));
+        // absent flag keeps the file value
+        config.apply_args(args(&[]));
+        assert!(config.resolve().unwrap().dry_run);
+
+        let mut config = targets_config();
+        config.apply_args(args(&["--dry-run"]));
+        assert!(config.resolve().unwrap().dry_run);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        for toml in [
+            "unknown = 1\n",
+            "[networks.default]\nwebsocket = \"ws://127.0.0.1\"\n",
+            "[defaults]\nbuy_amout = \"0.1\"\n",
+            "[ws]\nstall = 1\n",
+            "[[targets]]\ntoken = \"0x01\"\nbuy_amout = \"0.1\"\n",
+        ] {
+            assert!(toml::from_str::<Config>(toml).is_err(), "{}", toml);
+        }
+    }
+
+    #[test]
+    fn unknown_network_is_reported() {
+        let mut config = targets_config();
+        config.network = Some("mainnet".to_owned());
+        assert!(matches!(
+            config.resolve(),
+            Err(EnvSetUpError::UnknownNetwork(name)) if name == "mainnet"
+        ));
+    }
+
+    #[test]
+    fn network_needs_providers_and_addresses() {
+        let without_http = NETWORK.replace("http = [\"http://127.0.0.1:8545\"]\n", "");
+        let mut config = config(&format!(
+            "{}\n[[targets]]\ntoken = \"{}\"\n",
+            without_http, TOKEN_A
+        ));
+        assert!(matches!(
+            config.resolve(),
+            Err(EnvSetUpError::MissingSetting("http"))
+        ));
+
+        // cli completes the network of the config file
+        config.apply_args(args(&["--http", "http://127.0.0.1:8545"]));
+        assert!(config.resolve().is_ok());
+
+        config.apply_args(args(&["--router", "0x1234"]));
+        assert!(config.resolve().is_err());
+    }
+
+    #[test]
+    fn cli_network_is_created_for_unknown_name() {
+        let mut config = Config::default();
+        config.apply_args(args(&[
+            "--network",
+            "local",
+            "--wss",
+            "ws://a",
+            "--wss",
+            "ws://b",
+            "-H",
+            "http://a",
+            "--factory",
+            "0x00000000000000000000000000000000000000f1",
+            "--router",
+            "0x00000000000000000000000000000000000000f2",
+            "--native",
+            "0x00000000000000000000000000000000000000f3",
+            "--token",
+            TOKEN_A,
+        ]));
+        let settings = config.resolve().unwrap();
+        assert_eq!(settings.wss, vec!["ws://a".to_owned(), "ws://b".to_owned()]);
+    }
+
+    #[test]
+    fn missing_target_is_reported() {
+        assert!(matches!(
+            config(NETWORK).resolve(),
+            Err(EnvSetUpError::MissingSetting("token"))
+        ));
+    }
+
+    #[test]
+    fn filter_values_are_parsed() {
+        let settings = config(&format!(
+            "{}\n[filter]\nto = [\"{}\"]\nextra_selectors = [\"0xf305d719\"]\n\
+             min_value = \"0.5\"\nsenders = [\"{}\"]\n",
+            targets_config_toml(),
+            TOKEN_B,
+            TOKEN_A
+        ))
+        .resolve()
+        .unwrap();
+        assert_eq!(settings.filter.to, vec![TOKEN_B.parse().unwrap()]);
+        assert_eq!(
+            settings.filter.extra_selectors,
+            vec![[0xf3, 0x05, 0xd7, 0x19]]
+        );
+        assert_eq!(settings.filter.min_value, Some(parse_ether("0.5").unwrap()));
+        assert_eq!(settings.filter.senders, vec![TOKEN_A.parse().unwrap()]);
+        assert_eq!(
+            targets_config().resolve().unwrap().filter,
+            FilterSettings::default()
+        );
+    }
+
+    #[test]
+    fn invalid_filter_values_are_rejected() {
+        for filter in [
+            "min_value = \"-1\"",
+            "min_value = \"one\"",
+            "senders = [\"0x01\"]",
+            "to = [\"router\"]",
+            "extra_selectors = [\"0x1234\"]",
+            "extra_selectors = [\"0xzz345678\"]",
+        ] {
+            let toml = format!("{}\n[filter]\n{}\n", targets_config_toml(), filter);
+            let resolved = toml::from_str::<Config>(&toml).map(|config| config.resolve());
+            assert!(
+                !matches!(resolved, Ok(Ok(_))),
+                "{} should be rejected",
+                filter
+            );
+        }
+    }
+}
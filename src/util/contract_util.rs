@@ -10,7 +10,9 @@ pub fn decode_method_inputs<D: Detokenize, T: AsRef<[u8]>>(
     contract.decode_with_selector(function_signature, input)
 }
 
-pub fn get_method_name(contract: &Contract<Provider<Http>>, selector: Selector) -> String {
-    let (method_name, _) = contract.methods.get(&selector).expect("method not found");
-    method_name.to_owned()
+pub fn get_method_name(contract: &Contract<Provider<Http>>, selector: Selector) -> Option<String> {
+    contract
+        .methods
+        .get(&selector)
+        .map(|(method_name, _)| method_name.to_owned())
 }
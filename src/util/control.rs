@@ -0,0 +1,61 @@
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use std::sync::Arc;
+
+use super::mode::{BotMode, ModeSwitch};
+use super::pool::ProviderPool;
+
+/** Console control listener
+ * reads commands from stdin while the bot is running
+ *  - `snipe`, `watch`, `pause` switch the bot mode
+ *  - `mode` prints the current mode
+ *  - `status` prints the http provider stats
+**/
+pub fn spawn_stdin_listener(
+    mode_switch: ModeSwitch,
+    provider_pool: Arc<ProviderPool>,
+) -> JoinHandle<()> {
+    let control_span = tracing::info_span!("control_task");
+
+    tokio::spawn(
+        async move {
+            let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = line.trim();
+                if command.is_empty() {
+                    continue;
+                }
+
+                if command.eq_ignore_ascii_case("mode") {
+                    println!("current mode: {}", mode_switch.get());
+                    continue;
+                }
+
+                if command.eq_ignore_ascii_case("status") {
+                    for status in provider_pool.status() {
+                        println!("{}", status);
+                    }
+                    provider_pool.log_status();
+                    continue;
+                }
+
+                match command.parse::<BotMode>() {
+                    Ok(mode) => {
+                        let previous = mode_switch.set(mode);
+                        tracing::info!("bot mode switched from {} to {}", previous, mode);
+                        println!("mode switched from {} to {}", previous, mode);
+                    }
+                    Err(_) => println!(
+                        "unknown command `{}`, expected one of: snipe, watch, pause, mode, status",
+                        command
+                    ),
+                }
+            }
+            tracing::info!("stdin closed, control listener stopped");
+        }
+        .instrument(control_span),
+    )
+}
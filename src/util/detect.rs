@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use ethers::prelude::{Address, Filter, Log, Middleware, H256, U256, U64};
+use ethers::utils::keccak256;
+use serde::Serialize;
+use tokio::sync::mpsc::Sender;
+use tracing::instrument;
+
+use super::error::BlockBotError;
+use super::gas::TxType;
+use super::mode::{BotMode, ModeSwitch};
+use super::pool::ProviderPool;
+use super::retry::RetryPolicy;
+use super::supervisor::WsSupervisor;
+use super::target::TargetBook;
+use super::transaction::{fetch_transaction, SnipeSignal};
+use super::watch::WatchRecorder;
+use crate::contract::cake_factory::CakeFactory;
+
+/// pair abi is not bundled, pair event topics are hashed from their signatures
+const MINT_SIGNATURE: &str = "Mint(address,uint256,uint256)";
+const SYNC_SIGNATURE: &str = "Sync(uint112,uint112)";
+
+/// Where a liquidity add was detected
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DetectionSource {
+    /// pending liquidity add tx to the router
+    Mempool,
+    /// pair event in a confirmed block
+    Block,
+}
+
+impl fmt::Display for DetectionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DetectionSource::Mempool => f.write_str("mempool"),
+            DetectionSource::Block => f.write_str("block"),
+        }
+    }
+}
+
+/// Pair event showing liquidity was added
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PairEvent {
+    Mint,
+    /// sync with non-zero reserves
+    Sync,
+}
+
+impl fmt::Display for PairEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PairEvent::Mint => f.write_str("Mint"),
+            PairEvent::Sync => f.write_str("Sync"),
+        }
+    }
+}
+
+/// liquidity add of a waiting target found in a confirmed block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockDetection {
+    pub token: Address,
+    /// native pair of the token
+    pub pair: Address,
+    pub event: PairEvent,
+    /// tx the event was emitted by
+    pub tx_hash: H256,
+    pub block_number: Option<U64>,
+    /// mint amounts or sync reserves, in pair token order
+    pub amounts: (U256, U256),
+}
+
+/** De-duplicating channel to the swap receiver
+ * mempool and block detections of a target are forwarded once, the first one wins.
+ * A token is released again when its signal was skipped or its buy was put off
+**/
+#[derive(Debug)]
+pub struct SnipeChannel {
+    sender: Sender<SnipeSignal>,
+    /// first signal of every token
+    signaled: Mutex<HashMap<Address, SnipeSignal>>,
+}
+
+impl SnipeChannel {
+    pub fn new(sender: Sender<SnipeSignal>) -> SnipeChannel {
+        SnipeChannel {
+            sender,
+            signaled: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// forward signal unless its token was already signaled, true when forwarded
+    pub async fn send(&self, signal: SnipeSignal) -> bool {
+        {
+            let mut signaled = self.lock_signaled();
+            if let Some(first) = signaled.get(&signal.token) {
+                tracing::info!(
+                    "{} detection of {:?} in tx {:?} dropped, already signaled from {} tx {:?}",
+                    signal.source,
+                    signal.token,
+                    signal.tx_hash,
+                    first.source,
+                    first.tx_hash
+                );
+                return false;
+            }
+            signaled.insert(signal.token, signal);
+        }
+
+        if self.sender.send(signal).await.is_err() {
+            eprintln!("receiver is already closed");
+            return false;
+        }
+        true
+    }
+
+    /// forget the signal of `token`, its next detection is forwarded again
+    pub fn release(&self, token: &Address) {
+        if self.lock_signaled().remove(token).is_some() {
+            tracing::info!("detections of {:?} are forwarded again", token);
+        }
+    }
+
+    fn lock_signaled(&self) -> std::sync::MutexGuard<'_, HashMap<Address, SnipeSignal>> {
+        self.signaled
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+/** Block log liquidity add detector
+ * subscribes to new blocks and scans their logs for `PairCreated` of the factory
+ * and `Mint`/`Sync` of the native pairs of waiting targets.
+ * Catches liquidity adds whose pending tx was missed, e.g. ones sent privately
+**/
+#[derive(Debug)]
+pub struct BlockWatcher {
+    ws: Arc<WsSupervisor>,
+    /// providers the liquidity add tx is fetched from
+    provider_pool: Arc<ProviderPool>,
+    tx_fetch: RetryPolicy,
+    factory: CakeFactory,
+    native: Address,
+    targets: Arc<TargetBook>,
+    tx_type: TxType,
+    /// native pairs of targets, pair -> target token
+    pairs: HashMap<Address, Address>,
+    pair_created_topic: H256,
+    mint_topic: H256,
+    sync_topic: H256,
+}
+
+impl BlockWatcher {
+    /// pairs created before the start are looked up on the factory
+    #[instrument(skip_all)]
+    pub async fn new(
+        ws: Arc<WsSupervisor>,
+        provider_pool: Arc<ProviderPool>,
+        tx_fetch: RetryPolicy,
+        factory: CakeFactory,
+        native: Address,
+        targets: Arc<TargetBook>,
+        tx_type: TxType,
+    ) -> Result<BlockWatcher, BlockBotError> {
+        let mut pairs = HashMap::new();
+        for target in targets.targets() {
+            if let Some(pair) = factory.get_pair(target.token, native).await? {
+                tracing::info!("target {:?} has pair {:?}", target.token, pair);
+                pairs.insert(pair, target.token);
+            }
+        }
+
+        Ok(BlockWatcher {
+            pair_created_topic: factory.pair_created_topic()?,
+            mint_topic: H256::from(keccak256(MINT_SIGNATURE)),
+            sync_topic: H256::from(keccak256(SYNC_SIGNATURE)),
+            ws,
+            provider_pool,
+            tx_fetch,
+            factory,
+            native,
+            targets,
+            tx_type,
+            pairs,
+        })
+    }
+
+    /// scan every new block until all targets are handled
+    pub async fn run(
+        mut self,
+        channel: Arc<SnipeChannel>,
+        mode_switch: ModeSwitch,
+        watch_recorder: Arc<WatchRecorder>,
+    ) {
+        // resubscribed by the supervisor after reconnects
+        let mut blocks = self.ws.blocks();
+
+        while let Some(block) = blocks.recv().await {
+            if self.targets.all_handled() {
+                break;
+            }
+            // in paused mode liquidity add events are not watched
+            if mode_switch.get() == BotMode::Paused {
+                continue;
+            }
+            let block_hash = match block.hash {
+                Some(block_hash) => block_hash,
+                None => continue,
+            };
+
+            match self.detect(block_hash).await {
+                Ok(detections) => {
+                    for detection in detections {
+                        self.handle(detection, &channel, &mode_switch, &watch_recorder)
+                            .await;
+                    }
+                }
+                Err(err) => tracing::warn!("unable to scan block {:?}: {}", block_hash, err),
+            }
+        }
+        tracing::info!("block watcher stopped");
+    }
+
+    /// liquidity adds of waiting targets in block, one per target
+    #[instrument(skip(self))]
+    async fn detect(&mut self, block_hash: H256) -> Result<Vec<BlockDetection>, BlockBotError> {
+        let mut addresses = vec![self.factory.address()];
+        addresses.extend(self.pairs.keys());
+        let topics = vec![self.pair_created_topic, self.mint_topic, self.sync_topic];
+        let logs = self.block_logs(block_hash, addresses, topics).await?;
+
+        let mut new_pairs = Vec::new();
+        let mut detections = Vec::new();
+        for log in logs.iter() {
+            self.scan_log(log, &mut new_pairs, &mut detections);
+        }
+
+        // pair created and funded in the same block, its logs were not filtered for
+        if !new_pairs.is_empty() {
+            let topics = vec![self.mint_topic, self.sync_topic];
+            let logs = self.block_logs(block_hash, new_pairs, topics).await?;
+            for log in logs.iter() {
+                self.scan_log(log, &mut Vec::new(), &mut detections);
+            }
+        }
+        Ok(detections)
+    }
+
+    async fn block_logs(
+        &self,
+        block_hash: H256,
+        addresses: Vec<Address>,
+        topics: Vec<H256>,
+    ) -> Result<Vec<Log>, BlockBotError> {
+        let filter = Filter::new()
+            .at_block_hash(block_hash)
+            .address(addresses)
+            .topic0(topics);
+        Ok(self.ws.provider().get_logs(&filter).await?)
+    }
+
+    /// register pairs of waiting targets from `PairCreated`, collect liquidity adds
+    fn scan_log(
+        &mut self,
+        log: &Log,
+        new_pairs: &mut Vec<Address>,
+        detections: &mut Vec<BlockDetection>,
+    ) {
+        let topic = match log.topics.first() {
+            Some(topic) => *topic,
+            None => return,
+        };
+
+        if log.address == self.factory.address() && topic == self.pair_created_topic {
+            let created = match self.factory.decode_pair_created(log) {
+                Ok(created) => created,
+                Err(err) => {
+                    tracing::warn!("unable to decode PairCreated: {}", err);
+                    return;
+                }
+            };
+            if let Some(token) = created.other(&self.native) {
+                if self.targets.is_waiting(&token) && !self.pairs.contains_key(&created.pair) {
+                    tracing::info!(
+                        "pair {:?} of target {:?} created in tx {:?}",
+                        created.pair,
+                        token,
+                        log.transaction_hash
+                    );
+                    self.pairs.insert(created.pair, token);
+                    new_pairs.push(created.pair);
+                }
+            }
+            return;
+        }
+
+        let token = match self.pairs.get(&log.address) {
+            Some(token) => *token,
+            None => return,
+        };
+        if !self.targets.is_waiting(&token) || detections.iter().any(|d| d.token == token) {
+            return;
+        }
+        let (tx_hash, amounts) = match (log.transaction_hash, data_words(log)) {
+            (Some(tx_hash), Some(amounts)) => (tx_hash, amounts),
+            _ => return,
+        };
+        let event = if topic == self.mint_topic {
+            PairEvent::Mint
+        } else if topic == self.sync_topic && !(amounts.0.is_zero() && amounts.1.is_zero()) {
+            PairEvent::Sync
+        } else {
+            return;
+        };
+
+        detections.push(BlockDetection {
+            token,
+            pair: log.address,
+            event,
+            tx_hash,
+            block_number: log.block_number,
+            amounts,
+        });
+    }
+
+    /// record detection in watch mode, signal the swap receiver in snipe mode
+    async fn handle(
+        &self,
+        detection: BlockDetection,
+        channel: &SnipeChannel,
+        mode_switch: &ModeSwitch,
+        watch_recorder: &WatchRecorder,
+    ) {
+        tracing::info!(
+            "{} of target {:?} pair in block {:?}, tx {:?}",
+            detection.event,
+            detection.token,
+            detection.block_number,
+            detection.tx_hash
+        );
+        match mode_switch.get() {
+            BotMode::Paused => return,
+            BotMode::Watch => {
+                watch_recorder.record_block(&detection);
+                return;
+            }
+            BotMode::Snipe => {}
+        }
+
+        // gas of the liquidity add tx is the input of the gas strategy
+        match fetch_transaction(
+            Arc::clone(&self.provider_pool),
+            self.tx_fetch,
+            detection.tx_hash,
+        )
+        .await
+        {
+            Some(transaction) => {
+                channel
+                    .send(SnipeSignal::from_block(
+                        &detection,
+                        &transaction,
+                        self.tx_type,
+                    ))
+                    .await;
+            }
+            None => tracing::error!(
+                "unable to fetch liquidity add tx {:?} of block detection",
+                detection.tx_hash
+            ),
+        }
+    }
+}
+
+/// first two 32 byte words of the log data
+fn data_words(log: &Log) -> Option<(U256, U256)> {
+    let data = log.data.as_ref();
+    if data.len() < 64 {
+        return None;
+    }
+    Some((
+        U256::from_big_endian(&data[..32]),
+        U256::from_big_endian(&data[32..64]),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::gas::TxGas;
+    use ethers::prelude::Transaction;
+
+    fn signal(token: Address, source: DetectionSource) -> SnipeSignal {
+        SnipeSignal {
+            token,
+            tx_hash: H256::repeat_byte(1),
+            source_gas: TxGas::from_transaction(&Transaction::default(), TxType::Legacy),
+            source,
+        }
+    }
+
+    #[tokio::test]
+    async fn token_is_signaled_once_until_released() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+        let channel = SnipeChannel::new(sender);
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+
+        assert!(channel.send(signal(a, DetectionSource::Mempool)).await);
+        assert!(!channel.send(signal(a, DetectionSource::Block)).await);
+        assert!(channel.send(signal(b, DetectionSource::Block)).await);
+
+        // buy of a was put off, the block detection gets through
+        channel.release(&a);
+        assert!(channel.send(signal(a, DetectionSource::Block)).await);
+        assert!(!channel.send(signal(a, DetectionSource::Mempool)).await);
+
+        let mut forwarded = Vec::new();
+        while let Ok(signal) = receiver.try_recv() {
+            forwarded.push((signal.token, signal.source));
+        }
+        assert_eq!(
+            forwarded,
+            vec![
+                (a, DetectionSource::Mempool),
+                (b, DetectionSource::Block),
+                (a, DetectionSource::Block)
+            ]
+        );
+    }
+
+    #[test]
+    fn log_data_words_are_read() {
+        let log = Log {
+            data: ethers::abi::encode(&[
+                ethers::abi::Token::Uint(5u64.into()),
+                ethers::abi::Token::Uint(7u64.into()),
+            ])
+            .into(),
+            ..Default::default()
+        };
+        assert_eq!(data_words(&log), Some((5u64.into(), 7u64.into())));
+        assert_eq!(data_words(&Log::default()), None);
+    }
+}
@@ -1,78 +1,163 @@
 use clap::Parser;
 
-use ethers::prelude::{Address, Http, LocalWallet, Provider};
-use ethers::providers::Ws;
+use ethers::prelude::{Address, Http, Provider, Selector};
 
 use std::convert::TryFrom;
-use std::env;
 
 use std::sync::Arc;
 
+use super::backend::{BackendKind, BundleRelay, PublicRpc, RpcEndpoint, SubmissionBackend};
 use super::cli;
+use super::config::Config;
 use super::error::EnvSetUpError;
+use super::filter::FilterPipeline;
+use super::gas::TxType;
+use super::mode::BotMode;
+use super::pending::PendingTxMode;
+use super::pool::ProviderPool;
+use super::retry::RetryPolicy;
+use super::submit::ReplacementPolicy;
+use super::supervisor::WsSupervisor;
+use super::target::Target;
+use super::wallet::{SplitPlan, WalletSet};
 
 pub struct Env {
-    pub local_wallet: LocalWallet,
-    pub wss_provider: Arc<Provider<Ws>>,
+    pub wallets: WalletSet,
+    /// how the buy amount is split between wallets
+    pub buy_split: SplitPlan,
+    /// wss connections, replaced when they drop or stall. The first one is the primary
+    pub ws: Vec<Arc<WsSupervisor>>,
+    /// pending tx hashes remembered for de-duplication across ws providers
+    pub seen_txs: usize,
     pub http_providers: Vec<Arc<Provider<Http>>>,
+    /// http providers picked by score for tx fetches
+    pub provider_pool: Arc<ProviderPool>,
+    /// retries of tx fetches from the pool
+    pub tx_fetch: RetryPolicy,
+    /// pending tx pre-filter ahead of the liquidity add check
+    pub tx_filter: Arc<FilterPipeline>,
     pub factory_contract: Arc<Address>,
     pub router_contract: Arc<Address>,
+    /// router followed by the `[filter] to` routers, watched for liquidity adds
+    pub watched_routers: Arc<Vec<Address>>,
+    /// fork methods decoded as liquidity adds by their arguments
+    pub extra_selectors: Arc<Vec<Selector>>,
     pub bnb_address: Arc<Address>,
-    pub desired_token: Arc<Address>,
+    pub tx_type: TxType,
+    pub targets: Vec<Target>,
+    pub mode: BotMode,
+    pub watch_record: String,
+    /// swaps are simulated instead of sent
+    pub dry_run: bool,
+    pub replacement: ReplacementPolicy,
+    pub tx_record: String,
+    /// where signed txs are sent, shared by every wallet
+    pub submission_backend: Arc<dyn SubmissionBackend>,
+    /// liquidity adds are detected from block logs too
+    pub block_detection: bool,
+    pub pending_txs: PendingTxMode,
 }
 
 impl Env {
+    /// build env from config file (if any) overridden by cli flags
     pub async fn new() -> Result<Self, EnvSetUpError> {
         // parse args
         let args = cli::Args::parse();
 
-        // token addresses we are going to deal with
-        let desired_token = Arc::new(args.token.parse::<Address>()?);
+        // config file values, cli flags take precedence
+        let mut config = match &args.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        config.apply_args(args);
 
-        // bnb address
-        let bnb_address = Arc::new(args.native.parse::<Address>()?);
-
-        // contacts to watch
-        let factory = Arc::new(args.factory.parse::<Address>()?);
+        Self::from_config(config).await
+    }
 
-        // contacts to watch
-        let router = Arc::new(args.router.parse::<Address>()?);
+    pub async fn from_config(config: Config) -> Result<Self, EnvSetUpError> {
+        let settings = config.resolve()?;
 
-        // ws client
-        let ws = Ws::connect(args.wss)
-            .await
-            .expect("Error while making WebSocket connection");
+        // bot wallets
+        let wallets = WalletSet::load(&settings.wallet_sources)?;
+        let buy_split = settings.buy_split.validate(wallets.len())?;
 
-        // wss provider
-        let wss_provider: Arc<Provider<Ws>> =
-            Arc::new(Provider::new(ws).interval(std::time::Duration::from_millis(30)));
+        // supervised ws connections
+        let mut ws = Vec::new();
+        for wss in settings.wss {
+            ws.push(
+                WsSupervisor::connect(wss, settings.ws)
+                    .await
+                    .map_err(|e| EnvSetUpError::WsConnection(Box::new(e)))?,
+            );
+        }
 
         // http providers
-        let http_providers: Vec<Arc<Provider<Http>>> = args
+        let http_providers = settings
             .http
             .iter()
             .map(|provider_url| {
-                Arc::new(Provider::<Http>::try_from(provider_url).expect(&format!(
-                    "Error creating Http provider from url {}",
-                    provider_url
-                )))
+                Provider::<Http>::try_from(provider_url)
+                    .map(Arc::new)
+                    .map_err(|_| EnvSetUpError::InvalidHttpProvider(provider_url.to_owned()))
             })
-            .collect();
+            .collect::<Result<Vec<Arc<Provider<Http>>>, EnvSetUpError>>()?;
+
+        let provider_pool = ProviderPool::new(&http_providers, settings.provider_pool);
+
+        let tx_filter = Arc::new(FilterPipeline::from_settings(
+            settings.router,
+            &settings.filter,
+        ));
+        tx_filter.spawn_stats_logger();
 
-        const PVT_KEY_ENVKEY: &str = "BB_PRIVATE_KEY";
-        // pvt wallet
-        let local_wallet = env::var(PVT_KEY_ENVKEY)
-            .map(|pvt_key| pvt_key.parse::<LocalWallet>())
-            .map_err(|e| EnvSetUpError::EnvVarNotFound(PVT_KEY_ENVKEY.to_owned(), e))??;
+        // submission backend
+        let submission = settings.submission;
+        let submission_backend: Arc<dyn SubmissionBackend> = match submission.backend {
+            // raw txs are broadcast to every http and wss provider
+            BackendKind::Public => Arc::new(PublicRpc::new(
+                http_providers
+                    .iter()
+                    .map(|provider| RpcEndpoint::Http(Arc::clone(provider)))
+                    .chain(ws.iter().map(|ws| RpcEndpoint::Ws(Arc::clone(ws))))
+                    .collect(),
+            )),
+            BackendKind::Bundle => {
+                let relay_url = submission.relay_url.unwrap_or_default();
+                let relay = Provider::<Http>::try_from(relay_url.as_str())
+                    .map_err(|_| EnvSetUpError::InvalidHttpProvider(relay_url.clone()))?;
+                Arc::new(BundleRelay::new(
+                    relay,
+                    relay_url,
+                    Arc::clone(&http_providers[0]),
+                    submission.target_blocks,
+                ))
+            }
+        };
 
         Ok(Env {
-            local_wallet,
-            wss_provider,
+            wallets,
+            buy_split,
+            ws,
+            seen_txs: settings.seen_txs,
             http_providers,
-            factory_contract: factory,
-            router_contract: router,
-            bnb_address,
-            desired_token,
+            provider_pool,
+            tx_fetch: settings.tx_fetch,
+            tx_filter,
+            factory_contract: Arc::new(settings.factory),
+            router_contract: Arc::new(settings.router),
+            watched_routers: Arc::new(settings.filter.routers(settings.router)),
+            extra_selectors: Arc::new(settings.filter.extra_selectors.clone()),
+            bnb_address: Arc::new(settings.native),
+            tx_type: settings.tx_type,
+            targets: settings.targets,
+            mode: settings.mode,
+            watch_record: settings.watch_record,
+            dry_run: settings.dry_run,
+            replacement: settings.replacement,
+            tx_record: settings.tx_record,
+            submission_backend,
+            block_detection: settings.block_detection,
+            pending_txs: settings.pending_txs,
         })
     }
 }
@@ -1,5 +1,12 @@
-use ethers::{providers::ProviderError, signers::WalletError};
-use std::env;
+use ethers::{
+    contract::{AbiError, ContractError},
+    middleware::signer::SignerMiddlewareError,
+    prelude::{Address, Http, LocalWallet, Provider},
+    providers::{ProviderError, WsClientError},
+    signers::WalletError,
+    types::Selector,
+};
+use std::{env, io, sync::Arc};
 
 use thiserror::Error;
 
@@ -8,6 +15,24 @@ use thiserror::Error;
 pub enum BlockBotError {
     #[error("Envionment setup error")]
     EnvSetupFailed(#[from] EnvSetUpError),
+    #[error("Unable to read abi file {0}")]
+    AbiFileRead(String, #[source] io::Error),
+    #[error("Invalid abi file {0}")]
+    AbiFileParse(String, #[source] serde_json::Error),
+    #[error("Abi encoding or decoding failed")]
+    Abi(#[from] AbiError),
+    #[error("Contract call {0} failed")]
+    ContractCall(&'static str, #[source] ContractError<Provider<Http>>),
+    #[error("Rpc request failed")]
+    Rpc(#[from] ProviderError),
+    #[error("Signing or sending transaction failed")]
+    Signing(#[from] SignerMiddlewareError<Arc<Provider<Http>>, LocalWallet>),
+    #[error("Liquidity add decoding failed")]
+    Decode(#[from] LiquidityDecodeError),
+    #[error("Bundle relay {0} rejected the bundle")]
+    BundleRejected(String, #[source] ProviderError),
+    #[error("Unexpected response from {0}")]
+    UnexpectedResponse(&'static str),
 }
 
 #[derive(Error, Debug)]
@@ -22,4 +47,66 @@ pub enum EnvSetUpError {
     InvalidProvider(#[from] ProviderError),
     #[error("Invalid Wallet")]
     InvalidWallet(#[from] WalletError),
+    #[error("Invalid buy amount {0}, expected a positive amount of native token")]
+    InvalidBuyAmount(String),
+    #[error("Invalid slippage {0} bps, must be at most 10000")]
+    InvalidSlippage(u16),
+    #[error("Invalid {0} multiplier {1}, must be greater than 0 and at most 100")]
+    InvalidGasMultiplier(&'static str, f64),
+    #[error("Invalid gas price {0}, expected a positive amount of gwei")]
+    InvalidGasPrice(String),
+    #[error("Invalid token tax {0} bps, must be below 10000")]
+    InvalidTokenTax(u16),
+    #[error("Invalid exit rule: {0}")]
+    InvalidExitRule(String),
+    #[error("Invalid safety limit {0}: {1} bps")]
+    InvalidSafetyLimit(&'static str, u16),
+    #[error("Unable to read keystore {0}")]
+    KeystoreRead(String, #[source] io::Error),
+    #[error("Unable to decrypt keystore {0}")]
+    KeystoreDecrypt(String, #[source] WalletError),
+    #[error("Unable to read keystore password from {0}")]
+    PasswordRead(String, #[source] io::Error),
+    #[error("Wallet {0:?} is loaded more than once")]
+    DuplicateWallet(Address),
+    #[error("Invalid buy split: {0}")]
+    InvalidBuySplit(String),
+    #[error("Invalid submission setting: {0}")]
+    InvalidSubmission(String),
+    #[error("Invalid tx replacement setting: {0}")]
+    InvalidReplacement(String),
+    #[error("Invalid ws setting: {0}")]
+    InvalidWsSetting(String),
+    #[error("Invalid provider pool setting: {0}")]
+    InvalidProviderPool(String),
+    #[error("Invalid tx fetch retry setting: {0}")]
+    InvalidRetrySetting(String),
+    #[error("Invalid tx filter setting: {0}")]
+    InvalidFilter(String),
+    #[error("Unable to read config file {0}")]
+    ConfigRead(String, #[source] std::io::Error),
+    #[error("Invalid config file {0}: {1}")]
+    ConfigParse(String, String),
+    #[error("Unsupported config file format {0}, expected .toml, .yaml or .yml")]
+    UnsupportedConfigFormat(String),
+    #[error("Network {0} not found in config")]
+    UnknownNetwork(String),
+    #[error("Missing setting {0}, set it in config file or pass it on cli")]
+    MissingSetting(&'static str),
+    #[error("Invalid http provider url {0}")]
+    InvalidHttpProvider(String),
+    #[error("Unable to connect websocket provider")]
+    WsConnection(#[source] Box<WsClientError>),
+}
+
+#[derive(Error, Debug)]
+pub enum LiquidityDecodeError {
+    #[error("calldata of {0} bytes is too short for a method call")]
+    ShortCalldata(usize),
+    #[error("unknown method selector {0:?}")]
+    UnknownSelector(Selector),
+    #[error("method {0} is not a liquidity add")]
+    NotLiquidityAdd(String),
+    #[error("unable to decode {0} arguments")]
+    Abi(String, #[source] AbiError),
 }
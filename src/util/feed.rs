@@ -0,0 +1,342 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ethers::types::H256;
+use tokio::sync::mpsc;
+
+use super::pending::{PendingTx, PendingTxMode};
+use super::supervisor::WsSupervisor;
+
+/// tx hashes remembered for de-duplication when none is configured
+pub const DEFAULT_SEEN_TXS: usize = 50_000;
+
+/// merged pending txs buffered before the providers wait for the consumer
+const FEED_BUFFER: usize = 1024;
+
+/// how often the provider ranking is logged
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Mempool stats of one ws provider
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FeedStats {
+    /// pending txs received, duplicates included
+    pub seen: u64,
+    /// pending txs this provider delivered before every other one
+    pub first: u64,
+    /// summed delay behind the first provider of txs seen late
+    pub lag: Duration,
+}
+
+impl FeedStats {
+    /// average delay behind the first provider of txs seen late
+    pub fn avg_lag(&self) -> Duration {
+        let late = self.seen - self.first;
+        if late == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.lag.as_nanos() / u128::from(late)) as u64)
+    }
+}
+
+impl fmt::Display for FeedStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "first {} of {} seen, avg lag {}ms",
+            self.first,
+            self.seen,
+            self.avg_lag().as_millis()
+        )
+    }
+}
+
+/** Bounded LRU set of tx hashes
+ * every hit refreshes the hash, the least recently seen hash is evicted first.
+ * Refreshed hashes leave stale queue entries behind, skipped on eviction
+**/
+#[derive(Debug)]
+struct SeenTxs {
+    capacity: usize,
+    /// hash -> stamp of its latest queue entry, along with when it was first seen
+    entries: HashMap<H256, (u64, Instant)>,
+    order: VecDeque<(H256, u64)>,
+    stamp: u64,
+}
+
+impl SeenTxs {
+    fn new(capacity: usize) -> SeenTxs {
+        SeenTxs {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            stamp: 0,
+        }
+    }
+
+    /// mark hash seen now, returns when it was first seen when it is already known
+    fn insert(&mut self, tx_hash: H256, now: Instant) -> Option<Instant> {
+        self.stamp += 1;
+        let stamp = self.stamp;
+        let first_seen = match self.entries.get_mut(&tx_hash) {
+            Some(entry) => {
+                entry.0 = stamp;
+                Some(entry.1)
+            }
+            None => {
+                self.entries.insert(tx_hash, (stamp, now));
+                None
+            }
+        };
+        self.order.push_back((tx_hash, stamp));
+
+        while self.entries.len() > self.capacity {
+            self.evict_oldest();
+        }
+        // drop stale entries so refreshes do not grow the queue unbounded
+        if self.order.len() > self.capacity * 2 {
+            let entries = &self.entries;
+            self.order
+                .retain(|(tx_hash, stamp)| entries.get(tx_hash).is_some_and(|e| e.0 == *stamp));
+        }
+        first_seen
+    }
+
+    fn evict_oldest(&mut self) {
+        while let Some((tx_hash, stamp)) = self.order.pop_front() {
+            if self.entries.get(&tx_hash).is_some_and(|e| e.0 == stamp) {
+                self.entries.remove(&tx_hash);
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FeedState {
+    seen: SeenTxs,
+    stats: Vec<FeedStats>,
+}
+
+/** Merged pending tx feed of every ws provider
+ * each hash is forwarded once, by the provider delivering it first.
+ * Providers are ranked by how often they were first and how far behind they were otherwise
+**/
+#[derive(Debug)]
+pub struct MempoolFeed {
+    /// provider urls, in supervisor order
+    providers: Vec<String>,
+    state: Mutex<FeedState>,
+}
+
+impl MempoolFeed {
+    /// subscribe to pending txs of every supervisor and merge them into one receiver
+    pub fn start(
+        supervisors: &[Arc<WsSupervisor>],
+        mode: PendingTxMode,
+        seen_txs: usize,
+    ) -> (Arc<MempoolFeed>, mpsc::Receiver<PendingTx>) {
+        let feed = Arc::new(MempoolFeed {
+            providers: supervisors.iter().map(|ws| ws.url().to_owned()).collect(),
+            state: Mutex::new(FeedState {
+                seen: SeenTxs::new(seen_txs),
+                stats: vec![FeedStats::default(); supervisors.len()],
+            }),
+        });
+
+        let (sender, receiver) = mpsc::channel(FEED_BUFFER);
+        for (index, ws) in supervisors.iter().enumerate() {
+            let mut pending_txs = ws.pending_txs(mode);
+            let (feed, sender) = (Arc::clone(&feed), sender.clone());
+            tokio::spawn(async move {
+                while let Some(pending_tx) = pending_txs.recv().await {
+                    if feed.observe(index, pending_tx.hash(), Instant::now())
+                        && sender.send(pending_tx).await.is_err()
+                    {
+                        return;
+                    }
+                }
+            });
+        }
+
+        // ranking only matters when providers compete
+        if supervisors.len() > 1 {
+            let feed = Arc::downgrade(&feed);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(STATS_LOG_INTERVAL);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    match feed.upgrade() {
+                        Some(feed) => feed.log_stats(),
+                        None => return,
+                    }
+                }
+            });
+        }
+        (feed, receiver)
+    }
+
+    /// record that provider `index` delivered hash, true when no provider delivered it before
+    fn observe(&self, index: usize, tx_hash: H256, now: Instant) -> bool {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let first_seen = state.seen.insert(tx_hash, now);
+        let stats = &mut state.stats[index];
+        stats.seen += 1;
+        match first_seen {
+            Some(first_seen) => {
+                stats.lag += now.saturating_duration_since(first_seen);
+                false
+            }
+            None => {
+                stats.first += 1;
+                true
+            }
+        }
+    }
+
+    /// providers with their stats, the one most often first comes first
+    pub fn ranking(&self) -> Vec<(String, FeedStats)> {
+        let state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let mut ranking: Vec<(String, FeedStats)> = self
+            .providers
+            .iter()
+            .cloned()
+            .zip(state.stats.iter().copied())
+            .collect();
+        ranking.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.first));
+        ranking
+    }
+
+    pub fn log_stats(&self) {
+        for (rank, (provider, stats)) in self.ranking().iter().enumerate() {
+            tracing::info!("mempool feed #{} {}: {}", rank + 1, provider, stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::mock_ws::{MockWs, Session};
+    use crate::util::supervisor::WsPolicy;
+    use serde_json::json;
+
+    fn tx_hash(byte: u8) -> H256 {
+        H256::repeat_byte(byte)
+    }
+
+    #[test]
+    fn least_recently_seen_hash_is_evicted() {
+        let now = Instant::now();
+        let mut seen = SeenTxs::new(2);
+        assert_eq!(seen.insert(tx_hash(1), now), None);
+        assert_eq!(seen.insert(tx_hash(2), now), None);
+
+        // refresh 1, then 3 evicts 2
+        let later = now + Duration::from_millis(5);
+        assert_eq!(seen.insert(tx_hash(1), later), Some(now));
+        assert_eq!(seen.insert(tx_hash(3), later), None);
+        assert_eq!(seen.insert(tx_hash(1), later), Some(now));
+        assert_eq!(seen.insert(tx_hash(2), later), None);
+        assert_eq!(seen.entries.len(), 2);
+    }
+
+    #[test]
+    fn refreshes_do_not_grow_the_queue() {
+        let now = Instant::now();
+        let mut seen = SeenTxs::new(4);
+        for round in 0..100u8 {
+            seen.insert(tx_hash(round % 3), now);
+            assert!(seen.order.len() <= 2 * 4 + 1);
+        }
+        assert_eq!(seen.entries.len(), 3);
+    }
+
+    #[test]
+    fn first_provider_is_ranked_first() {
+        let feed = MempoolFeed {
+            providers: vec!["ws://a".to_owned(), "ws://b".to_owned()],
+            state: Mutex::new(FeedState {
+                seen: SeenTxs::new(DEFAULT_SEEN_TXS),
+                stats: vec![FeedStats::default(); 2],
+            }),
+        };
+        let now = Instant::now();
+        assert!(feed.observe(1, tx_hash(1), now));
+        assert!(!feed.observe(0, tx_hash(1), now + Duration::from_millis(30)));
+        assert!(feed.observe(1, tx_hash(2), now));
+        assert!(!feed.observe(0, tx_hash(2), now + Duration::from_millis(10)));
+        assert!(feed.observe(0, tx_hash(3), now));
+
+        let ranking = feed.ranking();
+        assert_eq!(ranking[0].0, "ws://b");
+        assert_eq!(
+            ranking[0].1,
+            FeedStats {
+                seen: 2,
+                first: 2,
+                lag: Duration::ZERO
+            }
+        );
+        assert_eq!(ranking[1].1.seen, 3);
+        assert_eq!(ranking[1].1.avg_lag(), Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn providers_are_merged_without_duplicates() {
+        let serve = |bytes: &'static [u8]| {
+            move |_| Session::Serve {
+                items: bytes.iter().map(|byte| json!(tx_hash(*byte))).collect(),
+                close_after: None,
+            }
+        };
+        let (a, b) = (
+            MockWs::start(serve(&[1, 2])).await,
+            MockWs::start(serve(&[2, 3])).await,
+        );
+        let mut supervisors = Vec::new();
+        for mock in [&a, &b] {
+            supervisors.push(
+                WsSupervisor::connect(mock.url().to_owned(), WsPolicy::default())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let (feed, mut pending_txs) =
+            MempoolFeed::start(&supervisors, PendingTxMode::Hashes, DEFAULT_SEEN_TXS);
+        let mut hashes = Vec::new();
+        for _ in 0..3 {
+            let pending_tx = tokio::time::timeout(Duration::from_secs(5), pending_txs.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            hashes.push(pending_tx.hash());
+        }
+        hashes.sort();
+        assert_eq!(hashes, vec![tx_hash(1), tx_hash(2), tx_hash(3)]);
+
+        // both providers delivered tx 2, only the first one was forwarded
+        while feed
+            .ranking()
+            .iter()
+            .map(|(_, stats)| stats.seen)
+            .sum::<u64>()
+            < 4
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(pending_txs.try_recv().is_err());
+        for ws in supervisors.iter() {
+            ws.shutdown();
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use ethers::prelude::{Address, Selector, Transaction, U256};
+use ethers::utils::id;
+
+/// router liquidity add signatures, accepted by the selector filter
+const ADD_LIQUIDITY_SIGNATURE: &str =
+    "addLiquidity(address,address,uint256,uint256,uint256,uint256,address,uint256)";
+const ADD_LIQUIDITY_ETH_SIGNATURE: &str =
+    "addLiquidityETH(address,uint256,uint256,uint256,address,uint256)";
+
+/// how often the filter counters are logged
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// validated pre-filter settings, the router `to` and liquidity add selectors always apply
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterSettings {
+    /// other routers of the same factory, their liquidity adds are decoded with the router abi
+    pub to: Vec<Address>,
+    /// selectors of router fork methods with `addLiquidity` or `addLiquidityETH` arguments,
+    /// e.g. `addLiquidityAVAX`
+    pub extra_selectors: Vec<Selector>,
+    /// minimum tx value in wei, None accepts any value
+    pub min_value: Option<U256>,
+    /// accepted senders, empty accepts every sender
+    pub senders: Vec<Address>,
+}
+
+impl FilterSettings {
+    /// `router` followed by the other watched routers
+    pub fn routers(&self, router: Address) -> Vec<Address> {
+        std::iter::once(router)
+            .chain(self.to.iter().copied())
+            .collect()
+    }
+}
+
+/// Pending tx check of a [`FilterPipeline`]
+pub trait TxFilter: fmt::Debug + Send + Sync {
+    /// name in the filter counters
+    fn name(&self) -> &'static str;
+
+    fn accepts(&self, transaction: &Transaction) -> bool;
+}
+
+/// accepts txs to one of the addresses, contract creations are rejected
+#[derive(Debug)]
+pub struct ToFilter(HashSet<Address>);
+
+impl ToFilter {
+    pub fn new(addresses: impl IntoIterator<Item = Address>) -> ToFilter {
+        ToFilter(addresses.into_iter().collect())
+    }
+}
+
+impl TxFilter for ToFilter {
+    fn name(&self) -> &'static str {
+        "to"
+    }
+
+    fn accepts(&self, transaction: &Transaction) -> bool {
+        transaction.to.is_some_and(|to| self.0.contains(&to))
+    }
+}
+
+/// accepts calls to one of the method selectors
+#[derive(Debug)]
+pub struct SelectorFilter(HashSet<Selector>);
+
+impl SelectorFilter {
+    pub fn new(selectors: impl IntoIterator<Item = Selector>) -> SelectorFilter {
+        SelectorFilter(selectors.into_iter().collect())
+    }
+
+    /// `addLiquidity` and `addLiquidityETH` along with `extra` selectors
+    pub fn liquidity_adds(extra: impl IntoIterator<Item = Selector>) -> SelectorFilter {
+        SelectorFilter::new(
+            vec![id(ADD_LIQUIDITY_SIGNATURE), id(ADD_LIQUIDITY_ETH_SIGNATURE)]
+                .into_iter()
+                .chain(extra),
+        )
+    }
+}
+
+impl TxFilter for SelectorFilter {
+    fn name(&self) -> &'static str {
+        "selector"
+    }
+
+    fn accepts(&self, transaction: &Transaction) -> bool {
+        transaction
+            .input
+            .get(..4)
+            .is_some_and(|selector| self.0.iter().any(|accepted| accepted[..] == *selector))
+    }
+}
+
+/// accepts txs sending at least the minimum value
+#[derive(Debug)]
+pub struct MinValueFilter(U256);
+
+impl MinValueFilter {
+    pub fn new(min_value: U256) -> MinValueFilter {
+        MinValueFilter(min_value)
+    }
+}
+
+impl TxFilter for MinValueFilter {
+    fn name(&self) -> &'static str {
+        "min value"
+    }
+
+    fn accepts(&self, transaction: &Transaction) -> bool {
+        transaction.value >= self.0
+    }
+}
+
+/// accepts txs from one of the senders, e.g. a known deployer
+#[derive(Debug)]
+pub struct SenderFilter(HashSet<Address>);
+
+impl SenderFilter {
+    pub fn new(senders: impl IntoIterator<Item = Address>) -> SenderFilter {
+        SenderFilter(senders.into_iter().collect())
+    }
+}
+
+impl TxFilter for SenderFilter {
+    fn name(&self) -> &'static str {
+        "sender"
+    }
+
+    fn accepts(&self, transaction: &Transaction) -> bool {
+        self.0.contains(&transaction.from)
+    }
+}
+
+/// filter with the number of txs it rejected
+#[derive(Debug)]
+struct CountedFilter {
+    filter: Box<dyn TxFilter>,
+    rejected: AtomicU64,
+}
+
+/// Counters of a filter pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterStats {
+    pub checked: u64,
+    pub passed: u64,
+    /// filter name -> txs it rejected, in pipeline order
+    pub rejected: Vec<(&'static str, u64)>,
+}
+
+impl fmt::Display for FilterStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} txs passed", self.passed, self.checked)?;
+        for (name, rejected) in self.rejected.iter() {
+            write!(f, ", {} rejected {}", name, rejected)?;
+        }
+        Ok(())
+    }
+}
+
+/** Pending tx pre-filter ahead of [`check_tx`](super::transaction::check_tx)
+ * filters run in order, the first one rejecting a tx ends the check and counts it.
+ * Cheap filters go first. A pending hash tells nothing about the tx, so only a full
+ * tx subscription saves the fetch, hashes are filtered once fetched
+**/
+#[derive(Debug, Default)]
+pub struct FilterPipeline {
+    filters: Vec<CountedFilter>,
+    checked: AtomicU64,
+    passed: AtomicU64,
+}
+
+impl FilterPipeline {
+    pub fn new() -> FilterPipeline {
+        FilterPipeline::default()
+    }
+
+    pub fn with(mut self, filter: impl TxFilter + 'static) -> FilterPipeline {
+        self.filters.push(CountedFilter {
+            filter: Box::new(filter),
+            rejected: AtomicU64::new(0),
+        });
+        self
+    }
+
+    /// `to` and selector filters of the router and the settings, then the min value and
+    /// sender filters when set
+    pub fn from_settings(router: Address, settings: &FilterSettings) -> FilterPipeline {
+        let mut pipeline = FilterPipeline::new()
+            .with(ToFilter::new(settings.routers(router)))
+            .with(SelectorFilter::liquidity_adds(
+                settings.extra_selectors.iter().copied(),
+            ));
+        if let Some(min_value) = settings.min_value {
+            pipeline = pipeline.with(MinValueFilter::new(min_value));
+        }
+        if !settings.senders.is_empty() {
+            pipeline = pipeline.with(SenderFilter::new(settings.senders.iter().copied()));
+        }
+        pipeline
+    }
+
+    /// true when every filter accepts the tx
+    pub fn accepts(&self, transaction: &Transaction) -> bool {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+        for counted in self.filters.iter() {
+            if !counted.filter.accepts(transaction) {
+                counted.rejected.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+        self.passed.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    pub fn stats(&self) -> FilterStats {
+        FilterStats {
+            checked: self.checked.load(Ordering::Relaxed),
+            passed: self.passed.load(Ordering::Relaxed),
+            rejected: self
+                .filters
+                .iter()
+                .map(|counted| {
+                    (
+                        counted.filter.name(),
+                        counted.rejected.load(Ordering::Relaxed),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn log_stats(&self) {
+        tracing::info!("tx filter {}", self.stats());
+    }
+
+    /// log the counters periodically while the pipeline is alive
+    pub fn spawn_stats_logger(self: &Arc<Self>) {
+        let pipeline: Weak<FilterPipeline> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STATS_LOG_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match pipeline.upgrade() {
+                    Some(pipeline) => pipeline.log_stats(),
+                    None => return,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::utils::parse_ether;
+
+    use super::*;
+
+    const ROUTER: Address = Address::repeat_byte(0xf2);
+    const DEPLOYER: Address = Address::repeat_byte(0xde);
+
+    fn tx(to: Option<Address>, signature: &str, value: U256, from: Address) -> Transaction {
+        Transaction {
+            to,
+            from,
+            value,
+            input: id(signature).to_vec().into(),
+            ..Default::default()
+        }
+    }
+
+    fn add_liquidity_eth(value: U256) -> Transaction {
+        tx(Some(ROUTER), ADD_LIQUIDITY_ETH_SIGNATURE, value, DEPLOYER)
+    }
+
+    #[test]
+    fn only_liquidity_adds_to_the_router_pass_by_default() {
+        let pipeline = FilterPipeline::from_settings(ROUTER, &FilterSettings::default());
+        assert!(pipeline.accepts(&add_liquidity_eth(U256::zero())));
+        assert!(pipeline.accepts(&tx(
+            Some(ROUTER),
+            ADD_LIQUIDITY_SIGNATURE,
+            U256::zero(),
+            DEPLOYER
+        )));
+        assert!(!pipeline.accepts(&tx(
+            Some(DEPLOYER),
+            ADD_LIQUIDITY_ETH_SIGNATURE,
+            U256::zero(),
+            DEPLOYER
+        )));
+        assert!(!pipeline.accepts(&tx(
+            None,
+            ADD_LIQUIDITY_ETH_SIGNATURE,
+            U256::zero(),
+            DEPLOYER
+        )));
+        assert!(!pipeline.accepts(&tx(
+            Some(ROUTER),
+            "transfer(address,uint256)",
+            U256::zero(),
+            DEPLOYER
+        )));
+
+        // input shorter than a selector
+        let mut short = add_liquidity_eth(U256::zero());
+        short.input = vec![0xf3, 0x05].into();
+        assert!(!pipeline.accepts(&short));
+    }
+
+    #[test]
+    fn other_routers_and_extra_selectors_widen_it() {
+        let fork = Address::repeat_byte(0xf4);
+        let fork_method = "addLiquidityAVAX(address,uint256,uint256,uint256,address,uint256)";
+        let settings = FilterSettings {
+            to: vec![fork],
+            extra_selectors: vec![id(fork_method)],
+            ..Default::default()
+        };
+        let pipeline = FilterPipeline::from_settings(ROUTER, &settings);
+        assert!(pipeline.accepts(&tx(Some(fork), fork_method, U256::zero(), DEPLOYER)));
+        assert!(pipeline.accepts(&add_liquidity_eth(U256::zero())));
+        assert!(!pipeline.accepts(&tx(Some(DEPLOYER), fork_method, U256::zero(), DEPLOYER)));
+        assert_eq!(settings.routers(ROUTER), vec![ROUTER, fork]);
+    }
+
+    #[test]
+    fn min_value_and_senders_narrow_it_down() {
+        let settings = FilterSettings {
+            min_value: Some(parse_ether("1").unwrap()),
+            senders: vec![DEPLOYER],
+            ..Default::default()
+        };
+        let pipeline = FilterPipeline::from_settings(ROUTER, &settings);
+        assert!(pipeline.accepts(&add_liquidity_eth(parse_ether("1").unwrap())));
+        assert!(!pipeline.accepts(&add_liquidity_eth(parse_ether("0.5").unwrap())));
+        assert!(!pipeline.accepts(&tx(
+            Some(ROUTER),
+            ADD_LIQUIDITY_ETH_SIGNATURE,
+            parse_ether("2").unwrap(),
+            ROUTER
+        )));
+    }
+
+    #[test]
+    fn first_rejecting_filter_counts_the_tx() {
+        let settings = FilterSettings {
+            min_value: Some(U256::one()),
+            senders: vec![DEPLOYER],
+            ..Default::default()
+        };
+        let pipeline = FilterPipeline::from_settings(ROUTER, &settings);
+        // rejected by both `to` and min value, only `to` counts it
+        pipeline.accepts(&tx(None, "", U256::zero(), ROUTER));
+        pipeline.accepts(&tx(Some(ROUTER), "", U256::one(), DEPLOYER));
+        pipeline.accepts(&add_liquidity_eth(U256::zero()));
+        pipeline.accepts(&tx(
+            Some(ROUTER),
+            ADD_LIQUIDITY_ETH_SIGNATURE,
+            U256::one(),
+            ROUTER,
+        ));
+        pipeline.accepts(&add_liquidity_eth(U256::one()));
+
+        let stats = pipeline.stats();
+        assert_eq!(
+            stats,
+            FilterStats {
+                checked: 5,
+                passed: 1,
+                rejected: vec![("to", 1), ("selector", 1), ("min value", 1), ("sender", 1)],
+            }
+        );
+        assert_eq!(
+            stats.to_string(),
+            "1 of 5 txs passed, to rejected 1, selector rejected 1, \
+             min value rejected 1, sender rejected 1"
+        );
+    }
+
+    #[test]
+    fn unset_filters_are_left_out() {
+        let pipeline = FilterPipeline::from_settings(ROUTER, &FilterSettings::default());
+        let names: Vec<&str> = pipeline
+            .stats()
+            .rejected
+            .iter()
+            .map(|(name, _)| *name)
+            .collect();
+        assert_eq!(names, vec!["to", "selector"]);
+    }
+}
@@ -0,0 +1,425 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use ethers::prelude::{Eip1559TransactionRequest, Transaction, TransactionRequest, U256};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::parse_units;
+use serde::Deserialize;
+
+use super::error::EnvSetUpError;
+
+/// Transaction envelope used for our transactions
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TxType {
+    /// type 0, single gas price
+    #[default]
+    Legacy,
+    /// type 2, max fee and priority fee
+    Eip1559,
+}
+
+impl fmt::Display for TxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxType::Legacy => f.write_str("legacy"),
+            TxType::Eip1559 => f.write_str("eip1559"),
+        }
+    }
+}
+
+/// Gas strategy names used in config and cli
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GasStrategyKind {
+    /// same gas price as the liquidity add, lands right after it
+    #[default]
+    Backrun,
+    /// liquidity add gas price plus a premium, capped at max gas price
+    Priority,
+    /// set gas price regardless of the liquidity add
+    Fixed,
+}
+
+/// How the fees of our tx are picked relative to the liquidity add tx
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GasStrategy {
+    #[default]
+    Backrun,
+    Priority {
+        premium: U256,
+    },
+    Fixed {
+        gas_price: U256,
+    },
+}
+
+impl GasStrategy {
+    /// build strategy of `kind`, gwei values are required by the kind using them
+    pub fn new(
+        kind: GasStrategyKind,
+        priority_premium: Option<&str>,
+        fixed_gas_price: Option<&str>,
+    ) -> Result<GasStrategy, EnvSetUpError> {
+        match kind {
+            GasStrategyKind::Backrun => Ok(GasStrategy::Backrun),
+            GasStrategyKind::Priority => Ok(GasStrategy::Priority {
+                premium: parse_gwei(
+                    priority_premium.ok_or(EnvSetUpError::MissingSetting("priority_premium"))?,
+                )?,
+            }),
+            GasStrategyKind::Fixed => Ok(GasStrategy::Fixed {
+                gas_price: parse_gwei(
+                    fixed_gas_price.ok_or(EnvSetUpError::MissingSetting("fixed_gas_price"))?,
+                )?,
+            }),
+        }
+    }
+
+    /// fees of our tx for the liquidity add tx `source` fees
+    /// for 1559 txs the premium is added to max fee and priority fee, fixed price is used for both
+    pub fn fees(&self, source: GasFees) -> GasFees {
+        match *self {
+            GasStrategy::Backrun => source,
+            GasStrategy::Priority { premium } => source.map(|fee| fee.saturating_add(premium)),
+            GasStrategy::Fixed { gas_price } => source.map(|_| gas_price),
+        }
+    }
+}
+
+impl fmt::Display for GasStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GasStrategy::Backrun => f.write_str("backrun"),
+            GasStrategy::Priority { premium } => write!(f, "priority +{}", premium),
+            GasStrategy::Fixed { gas_price } => write!(f, "fixed {}", gas_price),
+        }
+    }
+}
+
+/// Gas fees of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasFees {
+    Legacy {
+        gas_price: U256,
+    },
+    Eip1559 {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+}
+
+impl GasFees {
+    /** Fees of `transaction` in the form of `tx_type`
+     * legacy fees of a type 2 tx use its max fee,
+     * 1559 fees of a legacy tx use its gas price as both max fee and priority fee
+     **/
+    pub fn from_transaction(transaction: &Transaction, tx_type: TxType) -> GasFees {
+        let gas_price = transaction.gas_price.unwrap_or_default();
+        match tx_type {
+            TxType::Legacy => GasFees::Legacy {
+                gas_price: transaction.max_fee_per_gas.unwrap_or(gas_price),
+            },
+            TxType::Eip1559 => GasFees::Eip1559 {
+                max_fee_per_gas: transaction.max_fee_per_gas.unwrap_or(gas_price),
+                max_priority_fee_per_gas: transaction.max_priority_fee_per_gas.unwrap_or(gas_price),
+            },
+        }
+    }
+
+    /// highest price per gas the tx may pay
+    pub fn max_price(&self) -> U256 {
+        match self {
+            GasFees::Legacy { gas_price } => *gas_price,
+            GasFees::Eip1559 {
+                max_fee_per_gas, ..
+            } => *max_fee_per_gas,
+        }
+    }
+
+    /// fees set on `tx`, None when they are not filled yet
+    pub fn of(tx: &TypedTransaction) -> Option<GasFees> {
+        match tx {
+            TypedTransaction::Eip1559(tx) => Some(GasFees::Eip1559 {
+                max_fee_per_gas: tx.max_fee_per_gas?,
+                max_priority_fee_per_gas: tx.max_priority_fee_per_gas?,
+            }),
+            tx => tx
+                .gas_price()
+                .map(|gas_price| GasFees::Legacy { gas_price }),
+        }
+    }
+
+    /// set these fees on `tx`, 1559 fees on a legacy tx use the max fee as gas price
+    pub fn set_on(&self, tx: &mut TypedTransaction) {
+        match (tx, *self) {
+            (
+                TypedTransaction::Eip1559(tx),
+                GasFees::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                },
+            ) => {
+                tx.max_fee_per_gas = Some(max_fee_per_gas);
+                tx.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+            }
+            (tx, fees) => {
+                tx.set_gas_price(fees.max_price());
+            }
+        }
+    }
+
+    /// apply `f` to every fee, priority fee is kept at or below the max fee
+    pub fn map<F: Fn(U256) -> U256>(self, f: F) -> GasFees {
+        match self {
+            GasFees::Legacy { gas_price } => GasFees::Legacy {
+                gas_price: f(gas_price),
+            },
+            GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                let max_fee_per_gas = f(max_fee_per_gas);
+                GasFees::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas: f(max_priority_fee_per_gas).min(max_fee_per_gas),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for GasFees {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GasFees::Legacy { gas_price } => write!(f, "gas price: {}", gas_price),
+            GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => write!(
+                f,
+                "max fee: {}, priority fee: {}",
+                max_fee_per_gas, max_priority_fee_per_gas
+            ),
+        }
+    }
+}
+
+/// Gas limit and fees of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxGas {
+    pub gas: U256,
+    pub fees: GasFees,
+}
+
+impl TxGas {
+    /// gas of `transaction` with fees in the form of `tx_type`
+    pub fn from_transaction(transaction: &Transaction, tx_type: TxType) -> TxGas {
+        TxGas {
+            gas: transaction.gas,
+            fees: GasFees::from_transaction(transaction, tx_type),
+        }
+    }
+
+    /// typed transaction of `tx_req` with this gas limit and fees
+    pub fn apply(&self, tx_req: TransactionRequest) -> TypedTransaction {
+        let tx_req = tx_req.gas(self.gas);
+        match self.fees {
+            GasFees::Legacy { gas_price } => tx_req.gas_price(gas_price).into(),
+            GasFees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => eip1559_request(tx_req)
+                .max_fee_per_gas(max_fee_per_gas)
+                .max_priority_fee_per_gas(max_priority_fee_per_gas)
+                .into(),
+        }
+    }
+}
+
+impl fmt::Display for TxGas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "gas: {}, {}", self.gas, self.fees)
+    }
+}
+
+/// typed transaction of `tx_req` in the form of `tx_type`, fees are left for the signer to fill
+pub fn typed_transaction(tx_req: TransactionRequest, tx_type: TxType) -> TypedTransaction {
+    match tx_type {
+        TxType::Legacy => tx_req.into(),
+        TxType::Eip1559 => eip1559_request(tx_req).into(),
+    }
+}
+
+/// type 2 request with the fields of `tx_req`, gas price is dropped
+fn eip1559_request(tx_req: TransactionRequest) -> Eip1559TransactionRequest {
+    Eip1559TransactionRequest {
+        from: tx_req.from,
+        to: tx_req.to,
+        gas: tx_req.gas,
+        value: tx_req.value,
+        data: tx_req.data,
+        nonce: tx_req.nonce,
+        chain_id: tx_req.chain_id,
+        ..Default::default()
+    }
+}
+
+/// parse non zero gwei amount into wei
+pub fn parse_gwei(gwei: &str) -> Result<U256, EnvSetUpError> {
+    // negative amounts parse into wrapped values
+    if gwei.trim().starts_with('-') {
+        return Err(EnvSetUpError::InvalidGasPrice(gwei.to_owned()));
+    }
+    parse_units(gwei.trim(), "gwei")
+        .map(U256::from)
+        .ok()
+        .filter(|price| !price.is_zero())
+        .ok_or_else(|| EnvSetUpError::InvalidGasPrice(gwei.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::prelude::Address;
+
+    fn legacy_tx(gas_price: u64) -> Transaction {
+        Transaction {
+            gas: U256::from(300_000u64),
+            gas_price: Some(U256::from(gas_price)),
+            ..Default::default()
+        }
+    }
+
+    fn eip1559_tx(max_fee_per_gas: u64, max_priority_fee_per_gas: u64) -> Transaction {
+        Transaction {
+            gas: U256::from(300_000u64),
+            // nodes report the effective gas price of type 2 txs
+            gas_price: Some(U256::from(max_fee_per_gas - 1)),
+            max_fee_per_gas: Some(U256::from(max_fee_per_gas)),
+            max_priority_fee_per_gas: Some(U256::from(max_priority_fee_per_gas)),
+            transaction_type: Some(2u64.into()),
+            ..Default::default()
+        }
+    }
+
+    fn eip1559_fees(max_fee_per_gas: u64, max_priority_fee_per_gas: u64) -> GasFees {
+        GasFees::Eip1559 {
+            max_fee_per_gas: U256::from(max_fee_per_gas),
+            max_priority_fee_per_gas: U256::from(max_priority_fee_per_gas),
+        }
+    }
+
+    #[test]
+    fn source_fees_follow_our_tx_type() {
+        let legacy = legacy_tx(5);
+        let eip1559 = eip1559_tx(10, 2);
+
+        assert_eq!(
+            GasFees::from_transaction(&legacy, TxType::Legacy),
+            GasFees::Legacy {
+                gas_price: U256::from(5u64)
+            }
+        );
+        assert_eq!(
+            GasFees::from_transaction(&legacy, TxType::Eip1559),
+            eip1559_fees(5, 5)
+        );
+        assert_eq!(
+            GasFees::from_transaction(&eip1559, TxType::Legacy),
+            GasFees::Legacy {
+                gas_price: U256::from(10u64)
+            }
+        );
+        assert_eq!(
+            GasFees::from_transaction(&eip1559, TxType::Eip1559),
+            eip1559_fees(10, 2)
+        );
+    }
+
+    #[test]
+    fn priority_fee_stays_under_max_fee() {
+        let fees = eip1559_fees(10, 8).map(|fee| fee * 2);
+        assert_eq!(fees, eip1559_fees(20, 16));
+        let fees = eip1559_fees(10, 8).map(|fee| fee.min(U256::from(6u64)) + 4);
+        assert_eq!(fees, eip1559_fees(10, 10));
+        assert_eq!(fees.max_price(), U256::from(10u64));
+    }
+
+    #[test]
+    fn gwei_amounts_are_positive() {
+        assert_eq!(parse_gwei(" 1.5 ").unwrap(), U256::from(1_500_000_000u64));
+        for gwei in ["0", "", "-1", "five"] {
+            assert!(
+                matches!(parse_gwei(gwei), Err(EnvSetUpError::InvalidGasPrice(_))),
+                "{}",
+                gwei
+            );
+        }
+    }
+
+    #[test]
+    fn strategies_pick_fees_from_source() {
+        let gwei = |gwei: u64| U256::from(gwei) * U256::exp10(9);
+        let source = eip1559_fees(10, 2);
+
+        let backrun = GasStrategy::new(GasStrategyKind::Backrun, None, None).unwrap();
+        assert_eq!(backrun.fees(source), source);
+
+        let priority = GasStrategy::new(GasStrategyKind::Priority, Some("2"), None).unwrap();
+        assert_eq!(priority, GasStrategy::Priority { premium: gwei(2) });
+        assert_eq!(
+            priority.fees(source),
+            GasFees::Eip1559 {
+                max_fee_per_gas: U256::from(10u64) + gwei(2),
+                max_priority_fee_per_gas: U256::from(2u64) + gwei(2),
+            }
+        );
+
+        let fixed = GasStrategy::new(GasStrategyKind::Fixed, None, Some("5")).unwrap();
+        assert_eq!(
+            fixed.fees(GasFees::Legacy {
+                gas_price: U256::one()
+            }),
+            GasFees::Legacy { gas_price: gwei(5) }
+        );
+
+        assert!(matches!(
+            GasStrategy::new(GasStrategyKind::Priority, None, Some("5")),
+            Err(EnvSetUpError::MissingSetting("priority_premium"))
+        ));
+        assert!(matches!(
+            GasStrategy::new(GasStrategyKind::Fixed, Some("5"), None),
+            Err(EnvSetUpError::MissingSetting("fixed_gas_price"))
+        ));
+    }
+
+    #[test]
+    fn fees_are_applied_in_the_envelope_of_tx_type() {
+        let tx_req = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .value(7u64)
+            .nonce(3u64);
+        let tx_gas = TxGas::from_transaction(&eip1559_tx(10, 2), TxType::Eip1559);
+
+        let tx = tx_gas.apply(tx_req.clone());
+        let eip1559 = match &tx {
+            TypedTransaction::Eip1559(tx) => tx,
+            tx => panic!("unexpected envelope {:?}", tx),
+        };
+        assert_eq!(eip1559.gas, Some(U256::from(300_000u64)));
+        assert_eq!(eip1559.value, Some(U256::from(7u64)));
+        assert_eq!(eip1559.nonce, Some(U256::from(3u64)));
+        assert_eq!(GasFees::of(&tx), Some(eip1559_fees(10, 2)));
+
+        let mut legacy = typed_transaction(tx_req, TxType::Legacy);
+        assert_eq!(GasFees::of(&legacy), None);
+        tx_gas.fees.set_on(&mut legacy);
+        assert_eq!(
+            GasFees::of(&legacy),
+            Some(GasFees::Legacy {
+                gas_price: U256::from(10u64)
+            })
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::convert::TryInto;
+
+use ethers::abi::AbiDecode;
+use ethers::prelude::{Address, Selector, Transaction, U256};
+use serde::Serialize;
+
+use crate::contract::cake_router::CakeRouter;
+
+use super::error::LiquidityDecodeError;
+
+/// router method adding liquidity against the native token
+pub const ADD_LIQUIDITY_ETH: &str = "addLiquidityETH";
+
+/// router method adding liquidity for a token pair
+pub const ADD_LIQUIDITY: &str = "addLiquidity";
+
+/// abi encoded arguments of `addLiquidityETH` and `addLiquidity`
+const ETH_ARGUMENTS_LEN: usize = 6 * 32;
+const TOKEN_PAIR_ARGUMENTS_LEN: usize = 8 * 32;
+
+/// Decoded liquidity add call made to the router
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "method")]
+pub enum LiquidityAddEvent {
+    /// `addLiquidityETH`, native amount is the tx value
+    #[serde(rename = "addLiquidityETH")]
+    Eth {
+        token: Address,
+        amount_token_desired: U256,
+        amount_token_min: U256,
+        amount_eth: U256,
+        amount_eth_min: U256,
+        to: Address,
+        deadline: U256,
+    },
+    /// `addLiquidity`
+    #[serde(rename = "addLiquidity")]
+    TokenPair {
+        token_a: Address,
+        token_b: Address,
+        amount_a_desired: U256,
+        amount_b_desired: U256,
+        amount_a_min: U256,
+        amount_b_min: U256,
+        to: Address,
+        deadline: U256,
+    },
+}
+
+impl LiquidityAddEvent {
+    /// decode the liquidity add call of given router transaction
+    pub fn decode(
+        router: &CakeRouter,
+        transaction: &Transaction,
+    ) -> Result<LiquidityAddEvent, LiquidityDecodeError> {
+        let input = transaction.input.as_ref();
+        if input.len() < 4 {
+            return Err(LiquidityDecodeError::ShortCalldata(input.len()));
+        }
+
+        // extract method selector from the transaction input
+        let selector: Selector = input[0..4]
+            .try_into()
+            .map_err(|_| LiquidityDecodeError::ShortCalldata(input.len()))?;
+
+        let method_name = router
+            .get_method_name(selector)
+            .ok_or(LiquidityDecodeError::UnknownSelector(selector))?;
+
+        match method_name.as_str() {
+            ADD_LIQUIDITY_ETH => router
+                .decode_method_inputs(selector, input)
+                .map(|arguments| LiquidityAddEvent::eth(arguments, transaction.value))
+                .map_err(|e| LiquidityDecodeError::Abi(method_name, e)),
+            ADD_LIQUIDITY => router
+                .decode_method_inputs(selector, input)
+                .map(LiquidityAddEvent::token_pair)
+                .map_err(|e| LiquidityDecodeError::Abi(method_name, e)),
+            _ => Err(LiquidityDecodeError::NotLiquidityAdd(method_name)),
+        }
+    }
+
+    /** Decode a liquidity add call of a method the router abi does not know
+     * e.g. `addLiquidityAVAX` of a router fork. Arguments are decoded with the
+     * `addLiquidityETH` or `addLiquidity` layout, whichever fits the calldata length
+     **/
+    pub fn decode_by_layout(
+        transaction: &Transaction,
+    ) -> Result<LiquidityAddEvent, LiquidityDecodeError> {
+        let input = transaction.input.as_ref();
+        if input.len() < 4 {
+            return Err(LiquidityDecodeError::ShortCalldata(input.len()));
+        }
+        let (selector, arguments) = input.split_at(4);
+
+        match arguments.len() {
+            ETH_ARGUMENTS_LEN => AbiDecode::decode(arguments)
+                .map(|arguments| LiquidityAddEvent::eth(arguments, transaction.value))
+                .map_err(|e| LiquidityDecodeError::Abi(ADD_LIQUIDITY_ETH.to_owned(), e)),
+            TOKEN_PAIR_ARGUMENTS_LEN => AbiDecode::decode(arguments)
+                .map(LiquidityAddEvent::token_pair)
+                .map_err(|e| LiquidityDecodeError::Abi(ADD_LIQUIDITY.to_owned(), e)),
+            _ => Err(LiquidityDecodeError::UnknownSelector(
+                selector.try_into().unwrap_or_default(),
+            )),
+        }
+    }
+
+    fn eth(
+        (token, amount_token_desired, amount_token_min, amount_eth_min, to, deadline): (
+            Address,
+            U256,
+            U256,
+            U256,
+            Address,
+            U256,
+        ),
+        amount_eth: U256,
+    ) -> LiquidityAddEvent {
+        LiquidityAddEvent::Eth {
+            token,
+            amount_token_desired,
+            amount_token_min,
+            amount_eth,
+            amount_eth_min,
+            to,
+            deadline,
+        }
+    }
+
+    fn token_pair(
+        (
+            token_a,
+            token_b,
+            amount_a_desired,
+            amount_b_desired,
+            amount_a_min,
+            amount_b_min,
+            to,
+            deadline,
+        ): (Address, Address, U256, U256, U256, U256, Address, U256),
+    ) -> LiquidityAddEvent {
+        LiquidityAddEvent::TokenPair {
+            token_a,
+            token_b,
+            amount_a_desired,
+            amount_b_desired,
+            amount_a_min,
+            amount_b_min,
+            to,
+            deadline,
+        }
+    }
+
+    /// tokens whose liquidity is added
+    pub fn tokens(&self) -> Vec<Address> {
+        match self {
+            LiquidityAddEvent::Eth { token, .. } => vec![*token],
+            LiquidityAddEvent::TokenPair {
+                token_a, token_b, ..
+            } => vec![*token_a, *token_b],
+        }
+    }
+
+    pub fn method_name(&self) -> &'static str {
+        match self {
+            LiquidityAddEvent::Eth { .. } => ADD_LIQUIDITY_ETH,
+            LiquidityAddEvent::TokenPair { .. } => ADD_LIQUIDITY,
+        }
+    }
+
+    /// receiver of the liquidity tokens
+    pub fn recipient(&self) -> Address {
+        match self {
+            LiquidityAddEvent::Eth { to, .. } | LiquidityAddEvent::TokenPair { to, .. } => *to,
+        }
+    }
+
+    pub fn deadline(&self) -> U256 {
+        match self {
+            LiquidityAddEvent::Eth { deadline, .. }
+            | LiquidityAddEvent::TokenPair { deadline, .. } => *deadline,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    use ethers::abi::{encode, Token};
+    use ethers::prelude::{Http, LocalWallet, Provider};
+    use ethers::utils::id;
+
+    use super::*;
+    use crate::util::backend::PublicRpc;
+    use crate::util::nonce::NonceManager;
+    use crate::util::submit::{ReplacementPolicy, TxSubmitter};
+
+    /// router with the bundled abi, nothing is sent
+    fn router() -> CakeRouter {
+        let provider = Arc::new(Provider::<Http>::try_from("http://127.0.0.1:1").unwrap());
+        let wallet: LocalWallet = LocalWallet::new(&mut rand::thread_rng());
+        let submitter = TxSubmitter::new(
+            Arc::new(NonceManager::new(Arc::clone(&provider), Address::zero())),
+            Arc::new(PublicRpc::new(Vec::new())),
+            ReplacementPolicy::default(),
+            None,
+        );
+        CakeRouter::new(
+            Address::repeat_byte(0xf2),
+            "./abi/cake-router.json".to_owned(),
+            provider,
+            wallet,
+            Arc::new(submitter),
+        )
+        .unwrap()
+    }
+
+    fn call(signature: &str, args: &[Token], value: U256) -> Transaction {
+        let mut input = id(signature).to_vec();
+        input.extend(encode(args));
+        Transaction {
+            input: input.into(),
+            value,
+            ..Default::default()
+        }
+    }
+
+    fn uint(value: u64) -> Token {
+        Token::Uint(U256::from(value))
+    }
+
+    #[test]
+    fn decodes_add_liquidity_eth() {
+        let token = Address::repeat_byte(1);
+        let to = Address::repeat_byte(2);
+        let tx = call(
+            "addLiquidityETH(address,uint256,uint256,uint256,address,uint256)",
+            &[
+                Token::Address(token),
+                uint(1000),
+                uint(900),
+                uint(5),
+                Token::Address(to),
+                uint(99),
+            ],
+            U256::from(7),
+        );
+        let event = LiquidityAddEvent::decode(&router(), &tx).unwrap();
+        assert_eq!(
+            event,
+            LiquidityAddEvent::Eth {
+                token,
+                amount_token_desired: U256::from(1000),
+                amount_token_min: U256::from(900),
+                amount_eth: U256::from(7),
+                amount_eth_min: U256::from(5),
+                to,
+                deadline: U256::from(99),
+            }
+        );
+        assert_eq!(event.tokens(), vec![token]);
+        assert_eq!(event.method_name(), ADD_LIQUIDITY_ETH);
+    }
+
+    #[test]
+    fn decodes_add_liquidity() {
+        let (token_a, token_b) = (Address::repeat_byte(1), Address::repeat_byte(3));
+        let tx = call(
+            "addLiquidity(address,address,uint256,uint256,uint256,uint256,address,uint256)",
+            &[
+                Token::Address(token_a),
+                Token::Address(token_b),
+                uint(1),
+                uint(2),
+                uint(3),
+                uint(4),
+                Token::Address(Address::repeat_byte(2)),
+                uint(5),
+            ],
+            U256::zero(),
+        );
+        let event = LiquidityAddEvent::decode(&router(), &tx).unwrap();
+        assert_eq!(event.tokens(), vec![token_a, token_b]);
+        assert_eq!(event.recipient(), Address::repeat_byte(2));
+        assert_eq!(event.deadline(), U256::from(5));
+    }
+
+    #[test]
+    fn rejects_other_calls() {
+        let router = router();
+        let short = Transaction {
+            input: vec![0xf3, 0x05].into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            LiquidityAddEvent::decode(&router, &short),
+            Err(LiquidityDecodeError::ShortCalldata(2))
+        ));
+
+        let unknown = call("notARouterMethod()", &[], U256::zero());
+        assert!(matches!(
+            LiquidityAddEvent::decode(&router, &unknown),
+            Err(LiquidityDecodeError::UnknownSelector(_))
+        ));
+
+        let swap = call(
+            "swapExactETHForTokens(uint256,address[],address,uint256)",
+            &[
+                uint(1),
+                Token::Array(vec![Token::Address(Address::zero())]),
+                Token::Address(Address::zero()),
+                uint(1),
+            ],
+            U256::zero(),
+        );
+        assert!(matches!(
+            LiquidityAddEvent::decode(&router, &swap),
+            Err(LiquidityDecodeError::NotLiquidityAdd(_))
+        ));
+
+        let truncated = call(
+            "addLiquidityETH(address,uint256,uint256,uint256,address,uint256)",
+            &[Token::Address(Address::zero())],
+            U256::zero(),
+        );
+        assert!(matches!(
+            LiquidityAddEvent::decode(&router, &truncated),
+            Err(LiquidityDecodeError::Abi(..))
+        ));
+    }
+
+    #[test]
+    fn decodes_fork_methods_by_layout() {
+        let token = Address::repeat_byte(1);
+        let tx = call(
+            "addLiquidityAVAX(address,uint256,uint256,uint256,address,uint256)",
+            &[
+                Token::Address(token),
+                uint(1000),
+                uint(900),
+                uint(5),
+                Token::Address(Address::repeat_byte(2)),
+                uint(99),
+            ],
+            U256::from(7),
+        );
+        assert!(matches!(
+            LiquidityAddEvent::decode(&router(), &tx),
+            Err(LiquidityDecodeError::UnknownSelector(_))
+        ));
+        let event = LiquidityAddEvent::decode_by_layout(&tx).unwrap();
+        assert!(matches!(
+            event,
+            LiquidityAddEvent::Eth { amount_eth, .. } if amount_eth == U256::from(7)
+        ));
+        assert_eq!(event.tokens(), vec![token]);
+
+        let (token_a, token_b) = (Address::repeat_byte(1), Address::repeat_byte(3));
+        let tx = call(
+            "addLiquidityTokens(address,address,uint256,uint256,uint256,uint256,address,uint256)",
+            &[
+                Token::Address(token_a),
+                Token::Address(token_b),
+                uint(1),
+                uint(2),
+                uint(3),
+                uint(4),
+                Token::Address(Address::repeat_byte(2)),
+                uint(5),
+            ],
+            U256::zero(),
+        );
+        let event = LiquidityAddEvent::decode_by_layout(&tx).unwrap();
+        assert_eq!(event.tokens(), vec![token_a, token_b]);
+
+        let other = call("mint(address,uint256)", &[uint(1), uint(2)], U256::zero());
+        assert!(matches!(
+            LiquidityAddEvent::decode_by_layout(&other),
+            Err(LiquidityDecodeError::UnknownSelector(_))
+        ));
+    }
+}
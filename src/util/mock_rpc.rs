@@ -0,0 +1,154 @@
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+use ethers::prelude::{Http, Provider};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Answer of the mock to one json-rpc request
+#[derive(Debug, Clone)]
+pub(crate) enum Reply {
+    Result(Value),
+    Error(i64, String),
+}
+
+type Handler = dyn Fn(&str, &Value) -> Reply + Send + Sync;
+
+/** Json-rpc over http server for tests
+ * every request is answered by `handler` with the method and params,
+ * and recorded for assertions. Stopped when dropped
+**/
+pub(crate) struct MockRpc {
+    url: String,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockRpc {
+    pub async fn start(handler: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static) -> MockRpc {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let task_requests = Arc::clone(&requests);
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(
+                    stream,
+                    Arc::clone(&handler),
+                    Arc::clone(&task_requests),
+                ));
+            }
+        });
+        MockRpc {
+            url,
+            requests,
+            task,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn provider(&self) -> Provider<Http> {
+        Provider::<Http>::try_from(self.url()).unwrap()
+    }
+
+    /// params of every request of `method`, in arrival order
+    pub fn requests(&self, method: &str) -> Vec<Value> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| name == method)
+            .map(|(_, params)| params.clone())
+            .collect()
+    }
+}
+
+impl Drop for MockRpc {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// answer the requests of one keep-alive connection
+async fn serve(
+    mut stream: TcpStream,
+    handler: Arc<Handler>,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+) {
+    let mut buffer = Vec::new();
+    loop {
+        let body = match read_request(&mut stream, &mut buffer).await {
+            Some(body) => body,
+            None => return,
+        };
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let method = request["method"].as_str().unwrap_or_default().to_owned();
+        let params = request["params"].clone();
+        requests
+            .lock()
+            .unwrap()
+            .push((method.clone(), params.clone()));
+
+        let body = match handler(&method, &params) {
+            Reply::Result(result) => {
+                json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+            }
+            Reply::Error(code, message) => json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": {"code": code, "message": message}
+            }),
+        }
+        .to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        if stream.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// body of the next http request on the connection, None once it is closed
+async fn read_request(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .ok()
+            .filter(|read| *read > 0)?;
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let headers = String::from_utf8_lossy(&buffer[..header_end]).to_lowercase();
+    let content_length = headers
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|length| length.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    while buffer.len() < header_end + content_length {
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .ok()
+            .filter(|read| *read > 0)?;
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = buffer[header_end..header_end + content_length].to_vec();
+    buffer.drain(..header_end + content_length);
+    Some(body)
+}
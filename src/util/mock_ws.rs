@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+/// How the mock treats one ws connection
+#[derive(Debug, Clone)]
+pub(crate) enum Session {
+    /// drop the connection before the ws handshake
+    Refuse,
+    /// accept subscriptions and send `items` to each, then stay silent.
+    /// The connection is closed once `close_after` subscriptions were served
+    Serve {
+        items: Vec<Value>,
+        close_after: Option<usize>,
+    },
+}
+
+/** Json-rpc over ws server for tests
+ * `script` picks the session of every connection by its index, `eth_subscribe`
+ * params are recorded for assertions. Stopped when dropped
+**/
+pub(crate) struct MockWs {
+    url: String,
+    connections: Arc<AtomicUsize>,
+    subscriptions: Arc<Mutex<Vec<Value>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockWs {
+    pub async fn start(script: impl Fn(usize) -> Session + Send + Sync + 'static) -> MockWs {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let subscriptions = Arc::new(Mutex::new(Vec::new()));
+
+        let (task_connections, task_subscriptions) =
+            (Arc::clone(&connections), Arc::clone(&subscriptions));
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let index = task_connections.fetch_add(1, Ordering::SeqCst);
+                if let Session::Serve { items, close_after } = script(index) {
+                    tokio::spawn(serve(
+                        stream,
+                        items,
+                        close_after,
+                        Arc::clone(&task_subscriptions),
+                    ));
+                }
+            }
+        });
+        MockWs {
+            url,
+            connections,
+            subscriptions,
+            task,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// tcp connections accepted so far, refused ones included
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// params of every `eth_subscribe`, in arrival order
+    pub fn subscriptions(&self) -> Vec<Value> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockWs {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    stream: TcpStream,
+    items: Vec<Value>,
+    close_after: Option<usize>,
+    subscriptions: Arc<Mutex<Vec<Value>>>,
+) {
+    let mut ws = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(_) => return,
+    };
+    let mut next_id = 0u64;
+    while let Some(Ok(message)) = ws.next().await {
+        let request: Value = match message {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            Message::Close(_) => return,
+            _ => continue,
+        };
+        if request["method"] != "eth_subscribe" {
+            let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": true});
+            if ws.send(Message::Text(response.to_string())).await.is_err() {
+                return;
+            }
+            continue;
+        }
+
+        subscriptions
+            .lock()
+            .unwrap()
+            .push(request["params"].clone());
+        next_id += 1;
+        let subscription = format!("{:#x}", next_id);
+        let mut messages =
+            vec![json!({"jsonrpc": "2.0", "id": request["id"], "result": subscription})];
+        messages.extend(items.iter().map(|item| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "eth_subscription",
+                "params": {"subscription": subscription, "result": item}
+            })
+        }));
+        for message in messages {
+            if ws.send(Message::Text(message.to_string())).await.is_err() {
+                return;
+            }
+        }
+        if close_after.is_some_and(|close_after| next_id as usize >= close_after) {
+            let _ = ws.close(None).await;
+            return;
+        }
+    }
+}
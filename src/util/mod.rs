@@ -1,20 +1,68 @@
 //! Utility module
 //!
+//! backend
 //! cli_args
+//! config
+//! control
+//! detect
 //! env_setup
+//! feed
+//! filter
+//! gas
+//! liquidity
+//! mode
+//! nonce
+//! pending
+//! pool
+//! position
+//! retry
+//! safety
+//! submit
+//! supervisor
+//! target
+//! trade
 //! transaction
+//! wallet
+//! watch
 //!
 
+pub mod backend;
 mod cli;
+pub mod config;
 pub mod contract_util;
+pub mod control;
+pub mod detect;
 pub mod env_setup;
 pub mod error;
+pub mod feed;
+pub mod filter;
+pub mod gas;
+pub mod liquidity;
+#[cfg(test)]
+mod mock_rpc;
+#[cfg(test)]
+mod mock_ws;
+pub mod mode;
+pub mod nonce;
+pub mod pending;
+pub mod pool;
+pub mod position;
+pub mod retry;
+pub mod safety;
+pub mod submit;
+pub mod supervisor;
+pub mod target;
+pub mod trade;
 pub mod transaction;
+pub mod wallet;
+pub mod watch;
 
 use crate::contract::bep20::Bep20Token;
+use error::BlockBotError;
 use ethers::abi::Abi;
 use ethers::contract::Contract;
-use ethers::prelude::{Address, Http, LocalWallet, Provider, U256};
+use ethers::prelude::{Address, Http, LocalWallet, Provider, Signer, U256};
+use gas::TxType;
 use std::sync::Arc;
 
 use tracing::instrument;
@@ -26,54 +74,53 @@ impl Util {
         contract_address: &Address,
         abi_path: &str,
         provider: Arc<Provider<Http>>,
-    ) -> Contract<Provider<Http>> {
-        let file =
-            std::fs::read_to_string(abi_path).expect("something went wrong while reading abi file");
+    ) -> Result<Contract<Provider<Http>>, BlockBotError> {
+        let file = std::fs::read_to_string(abi_path)
+            .map_err(|e| BlockBotError::AbiFileRead(abi_path.to_owned(), e))?;
 
-        Contract::new(
-            *contract_address,
-            serde_json::from_str::<Abi>(&file).expect(""),
-            provider,
-        )
+        let abi = serde_json::from_str::<Abi>(&file)
+            .map_err(|e| BlockBotError::AbiFileParse(abi_path.to_owned(), e))?;
+
+        Ok(Contract::new(*contract_address, abi, provider))
     }
 
-    #[instrument]
+    /// wallet is kept out of the span, only its address is recorded
+    #[instrument(skip(token_contract, wallet), fields(wallet = ?wallet.address()))]
     pub async fn do_prerequisites(
         token_contract: &Bep20Token,
         wallet: LocalWallet,
         spender: Address,
-    ) {
-        let address = &format!("{:?}", wallet);
+        tx_type: TxType,
+    ) -> Result<(), BlockBotError> {
+        let address = wallet.address();
         let (total_supply, allowed_amt) =
-            Self::print_bep20_token_details(&token_contract, address, spender).await;
+            Self::print_bep20_token_details(token_contract, address, spender).await?;
 
         // if allowed spend amount is less than half of supply set it to total supply
-        if allowed_amt.le(&total_supply
-            .checked_div(U256::from(2u8))
-            .expect("div_error"))
-        {
+        if allowed_amt.le(&(total_supply / U256::from(2u8))) {
             token_contract
-                .approve_spend_allowance(spender, total_supply)
-                .await;
-            let _details = Self::print_bep20_token_details(&token_contract, address, spender);
+                .approve_spend_allowance(spender, total_supply, tx_type)
+                .await?;
+            Self::print_bep20_token_details(token_contract, address, spender).await?;
         }
         tracing::info!("Token pre-requisites completed");
+        Ok(())
     }
 
     #[instrument(skip(token_contract))]
     pub async fn print_bep20_token_details(
         token_contract: &Bep20Token,
-        user_address: &str,
+        user_address: Address,
         spender_address: Address,
-    ) -> (U256, U256) {
-        let name = token_contract.get_name().await;
-        let balance = token_contract.get_balance(&user_address).await;
-        let symbol = token_contract.get_symbol().await;
-        let decimals = token_contract.get_decimals().await;
-        let total_supply = token_contract.get_total_supply().await;
+    ) -> Result<(U256, U256), BlockBotError> {
+        let name = token_contract.get_name().await?;
+        let balance = token_contract.get_balance(user_address).await?;
+        let symbol = token_contract.get_symbol().await?;
+        let decimals = token_contract.get_decimals().await?;
+        let total_supply = token_contract.get_total_supply().await?;
         let allowed_amount = token_contract
-            .get_spend_allowance(&user_address, spender_address)
-            .await;
+            .get_spend_allowance(user_address, spender_address)
+            .await?;
 
         tracing::info!(
             "{:?} is {} ({}), decimals: {}, supply: {}, balance: {}, spend limit: {}",
@@ -85,6 +132,6 @@ impl Util {
             balance,
             allowed_amount
         );
-        (total_supply, allowed_amount)
+        Ok((total_supply, allowed_amount))
     }
 }
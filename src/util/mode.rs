@@ -0,0 +1,112 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Bot run mode
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BotMode {
+    /// watch liquidity add events and buy the desired token
+    Snipe,
+    /// watch liquidity add events and record them without swapping
+    Watch,
+    /// do not watch liquidity add events
+    Paused,
+}
+
+impl BotMode {
+    fn from_u8(value: u8) -> BotMode {
+        match value {
+            0 => BotMode::Snipe,
+            1 => BotMode::Watch,
+            _ => BotMode::Paused,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            BotMode::Snipe => 0,
+            BotMode::Watch => 1,
+            BotMode::Paused => 2,
+        }
+    }
+}
+
+impl fmt::Display for BotMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BotMode::Snipe => "snipe",
+            BotMode::Watch => "watch",
+            BotMode::Paused => "paused",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for BotMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "snipe" => Ok(BotMode::Snipe),
+            "watch" => Ok(BotMode::Watch),
+            "pause" | "paused" => Ok(BotMode::Paused),
+            other => Err(format!("unknown bot mode {}", other)),
+        }
+    }
+}
+
+/** Runtime mode switch
+ * cheap to clone, every clone observes the same mode
+**/
+#[derive(Clone, Debug)]
+pub struct ModeSwitch {
+    mode: Arc<AtomicU8>,
+}
+
+impl ModeSwitch {
+    pub fn new(mode: BotMode) -> ModeSwitch {
+        ModeSwitch {
+            mode: Arc::new(AtomicU8::new(mode.as_u8())),
+        }
+    }
+
+    pub fn get(&self) -> BotMode {
+        BotMode::from_u8(self.mode.load(Ordering::SeqCst))
+    }
+
+    /// switch to the given mode returning the previous one
+    pub fn set(&self, mode: BotMode) -> BotMode {
+        BotMode::from_u8(self.mode.swap(mode.as_u8(), Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn console_commands_parse_to_modes() {
+        assert_eq!(" Snipe\n".parse::<BotMode>(), Ok(BotMode::Snipe));
+        assert_eq!("watch".parse::<BotMode>(), Ok(BotMode::Watch));
+        assert_eq!("pause".parse::<BotMode>(), Ok(BotMode::Paused));
+        assert_eq!("paused".parse::<BotMode>(), Ok(BotMode::Paused));
+        assert!("buy".parse::<BotMode>().is_err());
+    }
+
+    #[test]
+    fn clones_share_the_mode() {
+        let mode_switch = ModeSwitch::new(BotMode::Watch);
+        let clone = mode_switch.clone();
+        assert_eq!(clone.set(BotMode::Paused), BotMode::Watch);
+        assert_eq!(mode_switch.get(), BotMode::Paused);
+        for mode in [BotMode::Snipe, BotMode::Watch, BotMode::Paused] {
+            mode_switch.set(mode);
+            assert_eq!(clone.get(), mode);
+        }
+    }
+}
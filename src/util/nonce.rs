@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use ethers::prelude::{Address, BlockId, BlockNumber, Http, Middleware, Provider, U256};
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use super::error::BlockBotError;
+
+/** Locally tracked nonce of the bot wallet
+ * shared by every signing path so txs sent in the same block do not clash,
+ * resynced from the pending tx count on startup and after failed sends
+**/
+#[derive(Debug)]
+pub struct NonceManager {
+    provider: Arc<Provider<Http>>,
+    address: Address,
+    /// next nonce to use, None when it has to be fetched again
+    next: Mutex<Option<U256>>,
+}
+
+impl NonceManager {
+    pub fn new(provider: Arc<Provider<Http>>, address: Address) -> NonceManager {
+        NonceManager {
+            provider,
+            address,
+            next: Mutex::new(None),
+        }
+    }
+
+    /// fetch the pending tx count of the wallet as next nonce
+    #[instrument(skip(self))]
+    pub async fn sync(&self) -> Result<U256, BlockBotError> {
+        let mut next = self.next.lock().await;
+        let nonce = self.fetch().await?;
+        *next = Some(nonce);
+        tracing::info!("nonce of {:?} synced to {}", self.address, nonce);
+        Ok(nonce)
+    }
+
+    /// reserve the next nonce
+    pub async fn next(&self) -> Result<U256, BlockBotError> {
+        let mut next = self.next.lock().await;
+        let nonce = match *next {
+            Some(nonce) => nonce,
+            None => self.fetch().await?,
+        };
+        *next = Some(nonce + 1);
+        Ok(nonce)
+    }
+
+    /// drop the tracked nonce, next reservation resyncs from the node
+    pub async fn invalidate(&self) {
+        *self.next.lock().await = None;
+    }
+
+    async fn fetch(&self) -> Result<U256, BlockBotError> {
+        Ok(self
+            .provider
+            .get_transaction_count(self.address, Some(BlockId::Number(BlockNumber::Pending)))
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::mock_rpc::{MockRpc, Reply};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// node reporting `pending` txs, the count is raised through the returned handle
+    async fn node(pending: u64) -> (MockRpc, Arc<AtomicU64>) {
+        let count = Arc::new(AtomicU64::new(pending));
+        let node_count = Arc::clone(&count);
+        let rpc = MockRpc::start(move |method, _| match method {
+            "eth_getTransactionCount" => {
+                Reply::Result(json!(format!("{:#x}", node_count.load(Ordering::SeqCst))))
+            }
+            _ => Reply::Error(-32601, "method not found".to_owned()),
+        })
+        .await;
+        (rpc, count)
+    }
+
+    #[tokio::test]
+    async fn nonces_are_reserved_locally_after_first_fetch() {
+        let (rpc, _) = node(5).await;
+        let address = Address::repeat_byte(1);
+        let nonces = NonceManager::new(Arc::new(rpc.provider()), address);
+
+        let (a, b, c) = tokio::join!(nonces.next(), nonces.next(), nonces.next());
+        let mut reserved = vec![a.unwrap(), b.unwrap(), c.unwrap()];
+        reserved.sort();
+        assert_eq!(reserved, vec![5u64.into(), 6u64.into(), 7u64.into()]);
+
+        let fetches = rpc.requests("eth_getTransactionCount");
+        assert_eq!(fetches, vec![json!([address, "pending"])]);
+    }
+
+    #[tokio::test]
+    async fn invalidated_nonce_is_fetched_again() {
+        let (rpc, count) = node(5).await;
+        let nonces = NonceManager::new(Arc::new(rpc.provider()), Address::repeat_byte(1));
+        assert_eq!(nonces.sync().await.unwrap(), 5u64.into());
+        assert_eq!(nonces.next().await.unwrap(), 5u64.into());
+        assert_eq!(nonces.next().await.unwrap(), 6u64.into());
+
+        // a send failed and the node saw a tx of ours we did not count
+        count.store(9, Ordering::SeqCst);
+        nonces.invalidate().await;
+        assert_eq!(nonces.next().await.unwrap(), 9u64.into());
+        assert_eq!(rpc.requests("eth_getTransactionCount").len(), 2);
+    }
+}
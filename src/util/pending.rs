@@ -0,0 +1,98 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use ethers::prelude::Transaction;
+use ethers::types::H256;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Pending tx subscription kinds
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PendingTxMode {
+    /// full tx bodies, hashes are fetched when the node sends hashes only
+    #[default]
+    Full,
+    /// tx hashes, every tx is fetched over http
+    Hashes,
+}
+
+impl PendingTxMode {
+    /** `eth_subscribe` params tried in order
+     * full mode asks for tx bodies first, nodes rejecting that get the plain
+     * hash subscription. Nodes ignoring the flag keep sending hashes, [`PendingTx`]
+     * takes either
+     **/
+    pub fn subscription_params(self) -> Vec<Value> {
+        let hashes = json!(["newPendingTransactions"]);
+        match self {
+            PendingTxMode::Full => vec![json!(["newPendingTransactions", true]), hashes],
+            PendingTxMode::Hashes => vec![hashes],
+        }
+    }
+}
+
+impl fmt::Display for PendingTxMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PendingTxMode::Full => f.write_str("full"),
+            PendingTxMode::Hashes => f.write_str("hashes"),
+        }
+    }
+}
+
+/// pending tx notification, a hash or the full tx depending on the node
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PendingTx {
+    Hash(H256),
+    Full(Box<Transaction>),
+}
+
+impl PendingTx {
+    pub fn hash(&self) -> H256 {
+        match self {
+            PendingTx::Hash(tx_hash) => *tx_hash,
+            PendingTx::Full(transaction) => transaction.hash,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_mode_falls_back_to_hashes() {
+        assert_eq!(
+            PendingTxMode::Full.subscription_params(),
+            vec![
+                json!(["newPendingTransactions", true]),
+                json!(["newPendingTransactions"])
+            ]
+        );
+        assert_eq!(
+            PendingTxMode::Hashes.subscription_params(),
+            vec![json!(["newPendingTransactions"])]
+        );
+    }
+
+    #[test]
+    fn notification_is_a_hash_or_a_full_tx() {
+        let tx_hash = H256::repeat_byte(0xab);
+        let pending: PendingTx = serde_json::from_value(json!(tx_hash)).unwrap();
+        assert!(matches!(pending, PendingTx::Hash(hash) if hash == tx_hash));
+
+        let transaction = Transaction {
+            hash: tx_hash,
+            ..Default::default()
+        };
+        let pending: PendingTx = serde_json::to_value(&transaction)
+            .and_then(serde_json::from_value)
+            .unwrap();
+        assert!(matches!(pending, PendingTx::Full(_)));
+        assert_eq!(pending.hash(), tx_hash);
+
+        assert!(serde_json::from_value::<PendingTx>(json!({"hash": 1})).is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use ethers::prelude::{Http, Middleware, Provider, ProviderError, Transaction};
+use ethers::types::H256;
+use rand::prelude::StdRng;
+use rand::Rng;
+
+use super::error::EnvSetUpError;
+
+/// consecutive failures after which an endpoint is taken out
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// seconds a failing endpoint stays out before it is tried again
+pub const DEFAULT_COOLDOWN_SECS: u64 = 30;
+
+/// how often the endpoint stats are logged
+const STATUS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// weight of the latest sample in the moving averages
+const EWMA_ALPHA: f64 = 0.2;
+
+/// latency assumed for endpoints without a sample yet
+const NEUTRAL_LATENCY: Duration = Duration::from_millis(200);
+
+/// lowest share of an endpoint weight kept by a bad error rate
+const MIN_SUCCESS_WEIGHT: f64 = 0.05;
+
+/// When an endpoint is taken out of the pool and for how long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolPolicy {
+    pub failure_threshold: u32,
+    pub cooldown: Duration,
+}
+
+impl Default for PoolPolicy {
+    fn default() -> Self {
+        PoolPolicy {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cooldown: Duration::from_secs(DEFAULT_COOLDOWN_SECS),
+        }
+    }
+}
+
+impl PoolPolicy {
+    pub fn validate(self) -> Result<PoolPolicy, EnvSetUpError> {
+        if self.failure_threshold == 0 {
+            return Err(EnvSetUpError::InvalidProviderPool(
+                "failure_threshold must be greater than 0".to_owned(),
+            ));
+        }
+        Ok(self)
+    }
+}
+
+/// result of one request to an endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Found(Duration),
+    /// endpoint answered without the tx, e.g. it did not see it yet
+    NotFound(Duration),
+    /// 429 or a rate limit json-rpc error
+    RateLimited,
+    Failed,
+}
+
+/// request stats and circuit breaker state of one endpoint
+#[derive(Debug, Default, Clone, Copy)]
+struct EndpointHealth {
+    requests: u64,
+    errors: u64,
+    rate_limited: u64,
+    not_found: u64,
+    /// moving averages of latency and error and not found rates
+    latency: Option<Duration>,
+    error_rate: f64,
+    not_found_rate: f64,
+    consecutive_failures: u32,
+    /// circuit breaker is open until then
+    open_until: Option<Instant>,
+}
+
+impl EndpointHealth {
+    fn record(&mut self, outcome: Outcome, policy: &PoolPolicy, now: Instant) {
+        self.requests += 1;
+        let (failed, not_found) = match outcome {
+            Outcome::Found(latency) => {
+                self.sample_latency(latency);
+                (false, false)
+            }
+            Outcome::NotFound(latency) => {
+                self.sample_latency(latency);
+                self.not_found += 1;
+                (false, true)
+            }
+            Outcome::RateLimited => {
+                self.errors += 1;
+                self.rate_limited += 1;
+                (true, false)
+            }
+            Outcome::Failed => {
+                self.errors += 1;
+                (true, false)
+            }
+        };
+        self.error_rate = ewma(self.error_rate, if failed { 1.0 } else { 0.0 });
+        self.not_found_rate = ewma(self.not_found_rate, if not_found { 1.0 } else { 0.0 });
+
+        if !failed {
+            self.consecutive_failures = 0;
+            self.open_until = None;
+            return;
+        }
+        // a failure after the cooldown opens the breaker again right away
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= policy.failure_threshold {
+            self.open_until = Some(now + policy.cooldown);
+        }
+    }
+
+    fn sample_latency(&mut self, latency: Duration) {
+        self.latency = Some(match self.latency {
+            Some(average) => {
+                Duration::from_secs_f64(ewma(average.as_secs_f64(), latency.as_secs_f64()))
+            }
+            None => latency,
+        });
+    }
+
+    fn is_open(&self, now: Instant) -> bool {
+        self.open_until.is_some_and(|until| until > now)
+    }
+
+    /// selection weight, fast endpoints with few errors and misses weigh the most
+    fn score(&self) -> f64 {
+        let latency_ms = self.latency.unwrap_or(NEUTRAL_LATENCY).as_secs_f64() * 1000.0;
+        let success = (1.0 - self.error_rate).max(MIN_SUCCESS_WEIGHT);
+        let found = 1.0 - self.not_found_rate / 2.0;
+        success * found * 1000.0 / (latency_ms + 50.0)
+    }
+}
+
+fn ewma(average: f64, sample: f64) -> f64 {
+    average + EWMA_ALPHA * (sample - average)
+}
+
+/// Stats of one endpoint, for logs and the `status` command
+#[derive(Debug, Clone)]
+pub struct EndpointStatus {
+    pub url: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub rate_limited: u64,
+    pub not_found: u64,
+    pub latency: Option<Duration>,
+    pub score: f64,
+    /// time left until the endpoint is tried again, None when it is in use
+    pub open_for: Option<Duration>,
+}
+
+impl fmt::Display for EndpointStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: score {:.2}, {} requests, {} errors, {} rate limited, {} not found",
+            self.url, self.score, self.requests, self.errors, self.rate_limited, self.not_found
+        )?;
+        if let Some(latency) = self.latency {
+            write!(f, ", latency {}ms", latency.as_millis())?;
+        }
+        match self.open_for {
+            Some(open_for) => write!(f, ", out for {}s", open_for.as_secs()),
+            None => f.write_str(", in use"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PoolEndpoint {
+    provider: Arc<Provider<Http>>,
+    health: Mutex<EndpointHealth>,
+}
+
+/** Http provider pool
+ * tracks latency, error, rate limit and not found rates of every endpoint and picks
+ * endpoints at random weighted by their score. An endpoint failing `failure_threshold`
+ * times in a row is taken out for `cooldown`, then gets one request to prove itself
+**/
+#[derive(Debug)]
+pub struct ProviderPool {
+    endpoints: Vec<PoolEndpoint>,
+    policy: PoolPolicy,
+}
+
+impl ProviderPool {
+    pub fn new(providers: &[Arc<Provider<Http>>], policy: PoolPolicy) -> Arc<ProviderPool> {
+        let pool = Arc::new(ProviderPool {
+            endpoints: providers
+                .iter()
+                .map(|provider| PoolEndpoint {
+                    provider: Arc::clone(provider),
+                    health: Mutex::new(EndpointHealth::default()),
+                })
+                .collect(),
+            policy,
+        });
+        spawn_status_logger(Arc::downgrade(&pool));
+        pool
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    /** Pick an endpoint index, weighted by score
+     * endpoints in `exclude` and endpoints taken out are skipped. When every endpoint
+     * is out, the one coming back first is picked. None when nothing is left
+     **/
+    pub fn pick(&self, random: &mut StdRng, exclude: &[usize]) -> Option<usize> {
+        let now = Instant::now();
+        let healths: Vec<(usize, EndpointHealth)> = (0..self.endpoints.len())
+            .filter(|index| !exclude.contains(index))
+            .map(|index| (index, self.health(index)))
+            .collect();
+
+        let available: Vec<(usize, f64)> = healths
+            .iter()
+            .filter(|(_, health)| !health.is_open(now))
+            .map(|(index, health)| (*index, health.score()))
+            .collect();
+        if available.is_empty() {
+            return healths
+                .iter()
+                .min_by_key(|(_, health)| health.open_until)
+                .map(|(index, _)| *index);
+        }
+
+        let total: f64 = available.iter().map(|(_, score)| score).sum();
+        let mut target = random.gen_range(0.0..total.max(f64::MIN_POSITIVE));
+        for (index, score) in available.iter() {
+            if target < *score {
+                return Some(*index);
+            }
+            target -= score;
+        }
+        available.last().map(|(index, _)| *index)
+    }
+
+    pub fn provider(&self, index: usize) -> Arc<Provider<Http>> {
+        Arc::clone(&self.endpoints[index].provider)
+    }
+
+    /// fetch tx from endpoint `index`, the outcome is recorded
+    pub async fn get_transaction(
+        &self,
+        index: usize,
+        tx_hash: H256,
+    ) -> Result<Option<Transaction>, ProviderError> {
+        let started = Instant::now();
+        let result = self.endpoints[index]
+            .provider
+            .get_transaction(tx_hash)
+            .await;
+        let outcome = match &result {
+            Ok(Some(_)) => Outcome::Found(started.elapsed()),
+            Ok(None) => Outcome::NotFound(started.elapsed()),
+            Err(err) if is_rate_limited(err) => Outcome::RateLimited,
+            Err(_) => Outcome::Failed,
+        };
+        self.record(index, outcome);
+        result
+    }
+
+    pub fn record(&self, index: usize, outcome: Outcome) {
+        let now = Instant::now();
+        let mut health = self.endpoints[index]
+            .health
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let (was_open, was_out) = (health.is_open(now), health.open_until.is_some());
+        health.record(outcome, &self.policy, now);
+        if health.is_open(now) && !was_open {
+            tracing::warn!(
+                "{} taken out for {}s after {} failures in a row",
+                self.endpoints[index].provider.url(),
+                self.policy.cooldown.as_secs(),
+                health.consecutive_failures
+            );
+        } else if was_out && health.open_until.is_none() {
+            tracing::info!("{} is back", self.endpoints[index].provider.url());
+        }
+    }
+
+    pub fn status(&self) -> Vec<EndpointStatus> {
+        let now = Instant::now();
+        self.endpoints
+            .iter()
+            .enumerate()
+            .map(|(index, endpoint)| {
+                let health = self.health(index);
+                EndpointStatus {
+                    url: endpoint.provider.url().to_string(),
+                    requests: health.requests,
+                    errors: health.errors,
+                    rate_limited: health.rate_limited,
+                    not_found: health.not_found,
+                    latency: health.latency,
+                    score: health.score(),
+                    open_for: health
+                        .open_until
+                        .filter(|until| *until > now)
+                        .map(|until| until - now),
+                }
+            })
+            .collect()
+    }
+
+    pub fn log_status(&self) {
+        for status in self.status() {
+            tracing::info!("http provider {}", status);
+        }
+    }
+
+    fn health(&self, index: usize) -> EndpointHealth {
+        *self.endpoints[index]
+            .health
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+/// log endpoint stats periodically while the pool is alive
+fn spawn_status_logger(pool: Weak<ProviderPool>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(STATUS_LOG_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match pool.upgrade() {
+                Some(pool) => pool.log_status(),
+                None => return,
+            }
+        }
+    });
+}
+
+/// 429 Too Many Requests or a json-rpc rate limit error
+pub fn is_rate_limited(err: &ProviderError) -> bool {
+    let message = err.to_string().to_lowercase();
+    message.contains("429")
+        || message.contains("too many requests")
+        || message.contains("rate limit")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use rand::SeedableRng;
+    use serde_json::Value;
+
+    use super::super::mock_rpc::{MockRpc, Reply};
+    use super::*;
+
+    fn policy() -> PoolPolicy {
+        PoolPolicy {
+            failure_threshold: 2,
+            cooldown: Duration::from_secs(10),
+        }
+    }
+
+    fn pool(urls: &[&str]) -> Arc<ProviderPool> {
+        let providers: Vec<Arc<Provider<Http>>> = urls
+            .iter()
+            .map(|url| Arc::new(Provider::<Http>::try_from(*url).unwrap()))
+            .collect();
+        ProviderPool::new(&providers, policy())
+    }
+
+    #[test]
+    fn failure_threshold_must_be_positive() {
+        let policy = PoolPolicy {
+            failure_threshold: 0,
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        assert!(PoolPolicy::default().validate().is_ok());
+    }
+
+    #[test]
+    fn failures_in_a_row_open_the_breaker_for_the_cooldown() {
+        let now = Instant::now();
+        let mut health = EndpointHealth::default();
+        health.record(Outcome::Failed, &policy(), now);
+        assert!(!health.is_open(now));
+
+        health.record(Outcome::RateLimited, &policy(), now);
+        assert!(health.is_open(now));
+        assert!(health.is_open(now + Duration::from_secs(9)));
+        assert!(!health.is_open(now + Duration::from_secs(10)));
+        assert_eq!((health.errors, health.rate_limited), (2, 1));
+
+        // one more failure after the cooldown takes it out again
+        let later = now + Duration::from_secs(11);
+        health.record(Outcome::Failed, &policy(), later);
+        assert!(health.is_open(later));
+    }
+
+    #[test]
+    fn answer_closes_the_breaker_and_resets_failures() {
+        let now = Instant::now();
+        let mut health = EndpointHealth::default();
+        health.record(Outcome::Failed, &policy(), now);
+        health.record(Outcome::Failed, &policy(), now);
+        health.record(Outcome::NotFound(Duration::from_millis(10)), &policy(), now);
+        assert!(health.open_until.is_none());
+        assert_eq!(health.consecutive_failures, 0);
+
+        health.record(Outcome::Failed, &policy(), now);
+        assert!(!health.is_open(now));
+    }
+
+    #[test]
+    fn score_favours_fast_endpoints_with_few_errors_and_misses() {
+        let now = Instant::now();
+        let record = |outcomes: &[Outcome]| {
+            let mut health = EndpointHealth::default();
+            for outcome in outcomes {
+                health.record(*outcome, &policy(), now);
+            }
+            health
+        };
+        let fast = record(&[Outcome::Found(Duration::from_millis(50))]);
+        let slow = record(&[Outcome::Found(Duration::from_millis(500))]);
+        let missing = record(&[Outcome::NotFound(Duration::from_millis(50))]);
+        let failing = record(&[Outcome::Found(Duration::from_millis(50)), Outcome::Failed]);
+        assert!(fast.score() > slow.score());
+        assert!(fast.score() > missing.score());
+        assert!(fast.score() > failing.score());
+        assert!(EndpointHealth::default().score() > slow.score());
+
+        // error rate never takes the weight to zero
+        let broken = record(&[Outcome::Failed; 50]);
+        assert!(broken.score() > 0.0);
+    }
+
+    #[test]
+    fn latency_is_a_moving_average() {
+        let now = Instant::now();
+        let mut health = EndpointHealth::default();
+        health.record(Outcome::Found(Duration::from_millis(100)), &policy(), now);
+        assert_eq!(health.latency, Some(Duration::from_millis(100)));
+        health.record(Outcome::Found(Duration::from_millis(200)), &policy(), now);
+        assert_eq!(health.latency.unwrap().as_millis(), 120);
+    }
+
+    #[tokio::test]
+    async fn pick_skips_excluded_and_taken_out_endpoints() {
+        let pool = pool(&[
+            "http://127.0.0.1:1",
+            "http://127.0.0.2:1",
+            "http://127.0.0.3:1",
+        ]);
+        let mut random = StdRng::seed_from_u64(7);
+        pool.record(1, Outcome::Failed);
+        pool.record(1, Outcome::Failed);
+        for _ in 0..50 {
+            assert_eq!(pool.pick(&mut random, &[0]), Some(2));
+            assert_ne!(pool.pick(&mut random, &[]), Some(1));
+        }
+        assert!(pool.status()[1].open_for.is_some());
+        assert_eq!(pool.pick(&mut random, &[0, 1, 2]), None);
+    }
+
+    #[tokio::test]
+    async fn pick_takes_the_endpoint_back_first_when_all_are_out() {
+        let pool = pool(&["http://127.0.0.1:1", "http://127.0.0.2:1"]);
+        let mut random = StdRng::seed_from_u64(7);
+        for index in [1, 0] {
+            pool.record(index, Outcome::Failed);
+            pool.record(index, Outcome::Failed);
+        }
+        assert_eq!(pool.pick(&mut random, &[]), Some(1));
+    }
+
+    #[tokio::test]
+    async fn pick_is_weighted_by_score() {
+        let pool = pool(&["http://127.0.0.1:1", "http://127.0.0.2:1"]);
+        let mut random = StdRng::seed_from_u64(7);
+        pool.record(0, Outcome::Found(Duration::from_millis(20)));
+        pool.record(1, Outcome::Found(Duration::from_millis(950)));
+        let picks = (0..1000)
+            .filter(|_| pool.pick(&mut random, &[]) == Some(0))
+            .count();
+        assert!(picks > 850, "fast endpoint picked {} times", picks);
+    }
+
+    #[tokio::test]
+    async fn fetch_outcomes_are_recorded() {
+        let rpc = MockRpc::start(|_, params: &Value| {
+            match params[0].as_str().unwrap_or_default().as_bytes()[65] {
+                b'1' => Reply::Result(Value::Null),
+                b'2' => Reply::Error(-32005, "rate limit exceeded".to_owned()),
+                _ => Reply::Error(-32000, "header not found".to_owned()),
+            }
+        })
+        .await;
+        let pool = pool(&[rpc.url()]);
+        for hash in [1u64, 2, 3] {
+            let _ = pool.get_transaction(0, H256::from_low_u64_be(hash)).await;
+        }
+        let status = &pool.status()[0];
+        assert_eq!(
+            (
+                status.requests,
+                status.not_found,
+                status.errors,
+                status.rate_limited
+            ),
+            (3, 1, 2, 1)
+        );
+        assert!(status.latency.is_some());
+        assert_eq!(rpc.requests("eth_getTransactionByHash").len(), 3);
+    }
+}
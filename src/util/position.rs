@@ -0,0 +1,510 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ethers::prelude::{Address, TransactionReceipt, H256, U256};
+use ethers::utils::keccak256;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::contract::cake_router::CakeRouter;
+
+use super::error::{BlockBotError, EnvSetUpError};
+use super::gas::{TxGas, TxType};
+use super::trade::TradeSettings;
+
+/// price poll interval used when none is configured
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 3000;
+
+/// consecutive failed quotes or sells after which the position is abandoned
+const MAX_FAILURES: u32 = 5;
+
+/// gas limit for sells when the buy receipt has no gas used
+const FALLBACK_SELL_GAS: u64 = 500_000;
+
+/// fixed point precision of value ratios
+const RATIO_PRECISION: u64 = 1_000_000;
+
+/// sell `sell_pct` percent of the remaining position once value reaches `multiple` x entry cost
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TakeProfit {
+    pub multiple: f64,
+    pub sell_pct: u8,
+}
+
+/// Per target exit rules, every rule is optional
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ExitRules {
+    /// take profit levels, each one fires once
+    #[serde(default)]
+    pub take_profit: Vec<TakeProfit>,
+    /// sell everything when value drops this percent below entry cost
+    pub stop_loss_pct: Option<f64>,
+    /// sell everything when value drops this percent below its peak
+    pub trailing_stop_pct: Option<f64>,
+    /// sell everything after holding this long
+    pub max_hold_secs: Option<u64>,
+    pub poll_interval_ms: Option<u64>,
+}
+
+impl ExitRules {
+    /// validate rules and order take profit levels by multiple
+    pub fn validate(mut self) -> Result<ExitRules, EnvSetUpError> {
+        for level in self.take_profit.iter() {
+            if !level.multiple.is_finite() || level.multiple <= 0.0 {
+                return Err(EnvSetUpError::InvalidExitRule(format!(
+                    "take profit multiple {} must be greater than 0",
+                    level.multiple
+                )));
+            }
+            if level.sell_pct == 0 || level.sell_pct > 100 {
+                return Err(EnvSetUpError::InvalidExitRule(format!(
+                    "take profit sell_pct {} must be between 1 and 100",
+                    level.sell_pct
+                )));
+            }
+        }
+        self.take_profit
+            .sort_by(|a, b| a.multiple.total_cmp(&b.multiple));
+
+        for (name, pct) in [
+            ("stop_loss_pct", self.stop_loss_pct),
+            ("trailing_stop_pct", self.trailing_stop_pct),
+        ]
+        .iter()
+        {
+            if let Some(pct) = pct {
+                if !pct.is_finite() || *pct <= 0.0 || *pct >= 100.0 {
+                    return Err(EnvSetUpError::InvalidExitRule(format!(
+                        "{} {} must be between 0 and 100",
+                        name, pct
+                    )));
+                }
+            }
+        }
+
+        if self.poll_interval_ms == Some(0) {
+            return Err(EnvSetUpError::InvalidExitRule(
+                "poll_interval_ms must be greater than 0".to_owned(),
+            ));
+        }
+        Ok(self)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS))
+    }
+}
+
+/// Why a sell was triggered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// index of the take profit level
+    TakeProfit(usize),
+    StopLoss,
+    TrailingStop,
+    MaxHold,
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitReason::TakeProfit(level) => write!(f, "take profit #{}", level + 1),
+            ExitReason::StopLoss => f.write_str("stop loss"),
+            ExitReason::TrailingStop => f.write_str("trailing stop"),
+            ExitReason::MaxHold => f.write_str("max hold time"),
+        }
+    }
+}
+
+/// Open token position created by a successful buy
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub token: Address,
+    /// native token spent on the buy
+    pub entry_cost: U256,
+    /// tokens received by the buy
+    pub initial_amount: U256,
+    /// tokens still held
+    pub remaining: U256,
+    pub opened_at: Instant,
+    /// highest value / cost ratio seen so far
+    peak_ratio: f64,
+    /// next take profit level to fire
+    next_take_profit: usize,
+}
+
+impl Position {
+    pub fn new(token: Address, entry_cost: U256, amount: U256) -> Position {
+        Position {
+            token,
+            entry_cost,
+            initial_amount: amount,
+            remaining: amount,
+            opened_at: Instant::now(),
+            peak_ratio: 0.0,
+            next_take_profit: 0,
+        }
+    }
+
+    /// open a position from buy receipt, bought amount is read from token transfer logs
+    pub fn from_receipt(
+        token: Address,
+        wallet: Address,
+        entry_cost: U256,
+        receipt: &TransactionReceipt,
+    ) -> Option<Position> {
+        let transfer_topic = H256::from(keccak256("Transfer(address,address,uint256)"));
+        let wallet_topic = H256::from(wallet);
+
+        let amount = receipt
+            .logs
+            .iter()
+            .filter(|log| {
+                log.address == token
+                    && log.topics.len() == 3
+                    && log.topics[0] == transfer_topic
+                    && log.topics[2] == wallet_topic
+            })
+            .fold(U256::zero(), |total, log| {
+                total.saturating_add(U256::from_big_endian(&log.data))
+            });
+
+        if amount.is_zero() {
+            None
+        } else {
+            Some(Position::new(token, entry_cost, amount))
+        }
+    }
+
+    /// entry cost of the tokens still held
+    pub fn remaining_cost(&self) -> U256 {
+        if self.initial_amount.is_zero() {
+            return U256::zero();
+        }
+        self.entry_cost.saturating_mul(self.remaining) / self.initial_amount
+    }
+
+    /// check exit rules against current value of the remaining tokens
+    /// returns the triggered rule and the token amount to sell
+    pub fn evaluate(&mut self, value: U256, rules: &ExitRules) -> Option<(ExitReason, U256)> {
+        let ratio = value_ratio(value, self.remaining_cost());
+        if ratio > self.peak_ratio {
+            self.peak_ratio = ratio;
+        }
+
+        if self.max_hold_reached(rules) {
+            return Some((ExitReason::MaxHold, self.remaining));
+        }
+
+        if let Some(stop_loss_pct) = rules.stop_loss_pct {
+            if ratio <= 1.0 - stop_loss_pct / 100.0 {
+                return Some((ExitReason::StopLoss, self.remaining));
+            }
+        }
+
+        if let Some(trailing_stop_pct) = rules.trailing_stop_pct {
+            if ratio <= self.peak_ratio * (1.0 - trailing_stop_pct / 100.0) {
+                return Some((ExitReason::TrailingStop, self.remaining));
+            }
+        }
+
+        if let Some(level) = rules.take_profit.get(self.next_take_profit) {
+            if ratio >= level.multiple {
+                let amount = if level.sell_pct >= 100 {
+                    self.remaining
+                } else {
+                    self.remaining.saturating_mul(U256::from(level.sell_pct)) / U256::from(100u8)
+                };
+                return Some((ExitReason::TakeProfit(self.next_take_profit), amount));
+            }
+        }
+        None
+    }
+
+    /// position was held for `max_hold_secs`, no quote is needed to tell
+    pub fn max_hold_reached(&self, rules: &ExitRules) -> bool {
+        rules.max_hold_secs.is_some_and(|max_hold_secs| {
+            self.opened_at.elapsed() >= Duration::from_secs(max_hold_secs)
+        })
+    }
+
+    /// book a confirmed sell
+    pub fn record_sell(&mut self, reason: ExitReason, amount: U256) {
+        self.remaining = self.remaining.saturating_sub(amount);
+        if let ExitReason::TakeProfit(level) = reason {
+            self.next_take_profit = level + 1;
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.remaining.is_zero()
+    }
+}
+
+/// value / cost as float, 0 when cost is 0
+fn value_ratio(value: U256, cost: U256) -> f64 {
+    if cost.is_zero() {
+        return 0.0;
+    }
+    let scaled = value.saturating_mul(U256::from(RATIO_PRECISION)) / cost;
+    if scaled > U256::from(u64::MAX) {
+        f64::MAX
+    } else {
+        scaled.as_u64() as f64 / RATIO_PRECISION as f64
+    }
+}
+
+/** Position manager
+ * polls `getAmountsOut` for the position value and sells on exit rules
+**/
+#[derive(Debug)]
+pub struct PositionManager {
+    router: Arc<CakeRouter>,
+    native: Address,
+    trade: TradeSettings,
+    rules: ExitRules,
+    sell_gas: U256,
+    tx_type: TxType,
+}
+
+impl PositionManager {
+    /// `buy_gas_used` sizes the sell gas limit
+    pub fn new(
+        router: Arc<CakeRouter>,
+        native: Address,
+        trade: TradeSettings,
+        rules: ExitRules,
+        buy_gas_used: Option<U256>,
+        tx_type: TxType,
+    ) -> PositionManager {
+        let sell_gas = trade.gas_limit(buy_gas_used.unwrap_or_else(|| FALLBACK_SELL_GAS.into()));
+        PositionManager {
+            router,
+            native,
+            trade,
+            rules,
+            sell_gas,
+            tx_type,
+        }
+    }
+
+    #[instrument(skip(self), fields(token = ?position.token))]
+    pub async fn run(self, mut position: Position) {
+        tracing::info!(
+            "opened position of {} tokens for {} native",
+            position.initial_amount,
+            ethers::utils::format_ether(position.entry_cost)
+        );
+
+        let mut failures = 0u32;
+        let mut interval = tokio::time::interval(self.rules.poll_interval());
+
+        while !position.is_closed() {
+            interval.tick().await;
+
+            // a pulled pair fails every quote, max hold must not depend on it
+            let exit = if position.max_hold_reached(&self.rules) {
+                Some((ExitReason::MaxHold, position.remaining))
+            } else {
+                match self
+                    .router
+                    .get_amounts_out(position.remaining, position.token, self.native)
+                    .await
+                {
+                    Ok(value) => {
+                        let exit = position.evaluate(value, &self.rules);
+                        match exit {
+                            Some((reason, _)) => tracing::info!(
+                                "{} triggered at value {} native",
+                                reason,
+                                ethers::utils::format_ether(value)
+                            ),
+                            None => failures = 0,
+                        }
+                        exit
+                    }
+                    Err(err) => {
+                        tracing::warn!("unable to fetch position value: {}", err);
+                        failures += 1;
+                        None
+                    }
+                }
+            };
+
+            if let Some((reason, amount)) = exit {
+                tracing::info!("{}, selling {} tokens", reason, amount);
+                match self.sell(position.token, amount).await {
+                    Ok(true) => {
+                        failures = 0;
+                        position.record_sell(reason, amount);
+                        tracing::info!("sold {} tokens, {} left", amount, position.remaining);
+                    }
+                    Ok(false) => failures += 1,
+                    Err(err) => {
+                        tracing::error!("sell failed: {}", err);
+                        failures += 1;
+                    }
+                }
+            }
+
+            if failures >= MAX_FAILURES {
+                tracing::error!(
+                    "giving up position after {} failed quotes or sells, {} tokens left",
+                    failures,
+                    position.remaining
+                );
+                return;
+            }
+        }
+        tracing::info!("position closed");
+    }
+
+    /// true when sell tx is confirmed successfully
+    async fn sell(&self, token: Address, amount: U256) -> Result<bool, BlockBotError> {
+        let tx_gas = TxGas {
+            gas: self.sell_gas,
+            fees: self
+                .trade
+                .gas_fees(self.router.current_fees(self.tx_type).await?),
+        };
+        let receipt = self
+            .router
+            .sell_tokens(&self.trade, amount, self.native, token, tx_gas)
+            .await?;
+        Ok(receipt
+            .map(|receipt| receipt.status == Some(1u64.into()))
+            .unwrap_or(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(toml: &str) -> ExitRules {
+        toml::from_str::<ExitRules>(toml)
+            .unwrap()
+            .validate()
+            .unwrap()
+    }
+
+    /// position of 1000 tokens bought for 1000 wei
+    fn position() -> Position {
+        Position::new(Address::zero(), U256::from(1000u64), U256::from(1000u64))
+    }
+
+    #[test]
+    fn take_profit_levels_sell_in_order() {
+        let rules = rules(
+            "take_profit = [{ multiple = 3.0, sell_pct = 100 }, { multiple = 2.0, sell_pct = 50 }]",
+        );
+        let mut position = position();
+
+        assert_eq!(position.evaluate(U256::from(1500u64), &rules), None);
+        let exit = position.evaluate(U256::from(2000u64), &rules);
+        assert_eq!(exit, Some((ExitReason::TakeProfit(0), U256::from(500u64))));
+        position.record_sell(ExitReason::TakeProfit(0), U256::from(500u64));
+
+        // value of the 500 tokens left, the first level is not hit again
+        assert_eq!(position.evaluate(U256::from(1000u64), &rules), None);
+        let exit = position.evaluate(U256::from(1500u64), &rules);
+        assert_eq!(exit, Some((ExitReason::TakeProfit(1), U256::from(500u64))));
+        position.record_sell(ExitReason::TakeProfit(1), U256::from(500u64));
+        assert!(position.is_closed());
+    }
+
+    #[test]
+    fn stop_loss_and_trailing_stop_sell_everything() {
+        let rules = rules("stop_loss_pct = 50.0\ntrailing_stop_pct = 20.0");
+        let mut position = position();
+
+        assert_eq!(position.evaluate(U256::from(600u64), &rules), None);
+        assert_eq!(
+            position.evaluate(U256::from(500u64), &rules),
+            Some((ExitReason::StopLoss, U256::from(1000u64)))
+        );
+
+        let mut position = self::position();
+        assert_eq!(position.evaluate(U256::from(3000u64), &rules), None);
+        assert_eq!(position.evaluate(U256::from(2500u64), &rules), None);
+        assert_eq!(
+            position.evaluate(U256::from(2400u64), &rules),
+            Some((ExitReason::TrailingStop, U256::from(1000u64)))
+        );
+    }
+
+    #[test]
+    fn max_hold_needs_no_quote() {
+        let rules = rules("max_hold_secs = 60\nstop_loss_pct = 50.0");
+        let mut position = position();
+        assert!(!position.max_hold_reached(&rules));
+        assert_eq!(position.evaluate(U256::from(1000u64), &rules), None);
+
+        position.opened_at = Instant::now() - Duration::from_secs(61);
+        assert!(position.max_hold_reached(&rules));
+        assert_eq!(
+            position.evaluate(U256::from(1000u64), &rules),
+            Some((ExitReason::MaxHold, U256::from(1000u64)))
+        );
+    }
+
+    #[test]
+    fn remaining_cost_follows_sells() {
+        let mut position = position();
+        position.record_sell(ExitReason::StopLoss, U256::from(250u64));
+        assert_eq!(position.remaining_cost(), U256::from(750u64));
+        assert_eq!(value_ratio(U256::from(1500u64), U256::from(750u64)), 2.0);
+        assert_eq!(value_ratio(U256::from(1500u64), U256::zero()), 0.0);
+    }
+
+    #[test]
+    fn position_amount_is_read_from_transfers_to_wallet() {
+        let token = Address::repeat_byte(1);
+        let wallet = Address::repeat_byte(2);
+        let transfer = |address: Address, to: Address, amount: u64| ethers::prelude::Log {
+            address,
+            topics: vec![
+                H256::from(keccak256("Transfer(address,address,uint256)")),
+                H256::from(Address::repeat_byte(3)),
+                H256::from(to),
+            ],
+            data: ethers::abi::encode(&[ethers::abi::Token::Uint(amount.into())]).into(),
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            logs: vec![
+                transfer(token, wallet, 700),
+                transfer(token, wallet, 300),
+                transfer(token, Address::repeat_byte(4), 50),
+                transfer(Address::repeat_byte(5), wallet, 50),
+            ],
+            ..Default::default()
+        };
+
+        let position = Position::from_receipt(token, wallet, U256::one(), &receipt).unwrap();
+        assert_eq!(position.remaining, U256::from(1000u64));
+        assert!(Position::from_receipt(wallet, wallet, U256::one(), &receipt).is_none());
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for toml in [
+            "take_profit = [{ multiple = 0.0, sell_pct = 50 }]",
+            "take_profit = [{ multiple = 2.0, sell_pct = 0 }]",
+            "take_profit = [{ multiple = 2.0, sell_pct = 101 }]",
+            "stop_loss_pct = 100.0",
+            "trailing_stop_pct = 0.0",
+            "poll_interval_ms = 0",
+        ] {
+            let rules: ExitRules = toml::from_str(toml).unwrap();
+            assert!(
+                matches!(rules.validate(), Err(EnvSetUpError::InvalidExitRule(_))),
+                "{}",
+                toml
+            );
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::fmt;
+use std::time::Duration;
+
+use ethers::prelude::ProviderError;
+use rand::prelude::StdRng;
+use rand::Rng;
+
+use super::error::EnvSetUpError;
+use super::pool::is_rate_limited;
+
+/// fetch rounds before a tx is given up
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+
+/// wait before the second round, doubled after every round
+pub const DEFAULT_RETRY_BACKOFF_MS: u64 = 100;
+
+/// upper bound of the wait between rounds
+pub const DEFAULT_MAX_RETRY_BACKOFF_MS: u64 = 1000;
+
+/// time a tx fetch may take in total, retries included
+pub const DEFAULT_FETCH_DEADLINE_MS: u64 = 3000;
+
+/// json-rpc codes of requests no node will answer, see EIP-1474
+const INVALID_REQUEST_CODES: [i64; 3] = [-32600, -32601, -32602];
+
+/// How often and how long a tx fetch is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// fetch is given up after this, whatever attempts are left
+    pub deadline: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            backoff: Duration::from_millis(DEFAULT_RETRY_BACKOFF_MS),
+            max_backoff: Duration::from_millis(DEFAULT_MAX_RETRY_BACKOFF_MS),
+            deadline: Duration::from_millis(DEFAULT_FETCH_DEADLINE_MS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(self) -> Result<RetryPolicy, EnvSetUpError> {
+        if self.max_attempts == 0 {
+            return Err(EnvSetUpError::InvalidRetrySetting(
+                "max_attempts must be greater than 0".to_owned(),
+            ));
+        }
+        if self.max_backoff < self.backoff {
+            return Err(EnvSetUpError::InvalidRetrySetting(
+                "max_backoff_ms must not be below backoff_ms".to_owned(),
+            ));
+        }
+        if self.deadline.is_zero() {
+            return Err(EnvSetUpError::InvalidRetrySetting(
+                "deadline_ms must be greater than 0".to_owned(),
+            ));
+        }
+        Ok(self)
+    }
+
+    /// wait before round `attempt` + 1, the doubled backoff with its upper half jittered
+    pub fn delay(&self, attempt: u32, random: &mut StdRng) -> Duration {
+        let doubled = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let half = doubled / 2;
+        half + half.mul_f64(random.gen_range(0.0..=1.0))
+    }
+}
+
+/// Whether another request can succeed where one failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// rate limits, transport errors and node side failures
+    Retryable,
+    /// invalid requests and responses no node will answer differently
+    Fatal,
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorClass::Retryable => f.write_str("retryable"),
+            ErrorClass::Fatal => f.write_str("fatal"),
+        }
+    }
+}
+
+/** Classify a provider error
+ * rate limits, http errors, json-rpc server errors and unparsable responses, e.g. a
+ * gateway error page, are retryable. Invalid json-rpc requests, txs that do not decode
+ * and errors unrelated to the transport are fatal
+**/
+pub fn classify(err: &ProviderError) -> ErrorClass {
+    if is_rate_limited(err) {
+        return ErrorClass::Retryable;
+    }
+    match err {
+        ProviderError::JsonRpcClientError(rpc_err) => match rpc_err.as_error_response() {
+            Some(response) if INVALID_REQUEST_CODES.contains(&response.code) => ErrorClass::Fatal,
+            _ => ErrorClass::Retryable,
+        },
+        ProviderError::HTTPError(_) => ErrorClass::Retryable,
+        _ => ErrorClass::Fatal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ethers::prelude::{Http, Middleware, Provider};
+    use rand::SeedableRng;
+    use serde_json::{json, Value};
+
+    use super::super::mock_rpc::{MockRpc, Reply};
+    use super::*;
+
+    const ZERO_HASH: &str = "0x0000000000000000000000000000000000000000000000000000000000000000";
+
+    /// error of a request answered with json-rpc error `code`
+    async fn rpc_error(code: i64, message: &str) -> ProviderError {
+        let message = message.to_owned();
+        let rpc = MockRpc::start(move |_, _| Reply::Error(code, message.clone())).await;
+        rpc.provider()
+            .request::<_, Value>("eth_getTransactionByHash", json!([ZERO_HASH]))
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_fatal() {
+        for code in INVALID_REQUEST_CODES {
+            let err = rpc_error(code, "invalid params").await;
+            assert_eq!(classify(&err), ErrorClass::Fatal, "{}", err);
+        }
+        let err = ProviderError::CustomError("unrelated".to_owned());
+        assert_eq!(classify(&err), ErrorClass::Fatal);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_and_server_errors_are_retryable() {
+        for (code, message) in [
+            (-32005, "rate limit exceeded"),
+            (-32602, "too many requests"),
+            (-32000, "header not found"),
+            (-32603, "internal error"),
+        ] {
+            let err = rpc_error(code, message).await;
+            assert_eq!(classify(&err), ErrorClass::Retryable, "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn transport_errors_are_retryable() {
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let err = provider.get_block_number().await.unwrap_err();
+        assert_eq!(classify(&err), ErrorClass::Retryable, "{}", err);
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_max_backoff() {
+        let policy = RetryPolicy::default();
+        let mut random = StdRng::seed_from_u64(7);
+        for (attempt, low, high) in [
+            (1, 50, 100),
+            (2, 100, 200),
+            (3, 200, 400),
+            (4, 400, 800),
+            (5, 500, 1000),
+            (40, 500, 1000),
+        ] {
+            for _ in 0..100 {
+                let delay = policy.delay(attempt, &mut random).as_millis();
+                assert!(
+                    (low..=high).contains(&delay),
+                    "attempt {} waited {}ms",
+                    attempt,
+                    delay
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn delay_is_jittered() {
+        let policy = RetryPolicy::default();
+        let mut random = StdRng::seed_from_u64(7);
+        let delays: Vec<Duration> = (0..10).map(|_| policy.delay(3, &mut random)).collect();
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[test]
+    fn invalid_policies_are_rejected() {
+        let policy = RetryPolicy::default();
+        assert!(policy.validate().is_ok());
+        for invalid in [
+            RetryPolicy {
+                max_attempts: 0,
+                ..policy
+            },
+            RetryPolicy {
+                max_backoff: policy.backoff / 2,
+                ..policy
+            },
+            RetryPolicy {
+                deadline: Duration::ZERO,
+                ..policy
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use std::fmt;
+use std::future::Future;
+
+use ethers::abi::{self, Token};
+use ethers::prelude::{Address, H256, U256};
+use ethers::types::spoof;
+use ethers::utils::keccak256;
+use futures::future::join_all;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::contract::bep20::Bep20Token;
+use crate::contract::cake_router::CakeRouter;
+
+use super::error::EnvSetUpError;
+use super::trade::{TradeSettings, MAX_BPS};
+
+/// simulated swaps sent at once per round of the tax search
+const TAX_PROBES: u16 = 8;
+
+/** Pre-trade safety limits
+ * buy is refused when the simulated round trip exceeds one of them
+**/
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SafetyLimits {
+    /// max effective buy tax in basis points
+    pub max_buy_tax_bps: Option<u16>,
+    /// max effective sell tax in basis points
+    pub max_sell_tax_bps: Option<u16>,
+    /// max native token lost by buying and selling right away, in basis points
+    pub max_round_trip_loss_bps: Option<u16>,
+    /// buy even when the round trip can not be simulated e.g. no liquidity in pending block yet
+    #[serde(default)]
+    pub allow_unverified: bool,
+}
+
+impl SafetyLimits {
+    pub fn validate(self) -> Result<SafetyLimits, EnvSetUpError> {
+        for (name, bps) in [
+            ("max_buy_tax_bps", self.max_buy_tax_bps),
+            ("max_sell_tax_bps", self.max_sell_tax_bps),
+            ("max_round_trip_loss_bps", self.max_round_trip_loss_bps),
+        ]
+        .iter()
+        {
+            if let Some(bps) = bps.filter(|bps| *bps > MAX_BPS) {
+                return Err(EnvSetUpError::InvalidSafetyLimit(name, bps));
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// storage slots of the token `balanceOf` and `allowance` mappings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenSlots {
+    pub balance: U256,
+    pub allowance: U256,
+}
+
+/// Effective taxes of a simulated buy followed by a sell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundTripReport {
+    pub buy_tax_bps: u16,
+    pub sell_tax_bps: u16,
+    pub round_trip_loss_bps: u16,
+}
+
+impl fmt::Display for RoundTripReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "buy tax: {} bps, sell tax: {} bps, round trip loss: {} bps",
+            self.buy_tax_bps, self.sell_tax_bps, self.round_trip_loss_bps
+        )
+    }
+}
+
+/// Outcome of the safety check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SafetyVerdict {
+    Safe(RoundTripReport),
+    /// limits exceeded or the token can not be sold
+    Unsafe(String),
+    /// round trip could not be simulated
+    Unverified(String),
+    /// router has no buy quote, liquidity add may not be in the pending block yet
+    NoLiquidity(String),
+}
+
+/// What to do with the target after its safety check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafetyDecision {
+    Buy,
+    Refuse,
+    /// put the buy off, a later detection checks the target again
+    Recheck,
+}
+
+impl fmt::Display for SafetyVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafetyVerdict::Safe(report) => write!(f, "safe, {}", report),
+            SafetyVerdict::Unsafe(reason) => write!(f, "unsafe, {}", reason),
+            SafetyVerdict::Unverified(reason) => write!(f, "unverified, {}", reason),
+            SafetyVerdict::NoLiquidity(reason) => write!(f, "no liquidity, {}", reason),
+        }
+    }
+}
+
+/** Honeypot / sellability check of one target
+ * buy and sell are simulated with `eth_call` against the pending block,
+ * for the sell the wallet token balance and router allowance are overridden.
+ * Effective taxes are found by searching the highest `amountOutMin` the
+ * fee-on-transfer swap methods accept compared to `getAmountsOut`.
+ * A check costs two quotes and about five rounds of parallel `eth_call`s per side,
+ * roughly a dozen node round trips ahead of the buy.
+**/
+#[derive(Debug, Clone)]
+pub struct SafetyCheck {
+    limits: SafetyLimits,
+    /// None when the token storage layout is unknown, sells can not be simulated then
+    slots: Option<TokenSlots>,
+}
+
+impl SafetyCheck {
+    /// locate token storage slots, token contract must already be deployed
+    #[instrument(skip(token))]
+    pub async fn prepare(
+        token: &Bep20Token,
+        wallet: Address,
+        router: Address,
+        limits: SafetyLimits,
+    ) -> SafetyCheck {
+        let balance = token.find_balance_slot(wallet).await;
+        let allowance = token.find_allowance_slot(wallet, router).await;
+
+        let slots = match (balance, allowance) {
+            (Ok(Some(balance)), Ok(Some(allowance))) => Some(TokenSlots { balance, allowance }),
+            (balance, allowance) => {
+                tracing::warn!(
+                    "storage slots of {:?} not found (balance: {:?}, allowance: {:?}), sells can not be simulated",
+                    token.get_token_address(),
+                    balance,
+                    allowance
+                );
+                None
+            }
+        };
+        SafetyCheck { limits, slots }
+    }
+
+    /// whether the buy may go ahead with this verdict
+    pub fn decide(&self, verdict: &SafetyVerdict) -> SafetyDecision {
+        match verdict {
+            SafetyVerdict::Safe(_) => SafetyDecision::Buy,
+            SafetyVerdict::Unsafe(_) => SafetyDecision::Refuse,
+            SafetyVerdict::Unverified(_) | SafetyVerdict::NoLiquidity(_)
+                if self.limits.allow_unverified =>
+            {
+                SafetyDecision::Buy
+            }
+            SafetyVerdict::Unverified(_) => SafetyDecision::Refuse,
+            SafetyVerdict::NoLiquidity(_) => SafetyDecision::Recheck,
+        }
+    }
+
+    /// simulate buying `trade.buy_amount` of token and selling it right away
+    #[instrument(skip(self, router, trade))]
+    pub async fn run(
+        &self,
+        router: &CakeRouter,
+        trade: &TradeSettings,
+        native: Address,
+        token: Address,
+    ) -> SafetyVerdict {
+        let slots = match self.slots {
+            Some(slots) => slots,
+            None => return SafetyVerdict::Unverified("token storage layout unknown".to_owned()),
+        };
+
+        // buy side
+        let buy_quote = match router
+            .get_amounts_out(trade.buy_amount, native, token)
+            .await
+        {
+            Ok(quote) if !quote.is_zero() => quote,
+            Ok(_) => return SafetyVerdict::NoLiquidity("zero buy quote".to_owned()),
+            Err(err) => return SafetyVerdict::NoLiquidity(format!("no buy quote: {}", err)),
+        };
+        let buy_tax_bps = match find_tax(buy_quote, |min_out| {
+            router.simulate_buy_min_out(trade.buy_amount, native, token, min_out)
+        })
+        .await
+        {
+            Some(tax_bps) => tax_bps,
+            None => return SafetyVerdict::Unsafe("buy reverts".to_owned()),
+        };
+
+        // sell side, bought amount is credited to the wallet with state overrides
+        let bought = deduct_bps(buy_quote, buy_tax_bps);
+        if bought.is_zero() {
+            return SafetyVerdict::Unsafe("buy yields no tokens".to_owned());
+        }
+        let sell_quote = match router.get_amounts_out(bought, token, native).await {
+            Ok(quote) if !quote.is_zero() => quote,
+            Ok(_) => return SafetyVerdict::Unsafe("zero sell quote".to_owned()),
+            Err(err) => return SafetyVerdict::Unverified(format!("no sell quote: {}", err)),
+        };
+        let state = sell_state(
+            token,
+            router.wallet_address(),
+            router.address(),
+            slots,
+            bought,
+        );
+        let sell_tax_bps = match find_tax(sell_quote, |min_out| {
+            router.simulate_sell_min_out(bought, native, token, min_out, &state)
+        })
+        .await
+        {
+            Some(tax_bps) => tax_bps,
+            None => return SafetyVerdict::Unsafe("token can not be sold".to_owned()),
+        };
+
+        let returned = deduct_bps(sell_quote, sell_tax_bps);
+        let lost = trade.buy_amount.saturating_sub(returned);
+        let round_trip_loss_bps =
+            (lost.saturating_mul(U256::from(MAX_BPS)) / trade.buy_amount).as_u32() as u16;
+
+        let report = RoundTripReport {
+            buy_tax_bps,
+            sell_tax_bps,
+            round_trip_loss_bps,
+        };
+        tracing::info!("round trip of {:?}: {}", token, report);
+
+        match self.exceeded_limit(&report) {
+            Some(reason) => SafetyVerdict::Unsafe(format!("{} ({})", reason, report)),
+            None => SafetyVerdict::Safe(report),
+        }
+    }
+
+    fn exceeded_limit(&self, report: &RoundTripReport) -> Option<&'static str> {
+        let exceeds = |value: u16, limit: Option<u16>| limit.is_some_and(|limit| value > limit);
+        if exceeds(report.buy_tax_bps, self.limits.max_buy_tax_bps) {
+            Some("buy tax over limit")
+        } else if exceeds(report.sell_tax_bps, self.limits.max_sell_tax_bps) {
+            Some("sell tax over limit")
+        } else if exceeds(
+            report.round_trip_loss_bps,
+            self.limits.max_round_trip_loss_bps,
+        ) {
+            Some("round trip loss over limit")
+        } else {
+            None
+        }
+    }
+}
+
+/** Smallest tax accepted by a simulated swap
+ * `passes(min_out)` tells whether the swap succeeds with given `amountOutMin`,
+ * tax is searched in basis points of `quote`. None when the swap never passes.
+ * Each round probes [`TAX_PROBES`] taxes at once and narrows the range to one
+ * of the gaps between them, 5 rounds cover the 10000 bps
+**/
+async fn find_tax<F, Fut>(quote: U256, passes: F) -> Option<u16>
+where
+    F: Fn(U256) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    // passes(tax) is monotonic, higher tax means lower min out.
+    // zero min out (full tax) is probed along with the first round
+    let (mut low, mut high) = (0u16, MAX_BPS);
+    let mut first_round = true;
+    while low < high {
+        let mut probes: Vec<u16> = (1..=TAX_PROBES)
+            .map(|i| low + ((high - low) as u32 * i as u32 / (TAX_PROBES as u32 + 1)) as u16)
+            .collect();
+        probes.dedup();
+
+        let round = join_all(probes.iter().map(|tax| passes(deduct_bps(quote, *tax))));
+        let results = if first_round {
+            let (passes_zero, results) = futures::join!(passes(U256::zero()), round);
+            if !passes_zero {
+                return None;
+            }
+            first_round = false;
+            results
+        } else {
+            round.await
+        };
+
+        match results.iter().position(|passed| *passed) {
+            Some(first) => {
+                high = probes[first];
+                if first > 0 {
+                    low = probes[first - 1] + 1;
+                }
+            }
+            None => low = probes[probes.len() - 1] + 1,
+        }
+    }
+    Some(low)
+}
+
+/// reduce `amount` by `bps` basis points
+fn deduct_bps(amount: U256, bps: u16) -> U256 {
+    amount.saturating_mul(U256::from(MAX_BPS.saturating_sub(bps))) / U256::from(MAX_BPS)
+}
+
+/// state override giving `wallet` `amount` tokens approved to `router`
+fn sell_state(
+    token: Address,
+    wallet: Address,
+    router: Address,
+    slots: TokenSlots,
+    amount: U256,
+) -> spoof::State {
+    let value = u256_to_h256(amount);
+    let mut state = spoof::state();
+    state
+        .account(token)
+        .store(balance_key(slots.balance, wallet), value)
+        .store(allowance_key(slots.allowance, wallet, router), value);
+    state
+}
+
+/// storage key of `holder` in a solidity `mapping(address => uint256)` at `slot`
+pub fn balance_key(slot: U256, holder: Address) -> H256 {
+    H256::from(keccak256(abi::encode(&[
+        Token::Address(holder),
+        Token::Uint(slot),
+    ])))
+}
+
+/// storage key of `owner` / `spender` in a solidity `mapping(address => mapping(address => uint256))` at `slot`
+pub fn allowance_key(slot: U256, owner: Address, spender: Address) -> H256 {
+    let inner = balance_key(slot, owner);
+    H256::from(keccak256(abi::encode(&[
+        Token::Address(spender),
+        Token::FixedBytes(inner.as_bytes().to_vec()),
+    ])))
+}
+
+pub fn u256_to_h256(value: U256) -> H256 {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    H256::from(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn check(allow_unverified: bool) -> SafetyCheck {
+        SafetyCheck {
+            limits: SafetyLimits {
+                allow_unverified,
+                ..Default::default()
+            },
+            slots: None,
+        }
+    }
+
+    /// tax found against a swap taking `tax_bps`, with the number of simulated swaps
+    async fn search(tax_bps: Option<u16>) -> (Option<u16>, usize) {
+        let quote = U256::exp10(18);
+        let calls = AtomicUsize::new(0);
+        let found = find_tax(quote, |min_out| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            let passes = tax_bps.is_some_and(|tax_bps| min_out <= deduct_bps(quote, tax_bps));
+            async move { passes }
+        })
+        .await;
+        (found, calls.into_inner())
+    }
+
+    #[tokio::test]
+    async fn tax_search_finds_exact_tax() {
+        for tax_bps in [0, 1, 537, 1000, 5000, 9998, 9999, MAX_BPS] {
+            let (found, calls) = search(Some(tax_bps)).await;
+            assert_eq!(found, Some(tax_bps));
+            // zero min out probe and at most 5 rounds
+            assert!(calls <= 1 + 5 * TAX_PROBES as usize, "{} calls", calls);
+        }
+    }
+
+    #[tokio::test]
+    async fn unsellable_token_has_no_tax() {
+        let (found, calls) = search(None).await;
+        assert_eq!(found, None);
+        // gave up after the first round
+        assert_eq!(calls, 1 + TAX_PROBES as usize);
+    }
+
+    #[test]
+    fn missing_liquidity_is_rechecked() {
+        let report = RoundTripReport {
+            buy_tax_bps: 0,
+            sell_tax_bps: 0,
+            round_trip_loss_bps: 0,
+        };
+        let no_liquidity = SafetyVerdict::NoLiquidity("no buy quote".to_owned());
+        let unverified = SafetyVerdict::Unverified("token storage layout unknown".to_owned());
+        let unsafe_token = SafetyVerdict::Unsafe("token can not be sold".to_owned());
+
+        let strict = check(false);
+        assert_eq!(
+            strict.decide(&SafetyVerdict::Safe(report)),
+            SafetyDecision::Buy
+        );
+        assert_eq!(strict.decide(&unsafe_token), SafetyDecision::Refuse);
+        assert_eq!(strict.decide(&unverified), SafetyDecision::Refuse);
+        assert_eq!(strict.decide(&no_liquidity), SafetyDecision::Recheck);
+
+        let lenient = check(true);
+        assert_eq!(lenient.decide(&unsafe_token), SafetyDecision::Refuse);
+        assert_eq!(lenient.decide(&unverified), SafetyDecision::Buy);
+        assert_eq!(lenient.decide(&no_liquidity), SafetyDecision::Buy);
+    }
+
+    #[test]
+    fn limits_are_checked_in_order() {
+        let check = SafetyCheck {
+            limits: SafetyLimits {
+                max_buy_tax_bps: Some(500),
+                max_sell_tax_bps: Some(500),
+                max_round_trip_loss_bps: Some(1500),
+                allow_unverified: false,
+            }
+            .validate()
+            .unwrap(),
+            slots: None,
+        };
+        let report = |buy_tax_bps, sell_tax_bps, round_trip_loss_bps| RoundTripReport {
+            buy_tax_bps,
+            sell_tax_bps,
+            round_trip_loss_bps,
+        };
+        assert_eq!(check.exceeded_limit(&report(500, 500, 1500)), None);
+        assert_eq!(
+            check.exceeded_limit(&report(501, 600, 1600)),
+            Some("buy tax over limit")
+        );
+        assert_eq!(
+            check.exceeded_limit(&report(0, 501, 0)),
+            Some("sell tax over limit")
+        );
+        assert_eq!(
+            check.exceeded_limit(&report(0, 0, 1501)),
+            Some("round trip loss over limit")
+        );
+
+        let limits = SafetyLimits {
+            max_sell_tax_bps: Some(MAX_BPS + 1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            limits.validate(),
+            Err(EnvSetUpError::InvalidSafetyLimit("max_sell_tax_bps", _))
+        ));
+    }
+}
@@ -0,0 +1,711 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use ethers::prelude::{
+    Http, LocalWallet, Middleware, Provider, SignerMiddleware, TransactionReceipt,
+    TransactionRequest, H256, U256,
+};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::utils::keccak256;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+use super::backend::SubmissionBackend;
+use super::error::{BlockBotError, EnvSetUpError};
+use super::gas::{GasFees, TxType};
+use super::nonce::NonceManager;
+
+/// blocks without inclusion after which a tx counts as stuck when nothing is configured
+pub const DEFAULT_STUCK_AFTER_BLOCKS: u64 = 3;
+
+/// percent added to the fees of a stuck tx when none is configured
+pub const DEFAULT_GAS_BUMP_PCT: u16 = 12;
+
+/// speed-ups of one tx when none is configured
+pub const DEFAULT_MAX_REPLACEMENTS: u32 = 2;
+
+/// nodes reject a replacement paying less than 10% over the tx it replaces
+pub const MIN_GAS_BUMP_PCT: u16 = 10;
+
+/// interval between receipt polls of a submitted tx
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// gas limit of a plain native token transfer
+const CANCEL_GAS: u64 = 21_000;
+
+type Signer = SignerMiddleware<Arc<Provider<Http>>, LocalWallet>;
+
+/// What to do with a tx still stuck after every speed-up
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GiveUpAction {
+    /// replace it with a zero value transfer to ourselves
+    #[default]
+    Cancel,
+    /// leave it in the mempool and stop waiting
+    Abandon,
+}
+
+/// Validated stuck tx detection and replacement limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplacementPolicy {
+    /// tx counts as stuck after this many blocks without inclusion
+    pub stuck_after_blocks: Option<u64>,
+    /// tx counts as stuck after this long without inclusion
+    pub stuck_after: Option<Duration>,
+    pub gas_bump_pct: u16,
+    pub max_replacements: u32,
+    /// speed-ups never pay more than this per gas
+    pub max_gas_price: Option<U256>,
+    pub give_up: GiveUpAction,
+}
+
+impl Default for ReplacementPolicy {
+    fn default() -> Self {
+        ReplacementPolicy {
+            stuck_after_blocks: Some(DEFAULT_STUCK_AFTER_BLOCKS),
+            stuck_after: None,
+            gas_bump_pct: DEFAULT_GAS_BUMP_PCT,
+            max_replacements: DEFAULT_MAX_REPLACEMENTS,
+            max_gas_price: None,
+            give_up: GiveUpAction::Cancel,
+        }
+    }
+}
+
+impl ReplacementPolicy {
+    pub fn validate(self) -> Result<ReplacementPolicy, EnvSetUpError> {
+        if self.gas_bump_pct < MIN_GAS_BUMP_PCT {
+            return Err(EnvSetUpError::InvalidReplacement(format!(
+                "gas_bump_pct {} must be at least {}",
+                self.gas_bump_pct, MIN_GAS_BUMP_PCT
+            )));
+        }
+        if self.stuck_after_blocks == Some(0) || self.stuck_after == Some(Duration::ZERO) {
+            return Err(EnvSetUpError::InvalidReplacement(
+                "stuck_after_blocks and stuck_after_secs must be greater than 0".to_owned(),
+            ));
+        }
+        Ok(self)
+    }
+
+    /// whether a tx sent `blocks` blocks and `elapsed` time ago is stuck
+    /// without any limit configured txs are waited for forever
+    fn is_stuck(&self, blocks: u64, elapsed: Duration) -> bool {
+        self.stuck_after_blocks.is_some_and(|limit| blocks >= limit)
+            || self.stuck_after.is_some_and(|limit| elapsed >= limit)
+    }
+
+    /// `fees` raised by the bump percent, at least by 1 wei
+    fn bump(&self, fees: GasFees) -> GasFees {
+        let pct = U256::from(100u16 + self.gas_bump_pct);
+        fees.map(|fee| {
+            let bumped = fee.saturating_mul(pct) / U256::from(100u8);
+            bumped.max(fee.saturating_add(U256::one()))
+        })
+    }
+}
+
+/// Kind of a submitted tx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxAction {
+    Original,
+    SpeedUp,
+    Cancel,
+}
+
+impl fmt::Display for TxAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxAction::Original => f.write_str("original"),
+            TxAction::SpeedUp => f.write_str("speed_up"),
+            TxAction::Cancel => f.write_str("cancel"),
+        }
+    }
+}
+
+/// How waiting for any tx of a nonce ended
+enum WaitOutcome {
+    Mined(Box<TransactionReceipt>),
+    Stuck,
+    /// nonce was taken by a tx we did not send
+    NonceUsed,
+}
+
+/// Submission of one intent, every tx shares the nonce and links to the first tx
+struct Submission<'a> {
+    intent: &'a str,
+    nonce: U256,
+    original: H256,
+    attempt: u32,
+}
+
+/** Tx journal
+ * appends every submitted tx and the final outcome as a json line,
+ * replacements share the intent and the hash of the original tx
+**/
+#[derive(Debug)]
+pub struct TxJournal {
+    file: Mutex<File>,
+}
+
+impl TxJournal {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<TxJournal> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(TxJournal {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn record(&self, record: serde_json::Value) {
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        if let Err(err) = writeln!(file, "{}", record) {
+            tracing::error!("unable to write tx record: {}", err);
+        }
+    }
+}
+
+/** Tx submitter of one wallet
+ * signs txs with the shared nonce manager, hands them to the submission backend
+ * and waits for them to be mined. Stuck txs are resubmitted with the same nonce
+ * and bumped fees up to the policy limits, then cancelled or abandoned.
+ * The nonce is resynced from the node whenever no tx of ours was mined with it
+**/
+#[derive(Debug)]
+pub struct TxSubmitter {
+    nonce_manager: Arc<NonceManager>,
+    backend: Arc<dyn SubmissionBackend>,
+    policy: ReplacementPolicy,
+    journal: Option<Arc<TxJournal>>,
+}
+
+impl TxSubmitter {
+    pub fn new(
+        nonce_manager: Arc<NonceManager>,
+        backend: Arc<dyn SubmissionBackend>,
+        policy: ReplacementPolicy,
+        journal: Option<Arc<TxJournal>>,
+    ) -> TxSubmitter {
+        TxSubmitter {
+            nonce_manager,
+            backend,
+            policy,
+            journal,
+        }
+    }
+
+    /** Send `tx` and wait until it or one of its replacements is mined
+     * `intent` names what the tx does in logs and the journal.
+     * None when the tx was abandoned, cancelled or its nonce was used elsewhere
+     **/
+    #[instrument(skip(self, signer, tx))]
+    pub async fn submit(
+        &self,
+        signer: &Signer,
+        mut tx: TypedTransaction,
+        intent: &str,
+    ) -> Result<Option<TransactionReceipt>, BlockBotError> {
+        let nonce = self.nonce_manager.next().await?;
+        tx.set_nonce(nonce);
+        tracing::info!("sending tx with nonce {}", nonce);
+
+        // fees and gas limit are filled now so replacements can bump them
+        let original = match self.fill_and_send(signer, &mut tx).await {
+            Ok(hash) => hash,
+            Err(err) => {
+                self.nonce_manager.invalidate().await;
+                return Err(err);
+            }
+        };
+
+        let mut submission = Submission {
+            intent,
+            nonce,
+            original,
+            attempt: 0,
+        };
+        self.record_attempt(&submission, TxAction::Original, &tx, Ok(original));
+
+        let mut hashes = vec![original];
+        let mut cancelled = false;
+        let mut cancel_hash = None;
+        loop {
+            match self.wait(signer, &hashes, nonce).await {
+                WaitOutcome::Mined(receipt) => {
+                    tracing::info!(
+                        "tx: {:?} confirmed, execution successful?: {:?}",
+                        receipt.transaction_hash,
+                        receipt.status
+                    );
+                    if cancel_hash == Some(receipt.transaction_hash) {
+                        self.record_outcome(&submission, "cancelled", Some(&receipt));
+                        return Ok(None);
+                    }
+                    self.record_outcome(&submission, "mined", Some(&receipt));
+                    return Ok(Some(*receipt));
+                }
+                WaitOutcome::NonceUsed => {
+                    tracing::warn!("nonce {} was used by another tx", nonce);
+                    self.record_outcome(&submission, "nonce_used", None);
+                    self.nonce_manager.invalidate().await;
+                    return Ok(None);
+                }
+                WaitOutcome::Stuck => {}
+            }
+
+            if cancelled {
+                tracing::warn!("cancel of {:?} is stuck too, giving up", original);
+                self.record_outcome(&submission, "gave_up", None);
+                self.nonce_manager.invalidate().await;
+                return Ok(None);
+            }
+
+            let (action, mut next_tx) = match self.speed_up(&tx, submission.attempt) {
+                Some(next_tx) => (TxAction::SpeedUp, next_tx),
+                None => match self.give_up() {
+                    GiveUpAction::Abandon => {
+                        tracing::warn!("abandoning stuck tx {:?}", original);
+                        self.record_outcome(&submission, "abandoned", None);
+                        // the nonce may never be used, later txs would wait behind it
+                        self.nonce_manager.invalidate().await;
+                        return Ok(None);
+                    }
+                    GiveUpAction::Cancel => {
+                        cancelled = true;
+                        (TxAction::Cancel, self.cancel(signer, &tx))
+                    }
+                },
+            };
+
+            submission.attempt += 1;
+            tracing::info!(
+                "tx {:?} stuck, sending {} #{}",
+                original,
+                action,
+                submission.attempt
+            );
+            let sent = self.fill_and_send(signer, &mut next_tx).await;
+            self.record_attempt(
+                &submission,
+                action,
+                &next_tx,
+                sent.as_ref().map(|hash| *hash),
+            );
+            match sent {
+                Ok(hash) => {
+                    if action == TxAction::Cancel {
+                        cancel_hash = Some(hash);
+                    }
+                    hashes.push(hash);
+                    tx = next_tx;
+                }
+                // earlier txs may still be mined, keep waiting for them
+                Err(err) => tracing::warn!("{} rejected: {}", action, err),
+            }
+        }
+    }
+
+    /// sign `tx` and hand it to the backend, fields left unset are filled on `tx` first
+    async fn fill_and_send(
+        &self,
+        signer: &Signer,
+        tx: &mut TypedTransaction,
+    ) -> Result<H256, BlockBotError> {
+        signer.fill_transaction(tx, None).await?;
+        let signature = signer.sign_transaction(tx, signer.address()).await?;
+        let raw_tx = tx.rlp_signed(&signature);
+        let tx_hash = H256::from(keccak256(&raw_tx));
+
+        self.backend.send_raw_transaction(raw_tx, tx_hash).await?;
+        Ok(tx_hash)
+    }
+
+    /// give up action of the policy, txs dropped by the backend need no cancel
+    fn give_up(&self) -> GiveUpAction {
+        if self.backend.drops_stuck_txs() {
+            GiveUpAction::Abandon
+        } else {
+            self.policy.give_up
+        }
+    }
+
+    /// replacement of `tx` with bumped fees, None when limits are reached
+    fn speed_up(&self, tx: &TypedTransaction, attempt: u32) -> Option<TypedTransaction> {
+        if attempt >= self.policy.max_replacements {
+            return None;
+        }
+        let fees = GasFees::of(tx)?;
+        let mut bumped = self.policy.bump(fees);
+        if let Some(cap) = self.policy.max_gas_price {
+            bumped = bumped.map(|fee| fee.min(cap));
+            // a replacement has to outbid the tx it replaces
+            if bumped.max_price() <= fees.max_price() {
+                tracing::warn!("speed-up limited by max gas price {}", cap);
+                return None;
+            }
+        }
+
+        let mut next_tx = tx.clone();
+        bumped.set_on(&mut next_tx);
+        Some(next_tx)
+    }
+
+    /// zero value transfer to ourselves replacing `tx`
+    /// bumped fees are not capped, the tx only pays for a plain transfer
+    fn cancel(&self, signer: &Signer, tx: &TypedTransaction) -> TypedTransaction {
+        let tx_type = match tx {
+            TypedTransaction::Eip1559(_) => TxType::Eip1559,
+            _ => TxType::Legacy,
+        };
+        let tx_req = TransactionRequest::new()
+            .from(signer.address())
+            .to(signer.address())
+            .value(0)
+            .gas(CANCEL_GAS);
+        let mut cancel_tx = super::gas::typed_transaction(tx_req, tx_type);
+        if let Some(nonce) = tx.nonce() {
+            cancel_tx.set_nonce(*nonce);
+        }
+        if let Some(chain_id) = tx.chain_id() {
+            cancel_tx.set_chain_id(chain_id);
+        }
+        if let Some(fees) = GasFees::of(tx) {
+            self.policy.bump(fees).set_on(&mut cancel_tx);
+        }
+        cancel_tx
+    }
+
+    /// poll receipts of every tx sent with `nonce` until one is mined or the latest one is stuck
+    /// rpc errors are logged and polled through, the txs may still be mined
+    async fn wait(&self, signer: &Signer, hashes: &[H256], nonce: U256) -> WaitOutcome {
+        let sent_at = Instant::now();
+        let mut sent_block = None;
+        let mut interval = tokio::time::interval(RECEIPT_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Some(receipt) = Self::find_receipt(signer, hashes).await {
+                return WaitOutcome::Mined(Box::new(receipt));
+            }
+
+            match signer.get_transaction_count(signer.address(), None).await {
+                Ok(mined_nonce) if mined_nonce > nonce => {
+                    // our tx may have been mined right after the receipt poll
+                    return match Self::find_receipt(signer, hashes).await {
+                        Some(receipt) => WaitOutcome::Mined(Box::new(receipt)),
+                        None => WaitOutcome::NonceUsed,
+                    };
+                }
+                Ok(_) => {}
+                Err(err) => tracing::warn!("unable to fetch mined nonce: {}", err),
+            }
+
+            let blocks = match signer.get_block_number().await {
+                Ok(block) => block
+                    .as_u64()
+                    .saturating_sub(*sent_block.get_or_insert(block.as_u64())),
+                Err(err) => {
+                    tracing::warn!("unable to fetch block number: {}", err);
+                    0
+                }
+            };
+            if self.policy.is_stuck(blocks, sent_at.elapsed()) {
+                return WaitOutcome::Stuck;
+            }
+        }
+    }
+
+    async fn find_receipt(signer: &Signer, hashes: &[H256]) -> Option<TransactionReceipt> {
+        for hash in hashes.iter() {
+            match signer.get_transaction_receipt(*hash).await {
+                Ok(Some(receipt)) => return Some(receipt),
+                Ok(None) => {}
+                Err(err) => tracing::warn!("unable to fetch receipt of {:?}: {}", hash, err),
+            }
+        }
+        None
+    }
+
+    fn record_attempt(
+        &self,
+        submission: &Submission,
+        action: TxAction,
+        tx: &TypedTransaction,
+        sent: Result<H256, &BlockBotError>,
+    ) {
+        if let Some(journal) = &self.journal {
+            let (tx_hash, error) = match sent {
+                Ok(hash) => (Some(hash), None),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            journal.record(json!({
+                "time": chrono::Utc::now().to_rfc3339(),
+                "intent": submission.intent,
+                "original_tx": submission.original,
+                "nonce": submission.nonce,
+                "attempt": submission.attempt,
+                "action": action.to_string(),
+                "backend": self.backend.name(),
+                "tx_hash": tx_hash,
+                "fees": GasFees::of(tx).map(|fees| fees.to_string()),
+                "error": error,
+            }));
+        }
+    }
+
+    fn record_outcome(
+        &self,
+        submission: &Submission,
+        outcome: &str,
+        receipt: Option<&TransactionReceipt>,
+    ) {
+        if let Some(journal) = &self.journal {
+            journal.record(json!({
+                "time": chrono::Utc::now().to_rfc3339(),
+                "intent": submission.intent,
+                "original_tx": submission.original,
+                "nonce": submission.nonce,
+                "attempts": submission.attempt + 1,
+                "outcome": outcome,
+                "tx_hash": receipt.map(|receipt| receipt.transaction_hash),
+                "status": receipt.and_then(|receipt| receipt.status),
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::backend::{PublicRpc, RpcEndpoint};
+    use crate::util::mock_rpc::{MockRpc, Reply};
+    use ethers::prelude::{Bytes, Signer as _};
+    use ethers::utils::rlp::Rlp;
+    use serde_json::Value;
+    use std::convert::TryFrom;
+
+    fn legacy(gas_price: u64) -> GasFees {
+        GasFees::Legacy {
+            gas_price: gas_price.into(),
+        }
+    }
+
+    fn submitter(policy: ReplacementPolicy) -> TxSubmitter {
+        let provider = Arc::new(Provider::<Http>::try_from("http://127.0.0.1:1").unwrap());
+        TxSubmitter::new(
+            Arc::new(NonceManager::new(provider, Default::default())),
+            Arc::new(PublicRpc::new(Vec::new())),
+            policy,
+            None,
+        )
+    }
+
+    fn stuck_tx(fees: GasFees) -> TypedTransaction {
+        let tx_type = match fees {
+            GasFees::Legacy { .. } => TxType::Legacy,
+            GasFees::Eip1559 { .. } => TxType::Eip1559,
+        };
+        let tx_req = TransactionRequest::new()
+            .to(ethers::prelude::Address::repeat_byte(1))
+            .value(5)
+            .nonce(7)
+            .chain_id(56);
+        let mut tx = crate::util::gas::typed_transaction(tx_req, tx_type);
+        fees.set_on(&mut tx);
+        tx
+    }
+
+    #[test]
+    fn bump_raises_every_fee() {
+        let policy = ReplacementPolicy::default();
+        assert_eq!(policy.bump(legacy(100)), legacy(112));
+        // rounding never leaves a fee where it was
+        assert_eq!(policy.bump(legacy(1)), legacy(2));
+        assert_eq!(policy.bump(legacy(0)), legacy(1));
+
+        let fees = GasFees::Eip1559 {
+            max_fee_per_gas: 200u64.into(),
+            max_priority_fee_per_gas: 50u64.into(),
+        };
+        assert_eq!(
+            policy.bump(fees),
+            GasFees::Eip1559 {
+                max_fee_per_gas: 224u64.into(),
+                max_priority_fee_per_gas: 56u64.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn stuck_after_blocks_or_time() {
+        let policy = ReplacementPolicy {
+            stuck_after_blocks: Some(3),
+            stuck_after: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        assert!(!policy.is_stuck(2, Duration::from_secs(9)));
+        assert!(policy.is_stuck(3, Duration::ZERO));
+        assert!(policy.is_stuck(0, Duration::from_secs(10)));
+
+        let patient = ReplacementPolicy {
+            stuck_after_blocks: None,
+            ..Default::default()
+        };
+        assert!(!patient.is_stuck(u64::MAX, Duration::MAX));
+    }
+
+    #[test]
+    fn policy_is_validated() {
+        assert!(ReplacementPolicy::default().validate().is_ok());
+        for policy in [
+            ReplacementPolicy {
+                gas_bump_pct: MIN_GAS_BUMP_PCT - 1,
+                ..Default::default()
+            },
+            ReplacementPolicy {
+                stuck_after_blocks: Some(0),
+                ..Default::default()
+            },
+            ReplacementPolicy {
+                stuck_after: Some(Duration::ZERO),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                policy.validate(),
+                Err(EnvSetUpError::InvalidReplacement(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn speed_ups_are_limited() {
+        let submitter = submitter(ReplacementPolicy {
+            max_gas_price: Some(120u64.into()),
+            ..Default::default()
+        });
+        let tx = stuck_tx(legacy(100));
+
+        let next_tx = submitter.speed_up(&tx, 0).unwrap();
+        assert_eq!(GasFees::of(&next_tx), Some(legacy(112)));
+        assert_eq!(next_tx.nonce(), tx.nonce());
+        assert_eq!(next_tx.to(), tx.to());
+
+        // capped at 120, still over the replaced tx
+        let next_tx = submitter.speed_up(&next_tx, 1).unwrap();
+        assert_eq!(GasFees::of(&next_tx), Some(legacy(120)));
+        // cap reached, a replacement would not outbid
+        assert!(submitter.speed_up(&next_tx, 1).is_none());
+        // out of replacements
+        assert!(submitter.speed_up(&tx, DEFAULT_MAX_REPLACEMENTS).is_none());
+    }
+
+    #[test]
+    fn cancel_replaces_nonce_with_self_transfer() {
+        let submitter = submitter(ReplacementPolicy {
+            max_gas_price: Some(1u64.into()),
+            ..Default::default()
+        });
+        let provider = Arc::new(Provider::<Http>::try_from("http://127.0.0.1:1").unwrap());
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let address = wallet.address();
+        let signer = SignerMiddleware::new(provider, wallet);
+
+        let tx = stuck_tx(GasFees::Eip1559 {
+            max_fee_per_gas: 100u64.into(),
+            max_priority_fee_per_gas: 10u64.into(),
+        });
+        let cancel_tx = submitter.cancel(&signer, &tx);
+        assert!(matches!(cancel_tx, TypedTransaction::Eip1559(_)));
+        assert_eq!(cancel_tx.nonce(), tx.nonce());
+        assert_eq!(cancel_tx.chain_id(), tx.chain_id());
+        assert_eq!(cancel_tx.to_addr(), Some(&address));
+        assert_eq!(cancel_tx.value(), Some(&U256::zero()));
+        // not capped by max gas price
+        assert_eq!(
+            GasFees::of(&cancel_tx),
+            Some(GasFees::Eip1559 {
+                max_fee_per_gas: 112u64.into(),
+                max_priority_fee_per_gas: 11u64.into(),
+            })
+        );
+    }
+
+    /// node with 5 txs of ours that never mines anything
+    async fn idle_node() -> MockRpc {
+        MockRpc::start(|method, _| match method {
+            "eth_getTransactionCount" => Reply::Result(json!("0x5")),
+            "eth_sendRawTransaction" => Reply::Result(json!(H256::zero())),
+            "eth_getTransactionReceipt" => Reply::Result(Value::Null),
+            "eth_blockNumber" => Reply::Result(json!("0x64")),
+            "eth_estimateGas" => Reply::Result(json!("0x5208")),
+            _ => Reply::Error(-32601, "method not found".to_owned()),
+        })
+        .await
+    }
+
+    fn nonce_of(raw_tx: &Value) -> U256 {
+        let raw_tx: Bytes = serde_json::from_value(raw_tx.clone()).unwrap();
+        let (tx, _) = TypedTransaction::decode_signed(&Rlp::new(&raw_tx)).unwrap();
+        *tx.nonce().unwrap()
+    }
+
+    #[tokio::test]
+    async fn nonce_is_resynced_after_an_abandoned_tx() {
+        let node = idle_node().await;
+        let provider = Arc::new(node.provider());
+        let wallet = LocalWallet::new(&mut rand::thread_rng()).with_chain_id(56u64);
+        let submitter = TxSubmitter::new(
+            Arc::new(NonceManager::new(Arc::clone(&provider), wallet.address())),
+            Arc::new(PublicRpc::new(vec![RpcEndpoint::Http(Arc::clone(
+                &provider,
+            ))])),
+            ReplacementPolicy {
+                // stuck right away, abandoned without a speed-up
+                stuck_after_blocks: None,
+                stuck_after: Some(Duration::ZERO),
+                max_replacements: 0,
+                give_up: GiveUpAction::Abandon,
+                ..Default::default()
+            },
+            None,
+        );
+        let signer = SignerMiddleware::new(provider, wallet);
+
+        for _ in 0..2 {
+            let tx = stuck_tx(legacy(100));
+            assert!(submitter
+                .submit(&signer, tx, "buy")
+                .await
+                .unwrap()
+                .is_none());
+        }
+
+        // the abandoned nonce is fetched again instead of skipped
+        let pending_counts = node
+            .requests("eth_getTransactionCount")
+            .into_iter()
+            .filter(|params| params[1] == "pending")
+            .count();
+        assert_eq!(pending_counts, 2);
+        let nonces: Vec<U256> = node
+            .requests("eth_sendRawTransaction")
+            .iter()
+            .map(|params| nonce_of(&params[0]))
+            .collect();
+        assert_eq!(nonces, vec![U256::from(5); 2]);
+    }
+}
@@ -0,0 +1,579 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers::prelude::{
+    Block, Filter, Log, Middleware, Provider, ProviderError, StreamExt, SubscriptionStream, Ws,
+    WsClientError, H256,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use tokio::time;
+
+use super::error::EnvSetUpError;
+use super::pending::{PendingTx, PendingTxMode};
+
+/// seconds without a subscription item after which the connection is replaced
+pub const DEFAULT_STALL_SECS: u64 = 30;
+
+/// wait before the first reconnect attempt, doubled after every failed one
+pub const DEFAULT_RECONNECT_BACKOFF_MS: u64 = 500;
+
+/// upper bound of the reconnect wait
+pub const DEFAULT_MAX_RECONNECT_BACKOFF_SECS: u64 = 30;
+
+/// a connect attempt hanging longer than this counts as failed
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// items buffered per subscription before the subscription waits for the consumer
+const SUBSCRIPTION_BUFFER: usize = 1024;
+
+/// health events kept for slow health receivers
+const HEALTH_BUFFER: usize = 64;
+
+/// polling interval of the ws provider
+const PROVIDER_INTERVAL: Duration = Duration::from_millis(30);
+
+/// When the connection is considered dead and how fast it is replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WsPolicy {
+    /// no subscription item for this long counts as a dead connection
+    pub stall_after: Duration,
+    pub reconnect_backoff: Duration,
+    pub max_reconnect_backoff: Duration,
+}
+
+impl Default for WsPolicy {
+    fn default() -> Self {
+        WsPolicy {
+            stall_after: Duration::from_secs(DEFAULT_STALL_SECS),
+            reconnect_backoff: Duration::from_millis(DEFAULT_RECONNECT_BACKOFF_MS),
+            max_reconnect_backoff: Duration::from_secs(DEFAULT_MAX_RECONNECT_BACKOFF_SECS),
+        }
+    }
+}
+
+impl WsPolicy {
+    pub fn validate(self) -> Result<WsPolicy, EnvSetUpError> {
+        if self.stall_after.is_zero() {
+            return Err(EnvSetUpError::InvalidWsSetting(
+                "stall_secs must be greater than 0".to_owned(),
+            ));
+        }
+        if self.reconnect_backoff.is_zero() {
+            return Err(EnvSetUpError::InvalidWsSetting(
+                "reconnect_backoff_ms must be greater than 0".to_owned(),
+            ));
+        }
+        if self.max_reconnect_backoff < self.reconnect_backoff {
+            return Err(EnvSetUpError::InvalidWsSetting(
+                "max_reconnect_backoff_secs must not be below reconnect_backoff_ms".to_owned(),
+            ));
+        }
+        Ok(self)
+    }
+}
+
+/// Ws connection health event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsHealth {
+    Subscribed {
+        subscription: &'static str,
+    },
+    /// no item within the stall limit
+    Stalled {
+        subscription: &'static str,
+        after: Duration,
+    },
+    /// subscription stream ended or could not be opened
+    Disconnected {
+        subscription: &'static str,
+    },
+    ReconnectFailed {
+        attempt: u32,
+        retry_in: Duration,
+        error: String,
+    },
+    Reconnected {
+        attempts: u32,
+    },
+}
+
+impl fmt::Display for WsHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WsHealth::Subscribed { subscription } => write!(f, "subscribed to {}", subscription),
+            WsHealth::Stalled {
+                subscription,
+                after,
+            } => write!(
+                f,
+                "no {} for {}s, reconnecting",
+                subscription,
+                after.as_secs()
+            ),
+            WsHealth::Disconnected { subscription } => {
+                write!(
+                    f,
+                    "{} subscription disconnected, reconnecting",
+                    subscription
+                )
+            }
+            WsHealth::ReconnectFailed {
+                attempt,
+                retry_in,
+                error,
+            } => write!(
+                f,
+                "reconnect attempt {} failed: {}, retrying in {}ms",
+                attempt,
+                error,
+                retry_in.as_millis()
+            ),
+            WsHealth::Reconnected { attempts } => {
+                write!(f, "reconnected after {} attempts", attempts)
+            }
+        }
+    }
+}
+
+/// ws provider along with how many times it was replaced
+#[derive(Debug, Clone)]
+struct Connection {
+    provider: Arc<Provider<Ws>>,
+    generation: u64,
+}
+
+/// why a subscription stopped forwarding items
+enum StreamEnd {
+    Shutdown,
+    /// consumer dropped its receiver
+    Closed,
+    /// connection was replaced, resubscribe on the new one
+    Moved,
+    Disconnected,
+    Stalled,
+}
+
+/** Supervised ws connection
+ * subscriptions are forwarded to channels that outlive the connection. A subscription
+ * ending or staying silent for `stall_after` gets the connection replaced with backoff,
+ * every subscription then resubscribes on the new connection.
+ * Health changes are broadcast to `health` receivers. Reconnects of the ethers ws
+ * client are turned off, they would hide a dropped connection from the supervisor
+**/
+#[derive(Debug)]
+pub struct WsSupervisor {
+    url: String,
+    policy: WsPolicy,
+    connection: watch::Sender<Connection>,
+    /// one reconnect at a time, other subscriptions wait for its connection
+    reconnecting: Mutex<()>,
+    health: broadcast::Sender<WsHealth>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl WsSupervisor {
+    /// connect once, a failing first connect is a setup error
+    pub async fn connect(
+        url: String,
+        policy: WsPolicy,
+    ) -> Result<Arc<WsSupervisor>, WsClientError> {
+        let ws = Ws::connect_with_reconnects(url.as_str(), 0).await?;
+        let (connection, _) = watch::channel(Connection {
+            provider: Arc::new(Provider::new(ws).interval(PROVIDER_INTERVAL)),
+            generation: 0,
+        });
+        let (health, _) = broadcast::channel(HEALTH_BUFFER);
+        let (shutdown, _) = watch::channel(false);
+        Ok(Arc::new(WsSupervisor {
+            url,
+            policy,
+            connection,
+            reconnecting: Mutex::new(()),
+            health,
+            shutdown,
+        }))
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// provider of the current connection, replaced on reconnect
+    pub fn provider(&self) -> Arc<Provider<Ws>> {
+        Arc::clone(&self.connection.borrow().provider)
+    }
+
+    pub fn health(&self) -> broadcast::Receiver<WsHealth> {
+        self.health.subscribe()
+    }
+
+    /// pending txs, see [`PendingTxMode::subscription_params`]
+    pub fn pending_txs(self: &Arc<Self>, mode: PendingTxMode) -> mpsc::Receiver<PendingTx> {
+        self.supervise("pending txs", mode.subscription_params())
+    }
+
+    pub fn blocks(self: &Arc<Self>) -> mpsc::Receiver<Block<H256>> {
+        self.supervise("blocks", vec![json!(["newHeads"])])
+    }
+
+    /// logs matching `filter`, the same filter is used on every connection
+    pub fn logs(self: &Arc<Self>, filter: &Filter) -> mpsc::Receiver<Log> {
+        self.supervise("logs", vec![json!(["logs", filter])])
+    }
+
+    /// end every subscription, their receivers yield None afterwards
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// forward subscription items to a channel, resubscribing after reconnects.
+    /// `candidates` are `eth_subscribe` params tried in order
+    fn supervise<R>(
+        self: &Arc<Self>,
+        name: &'static str,
+        candidates: Vec<Value>,
+    ) -> mpsc::Receiver<R>
+    where
+        R: DeserializeOwned + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        let supervisor = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut shutdown = supervisor.shutdown.subscribe();
+            let mut connection_changes = supervisor.connection.subscribe();
+            loop {
+                if *shutdown.borrow() {
+                    return;
+                }
+                let connection = connection_changes.borrow_and_update().clone();
+                let end = supervisor
+                    .forward(
+                        name,
+                        &connection.provider,
+                        &candidates,
+                        &sender,
+                        &mut shutdown,
+                        &mut connection_changes,
+                    )
+                    .await;
+                match end {
+                    StreamEnd::Shutdown | StreamEnd::Closed => return,
+                    StreamEnd::Moved => continue,
+                    StreamEnd::Disconnected => {
+                        supervisor.emit(WsHealth::Disconnected { subscription: name });
+                    }
+                    StreamEnd::Stalled => supervisor.emit(WsHealth::Stalled {
+                        subscription: name,
+                        after: supervisor.policy.stall_after,
+                    }),
+                }
+                supervisor.reconnect(connection.generation).await;
+            }
+        });
+        receiver
+    }
+
+    /// forward items of one subscription on `provider` until it ends
+    async fn forward<R>(
+        &self,
+        name: &'static str,
+        provider: &Provider<Ws>,
+        candidates: &[Value],
+        sender: &mpsc::Sender<R>,
+        shutdown: &mut watch::Receiver<bool>,
+        connection_changes: &mut watch::Receiver<Connection>,
+    ) -> StreamEnd
+    where
+        R: DeserializeOwned + Send + Sync,
+    {
+        let mut stream = match subscribe_first::<R>(provider, name, candidates).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                tracing::warn!("unable to subscribe to {}: {}", name, err);
+                return StreamEnd::Disconnected;
+            }
+        };
+        self.emit(WsHealth::Subscribed { subscription: name });
+
+        // dropping the stream unsubscribes it
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => return StreamEnd::Shutdown,
+                _ = connection_changes.changed() => return StreamEnd::Moved,
+                next = time::timeout(self.policy.stall_after, stream.next()) => match next {
+                    Ok(Some(item)) => {
+                        if sender.send(item).await.is_err() {
+                            return StreamEnd::Closed;
+                        }
+                    }
+                    Ok(None) => return StreamEnd::Disconnected,
+                    Err(_) => return StreamEnd::Stalled,
+                },
+            }
+        }
+    }
+
+    /// replace the connection of `failed_generation`, retrying with doubling backoff.
+    /// Nothing is done when another subscription already replaced it
+    async fn reconnect(&self, failed_generation: u64) {
+        let _reconnecting = self.reconnecting.lock().await;
+        if self.connection.borrow().generation != failed_generation {
+            return;
+        }
+
+        let mut backoff = self.policy.reconnect_backoff;
+        let mut attempt = 0;
+        while !*self.shutdown.borrow() {
+            attempt += 1;
+            let connect = Ws::connect_with_reconnects(self.url.as_str(), 0);
+            let error = match time::timeout(CONNECT_TIMEOUT, connect).await {
+                Ok(Ok(ws)) => {
+                    self.connection.send_replace(Connection {
+                        provider: Arc::new(Provider::new(ws).interval(PROVIDER_INTERVAL)),
+                        generation: failed_generation + 1,
+                    });
+                    self.emit(WsHealth::Reconnected { attempts: attempt });
+                    return;
+                }
+                Ok(Err(err)) => err.to_string(),
+                Err(_) => format!("no connection within {}s", CONNECT_TIMEOUT.as_secs()),
+            };
+            self.emit(WsHealth::ReconnectFailed {
+                attempt,
+                retry_in: backoff,
+                error,
+            });
+            time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.policy.max_reconnect_backoff);
+        }
+    }
+
+    fn emit(&self, event: WsHealth) {
+        match &event {
+            WsHealth::Subscribed { .. } | WsHealth::Reconnected { .. } => {
+                tracing::info!("ws {}", event)
+            }
+            _ => tracing::warn!("ws {}", event),
+        }
+        // no health receiver is fine
+        let _ = self.health.send(event);
+    }
+}
+
+/// subscribe with the first `eth_subscribe` params the node accepts
+async fn subscribe_first<'a, R>(
+    provider: &'a Provider<Ws>,
+    name: &'static str,
+    candidates: &[Value],
+) -> Result<SubscriptionStream<'a, Ws, R>, ProviderError>
+where
+    R: DeserializeOwned + Send + Sync,
+{
+    let mut last_err = None;
+    for params in candidates.iter() {
+        match provider.subscribe(params.clone()).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => {
+                tracing::warn!("{} subscription {} rejected: {}", name, params, err);
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.unwrap_or(ProviderError::UnsupportedRPC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::mock_ws::{MockWs, Session};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn policy(stall_after: Duration) -> WsPolicy {
+        WsPolicy {
+            stall_after,
+            reconnect_backoff: Duration::from_millis(20),
+            max_reconnect_backoff: Duration::from_millis(50),
+        }
+        .validate()
+        .unwrap()
+    }
+
+    fn tx_hash(byte: u8) -> H256 {
+        H256::repeat_byte(byte)
+    }
+
+    fn serve(items: &[u8], close_after: Option<usize>) -> Session {
+        Session::Serve {
+            items: items.iter().map(|byte| json!(tx_hash(*byte))).collect(),
+            close_after,
+        }
+    }
+
+    async fn next_tx(pending_txs: &mut mpsc::Receiver<PendingTx>) -> H256 {
+        time::timeout(WAIT, pending_txs.recv())
+            .await
+            .expect("no pending tx in time")
+            .expect("subscription ended")
+            .hash()
+    }
+
+    async fn next_health(health: &mut broadcast::Receiver<WsHealth>) -> WsHealth {
+        time::timeout(WAIT, health.recv())
+            .await
+            .expect("no health event in time")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn dropped_connection_is_replaced_once_for_every_subscription() {
+        let mock = MockWs::start(|connection| match connection {
+            0 => serve(&[1], Some(2)),
+            _ => serve(&[2], None),
+        })
+        .await;
+        let ws = WsSupervisor::connect(mock.url().to_owned(), policy(WAIT))
+            .await
+            .unwrap();
+        let mut health = ws.health();
+        let mut first = ws.pending_txs(PendingTxMode::Hashes);
+        let mut second = ws.pending_txs(PendingTxMode::Hashes);
+
+        // items of the old connection, then of the new one on the same channel
+        assert_eq!(next_tx(&mut first).await, tx_hash(1));
+        assert_eq!(next_tx(&mut first).await, tx_hash(2));
+        assert_eq!(next_tx(&mut second).await, tx_hash(1));
+        assert_eq!(next_tx(&mut second).await, tx_hash(2));
+        assert_eq!(mock.connections(), 2);
+        assert_eq!(
+            mock.subscriptions(),
+            vec![json!(["newPendingTransactions"]); 4]
+        );
+
+        let mut events = Vec::new();
+        while let Ok(event) = health.try_recv() {
+            events.push(event);
+        }
+        let count = |wanted: &WsHealth| events.iter().filter(|event| *event == wanted).count();
+        let subscribed = WsHealth::Subscribed {
+            subscription: "pending txs",
+        };
+        let disconnected = WsHealth::Disconnected {
+            subscription: "pending txs",
+        };
+        assert_eq!(count(&subscribed), 4);
+        assert_eq!(count(&disconnected), 2);
+        assert_eq!(count(&WsHealth::Reconnected { attempts: 1 }), 1);
+        ws.shutdown();
+    }
+
+    #[tokio::test]
+    async fn silent_subscription_counts_as_stalled() {
+        let mock = MockWs::start(|connection| match connection {
+            0 => serve(&[], None),
+            _ => serve(&[1], None),
+        })
+        .await;
+        let stall_after = Duration::from_millis(200);
+        let ws = WsSupervisor::connect(mock.url().to_owned(), policy(stall_after))
+            .await
+            .unwrap();
+        let mut health = ws.health();
+        let mut pending_txs = ws.pending_txs(PendingTxMode::Full);
+
+        assert_eq!(next_tx(&mut pending_txs).await, tx_hash(1));
+        let subscribed = WsHealth::Subscribed {
+            subscription: "pending txs",
+        };
+        assert_eq!(next_health(&mut health).await, subscribed);
+        assert_eq!(
+            next_health(&mut health).await,
+            WsHealth::Stalled {
+                subscription: "pending txs",
+                after: stall_after
+            }
+        );
+        assert_eq!(
+            next_health(&mut health).await,
+            WsHealth::Reconnected { attempts: 1 }
+        );
+        assert_eq!(next_health(&mut health).await, subscribed);
+        // full tx params on both connections
+        assert_eq!(
+            mock.subscriptions(),
+            vec![json!(["newPendingTransactions", true]); 2]
+        );
+        ws.shutdown();
+    }
+
+    #[tokio::test]
+    async fn logs_are_resubscribed_with_their_filter() {
+        let mock = MockWs::start(|connection| Session::Serve {
+            items: vec![json!(Log {
+                transaction_hash: Some(tx_hash(connection as u8 + 1)),
+                ..Default::default()
+            })],
+            close_after: if connection == 0 { Some(1) } else { None },
+        })
+        .await;
+        let ws = WsSupervisor::connect(mock.url().to_owned(), policy(WAIT))
+            .await
+            .unwrap();
+        let filter = Filter::new()
+            .address(ethers::prelude::Address::repeat_byte(0xf1))
+            .event("Mint(address,uint256,uint256)");
+        let mut logs = ws.logs(&filter);
+
+        for byte in [1, 2] {
+            let log = time::timeout(WAIT, logs.recv())
+                .await
+                .expect("no log in time")
+                .expect("subscription ended");
+            assert_eq!(log.transaction_hash, Some(tx_hash(byte)));
+        }
+        assert_eq!(mock.connections(), 2);
+        assert_eq!(mock.subscriptions(), vec![json!(["logs", filter]); 2]);
+        ws.shutdown();
+    }
+
+    #[tokio::test]
+    async fn failed_reconnects_back_off() {
+        let mock = MockWs::start(|connection| match connection {
+            0 => serve(&[], Some(1)),
+            1..=3 => Session::Refuse,
+            _ => serve(&[1], None),
+        })
+        .await;
+        let ws = WsSupervisor::connect(mock.url().to_owned(), policy(WAIT))
+            .await
+            .unwrap();
+        let mut health = ws.health();
+        let mut pending_txs = ws.pending_txs(PendingTxMode::Hashes);
+
+        assert_eq!(next_tx(&mut pending_txs).await, tx_hash(1));
+        let mut retries = Vec::new();
+        loop {
+            match next_health(&mut health).await {
+                WsHealth::ReconnectFailed {
+                    attempt, retry_in, ..
+                } => retries.push((attempt, retry_in.as_millis())),
+                WsHealth::Reconnected { attempts } => {
+                    assert_eq!(attempts, 4);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(retries, vec![(1, 20), (2, 40), (3, 50)]);
+
+        // receivers end on shutdown
+        ws.shutdown();
+        assert!(time::timeout(WAIT, pending_txs.recv())
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use ethers::prelude::Address;
+
+use super::position::ExitRules;
+use super::safety::SafetyLimits;
+use super::trade::TradeSettings;
+
+/// token to buy with its own trade and exit settings
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub token: Address,
+    pub trade: TradeSettings,
+    /// auto-sell rules, position is held when None
+    pub exit: Option<ExitRules>,
+    /// honeypot check limits, no check when None
+    pub safety: Option<SafetyLimits>,
+}
+
+/** Target state machine
+ * Waiting -> Buying -> Bought | Failed
+ * Buying -> Waiting when the buy is put off until a later detection
+**/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetState {
+    /// liquidity add not seen yet
+    Waiting,
+    /// buy tx in flight
+    Buying,
+    /// buy confirmed
+    Bought,
+    /// buy reverted or could not be sent
+    Failed,
+}
+
+impl fmt::Display for TargetState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TargetState::Waiting => "waiting",
+            TargetState::Buying => "buying",
+            TargetState::Bought => "bought",
+            TargetState::Failed => "failed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// all targets of the bot along with their current state
+#[derive(Debug)]
+pub struct TargetBook {
+    targets: HashMap<Address, Target>,
+    states: Mutex<HashMap<Address, TargetState>>,
+}
+
+impl TargetBook {
+    pub fn new(targets: Vec<Target>) -> TargetBook {
+        let states = targets
+            .iter()
+            .map(|target| (target.token, TargetState::Waiting))
+            .collect();
+        TargetBook {
+            targets: targets
+                .into_iter()
+                .map(|target| (target.token, target))
+                .collect(),
+            states: Mutex::new(states),
+        }
+    }
+
+    pub fn targets(&self) -> impl Iterator<Item = &Target> {
+        self.targets.values()
+    }
+
+    pub fn get(&self, token: &Address) -> Option<&Target> {
+        self.targets.get(token)
+    }
+
+    pub fn state(&self, token: &Address) -> Option<TargetState> {
+        self.lock_states().get(token).copied()
+    }
+
+    /// token is a target still waiting for liquidity
+    pub fn is_waiting(&self, token: &Address) -> bool {
+        self.state(token) == Some(TargetState::Waiting)
+    }
+
+    /// move target from waiting to buying, false when it is already handled
+    pub fn start_buy(&self, token: &Address) -> bool {
+        self.transition(token, TargetState::Waiting, TargetState::Buying)
+    }
+
+    /// move target from buying to bought or failed
+    pub fn finish_buy(&self, token: &Address, bought: bool) -> bool {
+        let next = if bought {
+            TargetState::Bought
+        } else {
+            TargetState::Failed
+        };
+        self.transition(token, TargetState::Buying, next)
+    }
+
+    /// move target from buying back to waiting, a later detection starts the buy again
+    pub fn abort_buy(&self, token: &Address) -> bool {
+        self.transition(token, TargetState::Buying, TargetState::Waiting)
+    }
+
+    /// no target is waiting or buying anymore
+    pub fn all_handled(&self) -> bool {
+        self.lock_states()
+            .values()
+            .all(|state| matches!(state, TargetState::Bought | TargetState::Failed))
+    }
+
+    fn transition(&self, token: &Address, from: TargetState, to: TargetState) -> bool {
+        let mut states = self.lock_states();
+        match states.get_mut(token) {
+            Some(state) if *state == from => {
+                *state = to;
+                tracing::info!("target {:?} {} -> {}", token, from, to);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn lock_states(&self) -> std::sync::MutexGuard<'_, HashMap<Address, TargetState>> {
+        self.states
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::trade::{SwapVariant, TradeSettings};
+
+    fn target(token: Address) -> Target {
+        Target {
+            token,
+            trade: TradeSettings::new("1", 100, 1.0, 1.0, None, SwapVariant::Auto, None).unwrap(),
+            exit: None,
+            safety: None,
+        }
+    }
+
+    #[test]
+    fn target_is_bought_once() {
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let book = TargetBook::new(vec![target(a), target(b)]);
+        assert!(book.is_waiting(&a));
+
+        assert!(book.start_buy(&a));
+        assert!(!book.start_buy(&a));
+        assert!(!book.is_waiting(&a));
+        assert_eq!(book.state(&a), Some(TargetState::Buying));
+        assert!(!book.all_handled());
+
+        assert!(book.finish_buy(&a, true));
+        assert!(!book.finish_buy(&a, false));
+        assert_eq!(book.state(&a), Some(TargetState::Bought));
+        assert!(!book.all_handled());
+
+        assert!(!book.finish_buy(&b, false));
+        assert!(book.start_buy(&b));
+        assert!(book.finish_buy(&b, false));
+        assert_eq!(book.state(&b), Some(TargetState::Failed));
+        assert!(book.all_handled());
+    }
+
+    #[test]
+    fn aborted_buy_can_start_again() {
+        let a = Address::repeat_byte(1);
+        let book = TargetBook::new(vec![target(a)]);
+        assert!(!book.abort_buy(&a));
+
+        assert!(book.start_buy(&a));
+        assert!(book.abort_buy(&a));
+        assert!(book.is_waiting(&a));
+        assert!(!book.all_handled());
+
+        assert!(book.start_buy(&a));
+        assert!(book.finish_buy(&a, true));
+        assert!(!book.abort_buy(&a));
+        assert!(book.all_handled());
+    }
+
+    #[test]
+    fn unknown_token_is_not_a_target() {
+        let book = TargetBook::new(vec![target(Address::repeat_byte(1))]);
+        let other = Address::repeat_byte(9);
+        assert!(!book.is_waiting(&other));
+        assert!(!book.start_buy(&other));
+        assert!(book.get(&other).is_none());
+        assert_eq!(book.state(&other), None);
+    }
+}
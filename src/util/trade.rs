@@ -0,0 +1,336 @@
+use clap::ValueEnum;
+use ethers::types::U256;
+use ethers::utils::parse_ether;
+use serde::Deserialize;
+
+use super::error::EnvSetUpError;
+use super::gas::{self, GasFees, GasStrategy, TxGas};
+
+/// basis points in 100%
+pub const MAX_BPS: u16 = 10_000;
+
+/// native token amount spent per buy when none is configured
+pub const DEFAULT_BUY_AMOUNT: &str = "0.00001";
+
+/// accepted slippage when none is configured
+pub const DEFAULT_SLIPPAGE_BPS: u16 = 5000;
+
+/// gas limit multiplier when none is configured
+pub const DEFAULT_GAS_LIMIT_MULTIPLIER: f64 = 2.0;
+
+/// gas price multiplier when none is configured
+pub const DEFAULT_GAS_PRICE_MULTIPLIER: f64 = 1.0;
+
+/// upper bound for gas multipliers, guards against typos like `20` for `2.0`
+const MAX_GAS_MULTIPLIER: f64 = 100.0;
+
+/// fixed point precision used while scaling gas values
+const MULTIPLIER_PRECISION: f64 = 1_000_000.0;
+
+/// Router swap method family
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SwapVariant {
+    /// fee-on-transfer variant unless the token is known to be tax free
+    Auto,
+    /// `swapExactETHForTokens` / `swapExactTokensForETH`
+    Standard,
+    /// `...SupportingFeeOnTransferTokens`, does not revert on taxed tokens
+    FeeOnTransfer,
+}
+
+/// Limits used to derive the minimum accepted swap output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputLimits {
+    /// accepted slippage in basis points
+    pub slippage_bps: u16,
+    /// transfer tax taken by the token in basis points
+    pub token_tax_bps: u16,
+}
+
+impl OutputLimits {
+    /// minimum output after token tax and slippage for the given router quote
+    pub fn min_amount_out(&self, max_out: U256) -> U256 {
+        apply_slippage(
+            apply_slippage(max_out, self.token_tax_bps),
+            self.slippage_bps,
+        )
+    }
+}
+
+/// Validated trade settings applied to every buy
+#[derive(Debug, Clone, Copy)]
+pub struct TradeSettings {
+    /// native token spent per buy in wei
+    pub buy_amount: U256,
+    /// accepted slippage in basis points
+    pub slippage_bps: u16,
+    /// multiplier applied to the liquidity add tx gas limit
+    pub gas_limit_multiplier: f64,
+    /// multiplier applied to the liquidity add tx gas price
+    pub gas_price_multiplier: f64,
+    /// gas price cap in wei
+    pub max_gas_price: Option<U256>,
+    /// how fees relate to the liquidity add tx
+    pub gas_strategy: GasStrategy,
+    /// configured swap method family
+    pub swap_variant: SwapVariant,
+    /// token transfer tax in basis points, None when unknown
+    pub token_tax_bps: Option<u16>,
+}
+
+impl TradeSettings {
+    /// build trade settings from raw values
+    /// `buy_amount` is in human units of the native token e.g. `0.05`
+    /// `max_gas_price` is in gwei
+    pub fn new(
+        buy_amount: &str,
+        slippage_bps: u16,
+        gas_limit_multiplier: f64,
+        gas_price_multiplier: f64,
+        max_gas_price: Option<&str>,
+        swap_variant: SwapVariant,
+        token_tax_bps: Option<u16>,
+    ) -> Result<TradeSettings, EnvSetUpError> {
+        let buy_amount_wei = parse_ether(buy_amount.trim())
+            .map_err(|_| EnvSetUpError::InvalidBuyAmount(buy_amount.to_owned()))?;
+        // negative amounts parse into wrapped values
+        if buy_amount_wei.is_zero() || buy_amount.trim().starts_with('-') {
+            return Err(EnvSetUpError::InvalidBuyAmount(buy_amount.to_owned()));
+        }
+
+        if slippage_bps > MAX_BPS {
+            return Err(EnvSetUpError::InvalidSlippage(slippage_bps));
+        }
+
+        if let Some(tax_bps) = token_tax_bps.filter(|tax_bps| *tax_bps >= MAX_BPS) {
+            return Err(EnvSetUpError::InvalidTokenTax(tax_bps));
+        }
+
+        validate_multiplier("gas limit", gas_limit_multiplier)?;
+        validate_multiplier("gas price", gas_price_multiplier)?;
+
+        let max_gas_price = max_gas_price.map(gas::parse_gwei).transpose()?;
+
+        Ok(TradeSettings {
+            buy_amount: buy_amount_wei,
+            slippage_bps,
+            gas_limit_multiplier,
+            gas_price_multiplier,
+            max_gas_price,
+            gas_strategy: GasStrategy::Backrun,
+            swap_variant,
+            token_tax_bps,
+        })
+    }
+
+    /// use `gas_strategy`, priority strategy needs max gas price as its cap
+    pub fn with_gas_strategy(
+        mut self,
+        gas_strategy: GasStrategy,
+    ) -> Result<TradeSettings, EnvSetUpError> {
+        if let (GasStrategy::Priority { .. }, None) = (gas_strategy, self.max_gas_price) {
+            return Err(EnvSetUpError::MissingSetting("max_gas_price"));
+        }
+        self.gas_strategy = gas_strategy;
+        Ok(self)
+    }
+
+    /// swap method family to use, `Auto` resolved by the known token tax
+    pub fn swap_variant(&self) -> SwapVariant {
+        match (self.swap_variant, self.token_tax_bps) {
+            (SwapVariant::Auto, Some(0)) => SwapVariant::Standard,
+            (SwapVariant::Auto, _) => SwapVariant::FeeOnTransfer,
+            (variant, _) => variant,
+        }
+    }
+
+    pub fn output_limits(&self) -> OutputLimits {
+        OutputLimits {
+            slippage_bps: self.slippage_bps,
+            token_tax_bps: self.token_tax_bps.unwrap_or(0),
+        }
+    }
+
+    /// gas limit for our tx derived from the liquidity add tx gas limit
+    pub fn gas_limit(&self, source_gas: U256) -> U256 {
+        scale(source_gas, self.gas_limit_multiplier)
+    }
+
+    /// gas fees for our tx derived from the liquidity add tx fees
+    /// scaled by the gas price multiplier, then picked by the gas strategy
+    /// every fee is capped at max gas price when one is set
+    pub fn gas_fees(&self, source_fees: GasFees) -> GasFees {
+        let scaled = source_fees.map(|fee| scale(fee, self.gas_price_multiplier));
+        self.gas_strategy
+            .fees(scaled)
+            .map(|fee| match self.max_gas_price {
+                Some(max_gas_price) => fee.min(max_gas_price),
+                None => fee,
+            })
+    }
+
+    /// gas limit and fees for our tx derived from the liquidity add tx
+    pub fn tx_gas(&self, source: TxGas) -> TxGas {
+        TxGas {
+            gas: self.gas_limit(source.gas),
+            fees: self.gas_fees(source.fees),
+        }
+    }
+}
+
+fn validate_multiplier(name: &'static str, multiplier: f64) -> Result<(), EnvSetUpError> {
+    if multiplier.is_finite() && multiplier > 0.0 && multiplier <= MAX_GAS_MULTIPLIER {
+        Ok(())
+    } else {
+        Err(EnvSetUpError::InvalidGasMultiplier(name, multiplier))
+    }
+}
+
+/// multiply `value` by `multiplier` using fixed point arithmetic
+pub fn scale(value: U256, multiplier: f64) -> U256 {
+    let factor = U256::from((multiplier * MULTIPLIER_PRECISION).round() as u64);
+    value.saturating_mul(factor) / U256::from(MULTIPLIER_PRECISION as u64)
+}
+
+/// reduce `amount` by `bps` basis points
+pub fn apply_slippage(amount: U256, slippage_bps: u16) -> U256 {
+    let keep_bps = MAX_BPS.saturating_sub(slippage_bps);
+    amount.saturating_mul(U256::from(keep_bps)) / U256::from(MAX_BPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(buy_amount: &str, slippage_bps: u16) -> Result<TradeSettings, EnvSetUpError> {
+        TradeSettings::new(
+            buy_amount,
+            slippage_bps,
+            DEFAULT_GAS_LIMIT_MULTIPLIER,
+            DEFAULT_GAS_PRICE_MULTIPLIER,
+            None,
+            SwapVariant::Auto,
+            None,
+        )
+    }
+
+    #[test]
+    fn buy_amount_is_parsed_in_native_units() {
+        let trade = settings(" 0.05 ", 100).unwrap();
+        assert_eq!(trade.buy_amount, U256::exp10(16) * 5);
+        for invalid in ["0", "-1", "abc", ""] {
+            assert!(matches!(
+                settings(invalid, 100),
+                Err(EnvSetUpError::InvalidBuyAmount(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn slippage_is_at_most_full() {
+        assert!(settings("1", MAX_BPS).is_ok());
+        assert!(matches!(
+            settings("1", MAX_BPS + 1),
+            Err(EnvSetUpError::InvalidSlippage(_))
+        ));
+    }
+
+    #[test]
+    fn multipliers_are_bounded() {
+        for multiplier in [0.0, -1.0, f64::NAN, f64::INFINITY, 100.5] {
+            let result =
+                TradeSettings::new("1", 100, multiplier, 1.0, None, SwapVariant::Auto, None);
+            assert!(matches!(
+                result,
+                Err(EnvSetUpError::InvalidGasMultiplier("gas limit", _))
+            ));
+        }
+    }
+
+    #[test]
+    fn auto_variant_follows_known_tax() {
+        let variant = |swap_variant, token_tax_bps| {
+            TradeSettings::new("1", 100, 1.0, 1.0, None, swap_variant, token_tax_bps)
+                .unwrap()
+                .swap_variant()
+        };
+        assert_eq!(variant(SwapVariant::Auto, Some(0)), SwapVariant::Standard);
+        assert_eq!(
+            variant(SwapVariant::Auto, Some(500)),
+            SwapVariant::FeeOnTransfer
+        );
+        assert_eq!(variant(SwapVariant::Auto, None), SwapVariant::FeeOnTransfer);
+        assert_eq!(
+            variant(SwapVariant::Standard, Some(500)),
+            SwapVariant::Standard
+        );
+        assert!(matches!(
+            TradeSettings::new("1", 100, 1.0, 1.0, None, SwapVariant::Auto, Some(MAX_BPS)),
+            Err(EnvSetUpError::InvalidTokenTax(MAX_BPS))
+        ));
+    }
+
+    #[test]
+    fn min_out_accounts_for_tax_and_slippage() {
+        let limits = OutputLimits {
+            slippage_bps: 1000,
+            token_tax_bps: 500,
+        };
+        // 10% slippage on top of the 5% tax
+        assert_eq!(
+            limits.min_amount_out(U256::from(10_000u64)),
+            U256::from(8_550u64)
+        );
+        assert_eq!(
+            OutputLimits::default().min_amount_out(U256::from(10_000u64)),
+            U256::from(10_000u64)
+        );
+    }
+
+    #[test]
+    fn eip1559_fees_are_scaled_and_capped() {
+        let trade =
+            TradeSettings::new("1", 100, 1.0, 2.0, Some("15"), SwapVariant::Auto, None).unwrap();
+        let gwei = |gwei: u64| U256::from(gwei) * U256::exp10(9);
+        let fees = trade.gas_fees(GasFees::Eip1559 {
+            max_fee_per_gas: gwei(10),
+            max_priority_fee_per_gas: gwei(3),
+        });
+        assert_eq!(
+            fees,
+            GasFees::Eip1559 {
+                max_fee_per_gas: gwei(15),
+                max_priority_fee_per_gas: gwei(6),
+            }
+        );
+    }
+
+    #[test]
+    fn priority_strategy_is_capped_at_max_gas_price() {
+        let gwei = |gwei: u64| U256::from(gwei) * U256::exp10(9);
+        let priority = GasStrategy::Priority { premium: gwei(3) };
+        assert!(matches!(
+            settings("1", 100).unwrap().with_gas_strategy(priority),
+            Err(EnvSetUpError::MissingSetting("max_gas_price"))
+        ));
+
+        let trade = TradeSettings::new("1", 100, 1.0, 1.0, Some("10"), SwapVariant::Auto, None)
+            .unwrap()
+            .with_gas_strategy(priority)
+            .unwrap();
+        let legacy = |gas_price| GasFees::Legacy { gas_price };
+        assert_eq!(trade.gas_fees(legacy(gwei(5))), legacy(gwei(8)));
+        assert_eq!(trade.gas_fees(legacy(gwei(9))), legacy(gwei(10)));
+    }
+
+    #[test]
+    fn scale_and_slippage() {
+        let value = U256::from(1_000_000u64);
+        assert_eq!(scale(value, 1.5), U256::from(1_500_000u64));
+        assert_eq!(scale(value, 0.333333), U256::from(333_333u64));
+        assert_eq!(scale(U256::MAX, 2.0), U256::MAX / U256::from(1_000_000u64));
+        assert_eq!(apply_slippage(value, 250), U256::from(975_000u64));
+        assert_eq!(apply_slippage(value, MAX_BPS), U256::zero());
+    }
+}
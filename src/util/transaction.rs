@@ -1,192 +1,485 @@
-use std::{convert::TryInto, sync::Arc};
+use std::sync::Arc;
 
-use ethers::prelude::{
-    Address, Bytes, Contract, Http, Middleware, Provider, ProviderError, Transaction, U256,
-};
+use crate::contract::cake_router::CakeRouter;
+use crate::util::detect::{BlockDetection, DetectionSource};
+use crate::util::error::LiquidityDecodeError;
+use crate::util::gas::{TxGas, TxType};
+use crate::util::liquidity::LiquidityAddEvent;
+use crate::util::pool::ProviderPool;
+use crate::util::retry::{classify, ErrorClass, RetryPolicy};
+use crate::util::target::TargetBook;
+
+use ethers::prelude::{Address, ProviderError, Selector, Transaction};
 
 use tracing::{instrument, Level};
 
+/// liquidity add call found by [`check_tx`]
+#[derive(Debug, Clone)]
+pub struct LiquidityMatch {
+    /// target token the liquidity is added for
+    pub token: Address,
+    /// decoded liquidity add call
+    pub event: LiquidityAddEvent,
+}
+
+/// liquidity add picked up in snipe mode, sent to the swap receiver
+#[derive(Debug, Clone, Copy)]
+pub struct SnipeSignal {
+    pub token: Address,
+    /// liquidity add tx hash
+    pub tx_hash: H256,
+    /// gas of the liquidity add tx, input of the gas strategy
+    pub source_gas: TxGas,
+    pub source: DetectionSource,
+}
+
+impl SnipeSignal {
+    pub fn new(
+        liquidity_match: &LiquidityMatch,
+        transaction: &Transaction,
+        tx_type: TxType,
+    ) -> Self {
+        SnipeSignal {
+            token: liquidity_match.token,
+            tx_hash: transaction.hash,
+            source_gas: TxGas::from_transaction(transaction, tx_type),
+            source: DetectionSource::Mempool,
+        }
+    }
+
+    /// liquidity add found in the logs of a confirmed block
+    pub fn from_block(
+        detection: &BlockDetection,
+        transaction: &Transaction,
+        tx_type: TxType,
+    ) -> Self {
+        SnipeSignal {
+            token: detection.token,
+            tx_hash: detection.tx_hash,
+            source_gas: TxGas::from_transaction(transaction, tx_type),
+            source: DetectionSource::Block,
+        }
+    }
+}
+
 /** Transaction checker function
- * function checks if provided transaction object is of cake router
- * and also the transaction contains deals with desired token only
+ * function checks if provided transaction object is of one of the watched routers
+ * and also the transaction deals with one of the waiting targets
+ * returns the decoded liquidity add call when it matches. Calls of `extra_selectors`
+ * are decoded by their argument layout
 **/
-#[instrument]
+#[instrument(skip(cake_router, targets))]
 pub async fn check_tx(
     transaction: &Transaction,
-    contract_to_watch: &Address,
-    cake_factory: &Contract<Provider<Http>>,
-    desired_token: Arc<Address>,
-) -> bool {
+    routers: &[Address],
+    extra_selectors: &[Selector],
+    cake_router: &CakeRouter,
+    targets: &TargetBook,
+) -> Option<LiquidityMatch> {
     if let Some(tx_to) = transaction.to {
-        if tx_to.eq(&contract_to_watch) {
+        if routers.contains(&tx_to) {
             tracing::info!("tx {:?} is for target contract", transaction.hash);
 
-            // extract method selector from the transaction input
-            let fn_selector = transaction.input.as_ref()[0..=3]
-                .try_into()
-                .expect("got an error");
-
-            // extract method name from the selector
-            let method_name = contract_util::get_method_name(&cake_factory, fn_selector);
-
             // check if the method invoked is liquidity add event
-            if method_name.eq("addLiquidityETH") {
-                let (token, ..) =
-                    contract_util::decode_method_inputs::<
-                        (Address, U256, U256, U256, Address, U256),
-                        Bytes,
-                    >(&cake_factory, fn_selector, transaction.input.clone())
-                    .expect("problem decoding");
-                token.eq(&*desired_token)
-            } else if method_name.eq("addLiquidity") {
-                let (token_a, token_b, ..) =
-                    contract_util::decode_method_inputs::<
-                        (Address, Address, U256, U256, U256, U256, Address, U256),
-                        Bytes,
-                    >(&cake_factory, fn_selector, transaction.input.clone())
-                    .expect("problem decoding");
-                token_a.eq(&*desired_token) || token_b.eq(&*desired_token)
-            } else {
-                // if method invoked is not related to liquidity return false
-                false
-            }
+            let decoded = match LiquidityAddEvent::decode(cake_router, transaction) {
+                Err(LiquidityDecodeError::UnknownSelector(selector))
+                    if extra_selectors.contains(&selector) =>
+                {
+                    LiquidityAddEvent::decode_by_layout(transaction)
+                }
+                decoded => decoded,
+            };
+            let event = match decoded {
+                Ok(event) => event,
+                Err(LiquidityDecodeError::NotLiquidityAdd(_)) => return None,
+                Err(err) => {
+                    tracing::warn!("unable to decode tx {:?}: {}", transaction.hash, err);
+                    return None;
+                }
+            };
+
+            event
+                .tokens()
+                .into_iter()
+                .find(|token| targets.is_waiting(token))
+                .map(|token| LiquidityMatch { token, event })
         } else {
-            // if not related to contract to watch return false
-            false
+            // if not related to contract to watch return None
+            None
         }
     } else {
-        // if tx contract creation return false
-        false
+        // if tx contract creation return None
+        None
     }
 }
 
 use ethers::types::H256;
 use rand::prelude::StdRng;
-use rand::{RngCore, SeedableRng};
+use rand::SeedableRng;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tokio::time;
 use tracing::Instrument;
 
-use crate::util::contract_util;
+/// providers asked in parallel in every round after the first
+const RETRY_FANOUT: usize = 2;
+
+/// result of one fetch round
+enum RoundResult {
+    Found(Box<Transaction>),
+    /// no provider had the tx yet or every error was retryable
+    Missed,
+    Fatal(ProviderError),
+}
 
 /** transaction fetching utitlity
- * manages fetching of transaction with retries for non-propogated transactions
+ * manages fetching of transaction with retries for non-propogated transactions.
+ * The first round asks one provider picked from the pool by score, later rounds ask
+ * two other providers in parallel after a jittered backoff. The fetch ends with the
+ * first hit, on a fatal error, after `max_attempts` rounds or at the deadline
 **/
-#[instrument(skip(providers))]
+#[instrument(skip(pool, retry))]
 pub async fn fetch_transaction(
-    providers: Vec<Arc<Provider<Http>>>,
+    pool: Arc<ProviderPool>,
+    retry: RetryPolicy,
     tx_hash: H256,
 ) -> Option<Transaction> {
-    let mut random: StdRng = SeedableRng::from_entropy();
-    // to prevent bottleneck to only one http provider
-    let arc_provider = Arc::clone(
-        providers
-            .get(random.next_u32() as usize % providers.len())
-            .expect("item_not_found"),
-    );
-
-    tracing::info!("first fetch attempt {:?}", tx_hash);
-    match arc_provider.get_transaction(tx_hash).await {
-        Ok(Some(tx)) => {
-            tracing::info!("got tx in first attempt {:?}", tx_hash);
-            Some(tx)
-        }
-
-        Ok(None) => {
-            tracing::warn!("first fetch attempt returned None");
-            get_transaction_from_any(providers, tx_hash, random).await
-        }
-
-        Err(err) => {
-            let error_msg = match err {
-                /* TODO depending JsonRpcClientError type decide whether to re-fetch the tx or not
-                 *  e.g. in case of 429 Too Many Requests */
-                ProviderError::JsonRpcClientError(rpc_err) => rpc_err.to_string(),
-                ProviderError::EnsError(ens_err) => ens_err,
-                ProviderError::SerdeJson(json_err) => json_err.to_string(),
-                ProviderError::HexError(hex_err) => hex_err.to_string(),
-                ProviderError::CustomError(cust_err) => cust_err,
-                ProviderError::EnsNotOwned(err) => err,
-                ProviderError::HTTPError(err) => err.to_string(),
-                ProviderError::UnsupportedRPC => "Unsupported RPC".into(),
-                ProviderError::UnsupportedNodeClient => "Unsupported Node Client".into(),
-                ProviderError::SignerUnavailable => "Signer Available".into(),
-            };
-            tracing::error!(message = "tx_fetch_error", %error_msg);
+    // requests still in flight are cancelled along with the fetch
+    match time::timeout(retry.deadline, fetch_with_retries(&pool, &retry, tx_hash)).await {
+        Ok(transaction) => transaction,
+        Err(_) => {
+            tracing::error!(
+                "{:?} : transaction fetch gave up after {}ms",
+                tx_hash,
+                retry.deadline.as_millis()
+            );
             None
         }
     }
 }
 
-#[instrument(skip(providers, random))]
-async fn get_transaction_from_any(
-    providers: Vec<Arc<Provider<Http>>>,
+async fn fetch_with_retries(
+    pool: &Arc<ProviderPool>,
+    retry: &RetryPolicy,
     tx_hash: H256,
-    mut random: StdRng,
 ) -> Option<Transaction> {
-    tracing::info!("fetching transaction parallely");
+    let mut random: StdRng = SeedableRng::from_entropy();
+    // providers asked since every provider was last tried
+    let mut tried = Vec::new();
 
-    let (tx_sender, mut tx_receiver) = mpsc::channel::<Option<Transaction>>(6);
+    for attempt in 1..=retry.max_attempts {
+        if attempt > 1 {
+            time::sleep(retry.delay(attempt - 1, &mut random)).await;
+        }
+        // to prevent bottleneck to only one http provider
+        let fanout = if attempt == 1 { 1 } else { RETRY_FANOUT };
+        let indexes = pick_providers(pool, &mut random, &mut tried, fanout);
+        if indexes.is_empty() {
+            tracing::error!("no http provider to fetch tx {:?}", tx_hash);
+            return None;
+        }
 
-    // receiver tokio task
-    let receiver_join_handle = tokio::spawn(async move {
-        let mut found_transaction = None;
-        while let Some(tx) = tx_receiver.recv().await {
-            if tx.is_some() {
-                found_transaction = tx;
+        tracing::info!("fetch attempt {} {:?}", attempt, tx_hash);
+        match fetch_round(pool, tx_hash, &indexes).await {
+            RoundResult::Found(tx) => {
+                tracing::info!("got tx in attempt {} {:?}", attempt, tx_hash);
+                return Some(*tx);
+            }
+            RoundResult::Missed => {}
+            RoundResult::Fatal(err) => {
+                tracing::error!(message = "tx_fetch_error", error_msg = %err);
+                return None;
             }
         }
-        found_transaction
-    });
+    }
 
-    fetch_tx_with_multiple_task(&providers, tx_hash, &mut random, &tx_sender);
-    fetch_tx_with_multiple_task(&providers, tx_hash, &mut random, &tx_sender);
+    tracing::error!(
+        "{:?} : transaction fetch failed even after {} attempts",
+        tx_hash,
+        retry.max_attempts
+    );
+    None
+}
+
+/// up to `count` providers not tried yet, every provider is tried again once all were
+fn pick_providers(
+    pool: &ProviderPool,
+    random: &mut StdRng,
+    tried: &mut Vec<usize>,
+    count: usize,
+) -> Vec<usize> {
+    if tried.len() >= pool.len() {
+        tried.clear();
+    }
+    let mut indexes = Vec::with_capacity(count);
+    while indexes.len() < count {
+        match pool.pick(random, tried) {
+            Some(index) => {
+                tried.push(index);
+                indexes.push(index);
+            }
+            None => break,
+        }
+    }
+    indexes
+}
 
-    let receiver_response = receiver_join_handle.await.expect("tx reciever error");
+/// fetch tasks of a round, aborted when the round ends or is dropped
+struct RoundTasks(Vec<JoinHandle<()>>);
 
-    if receiver_response.is_none() {
-        tracing::error!(
-            "{:?} : transaction fetch retry failed even after three attempts",
-            tx_hash
-        );
+impl Drop for RoundTasks {
+    fn drop(&mut self) {
+        for task in self.0.iter() {
+            task.abort();
+        }
     }
-    receiver_response
 }
 
-#[instrument(skip(providers, random, tx_sender))]
-fn fetch_tx_with_multiple_task(
-    providers: &Vec<Arc<Provider<Http>>>,
-    tx_hash: H256,
-    random: &mut StdRng,
-    tx_sender: &Sender<Option<Transaction>>,
-) {
-    let arc_provider = Arc::clone(
-        providers
-            .get(random.next_u32() as usize % providers.len())
-            .expect("item_not_found"),
+/// ask providers in parallel, the first hit or fatal error cancels the other requests
+#[instrument(skip(pool))]
+async fn fetch_round(pool: &Arc<ProviderPool>, tx_hash: H256, indexes: &[usize]) -> RoundResult {
+    let (tx_sender, mut tx_receiver) = mpsc::channel(indexes.len());
+    let _tasks = RoundTasks(
+        indexes
+            .iter()
+            .map(|&index| {
+                let (pool, tx_sender) = (Arc::clone(pool), tx_sender.clone());
+                let tx_fetch_tx_span = tracing::span!(Level::INFO, "tx_fetch_tx_task", index);
+                tokio::spawn(
+                    async move {
+                        let result = pool.get_transaction(index, tx_hash).await;
+                        // receiver is gone once the round ended
+                        let _ = tx_sender.send((index, result)).await;
+                    }
+                    .instrument(tx_fetch_tx_span),
+                )
+            })
+            .collect(),
     );
-    let tx_sender_clone = tx_sender.clone();
-    let tx_fetch_tx_span = tracing::span!(Level::INFO, "tx_fetch_tx_task_attempt_2");
-
-    tokio::spawn(
-        async move {
-            tracing::info!(message = "fetching tx");
-            match arc_provider.get_transaction(tx_hash).await {
-                Ok(Some(tx)) => {
-                    tracing::info!("got tx successfully");
-                    tx_sender_clone
-                        .send(Some(tx))
-                        .await
-                        .unwrap_or_else(|_| tracing::warn!("tx receiver already closed"))
-                }
-                Ok(None) => {
-                    tracing::warn!("got none")
-                }
-                Err(_) => {
-                    tracing::warn!("got error")
+    drop(tx_sender);
+
+    while let Some((index, result)) = tx_receiver.recv().await {
+        let url = pool.provider(index).url().to_string();
+        match result {
+            Ok(Some(tx)) => return RoundResult::Found(Box::new(tx)),
+            Ok(None) => tracing::warn!("{} does not have tx {:?} yet", url, tx_hash),
+            Err(err) => match classify(&err) {
+                ErrorClass::Retryable => {
+                    tracing::warn!("{} failed to fetch tx {:?}: {}", url, tx_hash, err)
                 }
+                ErrorClass::Fatal => return RoundResult::Fatal(err),
+            },
+        }
+    }
+    RoundResult::Missed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use ethers::prelude::{Http, Provider};
+    use serde_json::Value;
+
+    use ethers::abi::{encode, Token};
+    use ethers::prelude::{LocalWallet, U256};
+    use ethers::utils::id;
+
+    use super::super::backend::PublicRpc;
+    use super::super::mock_rpc::{MockRpc, Reply};
+    use super::super::nonce::NonceManager;
+    use super::super::pool::PoolPolicy;
+    use super::super::submit::{ReplacementPolicy, TxSubmitter};
+    use super::super::target::Target;
+    use super::super::trade::{SwapVariant, TradeSettings};
+    use super::*;
+
+    const GET_TX: &str = "eth_getTransactionByHash";
+
+    fn retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(20),
+            deadline: Duration::from_secs(2),
+        }
+    }
+
+    fn pool(rpcs: &[&MockRpc]) -> Arc<ProviderPool> {
+        let providers: Vec<Arc<Provider<Http>>> =
+            rpcs.iter().map(|rpc| Arc::new(rpc.provider())).collect();
+        ProviderPool::new(&providers, PoolPolicy::default())
+    }
+
+    fn tx_json(tx_hash: H256) -> Value {
+        serde_json::to_value(Transaction {
+            hash: tx_hash,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// mock answering the first `misses` requests with `miss`, then with the tx
+    async fn found_after(misses: usize, miss: Reply, tx_hash: H256) -> MockRpc {
+        let requests = AtomicUsize::new(0);
+        MockRpc::start(move |_, _| {
+            if requests.fetch_add(1, Ordering::SeqCst) < misses {
+                miss.clone()
+            } else {
+                Reply::Result(tx_json(tx_hash))
             }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn missed_tx_is_fetched_again() {
+        let tx_hash = H256::repeat_byte(1);
+        let rpc = found_after(1, Reply::Result(Value::Null), tx_hash).await;
+        let transaction = fetch_transaction(pool(&[&rpc]), retry(), tx_hash).await;
+        assert_eq!(transaction.map(|tx| tx.hash), Some(tx_hash));
+        assert_eq!(rpc.requests(GET_TX).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn retryable_error_is_retried() {
+        let tx_hash = H256::repeat_byte(1);
+        let miss = Reply::Error(-32005, "rate limit exceeded".to_owned());
+        let rpc = found_after(2, miss, tx_hash).await;
+        let transaction = fetch_transaction(pool(&[&rpc]), retry(), tx_hash).await;
+        assert_eq!(transaction.map(|tx| tx.hash), Some(tx_hash));
+        assert_eq!(rpc.requests(GET_TX).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn fatal_error_ends_the_fetch() {
+        let tx_hash = H256::repeat_byte(1);
+        let rpc = found_after(
+            1,
+            Reply::Error(-32602, "invalid params".to_owned()),
+            tx_hash,
+        )
+        .await;
+        assert!(fetch_transaction(pool(&[&rpc]), retry(), tx_hash)
+            .await
+            .is_none());
+        assert_eq!(rpc.requests(GET_TX).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_go_to_two_providers_until_attempts_run_out() {
+        let tx_hash = H256::repeat_byte(1);
+        let mut rpcs = Vec::new();
+        for _ in 0..3 {
+            rpcs.push(MockRpc::start(|_, _| Reply::Result(Value::Null)).await);
         }
-        .instrument(tx_fetch_tx_span),
-    );
+        let pool = pool(&rpcs.iter().collect::<Vec<_>>());
+        assert!(fetch_transaction(pool, retry(), tx_hash).await.is_none());
+        let requests: usize = rpcs.iter().map(|rpc| rpc.requests(GET_TX).len()).sum();
+        assert_eq!(requests, 1 + 2 * RETRY_FANOUT);
+    }
+
+    #[tokio::test]
+    async fn fetch_is_given_up_at_the_deadline() {
+        let tx_hash = H256::repeat_byte(1);
+        let rpc = MockRpc::start(|_, _| Reply::Result(Value::Null)).await;
+        let retry = RetryPolicy {
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_millis(500),
+            deadline: Duration::from_millis(100),
+            ..retry()
+        };
+        assert!(fetch_transaction(pool(&[&rpc]), retry, tx_hash)
+            .await
+            .is_none());
+        assert_eq!(rpc.requests(GET_TX).len(), 1);
+    }
+
+    /// router with the bundled abi, nothing is sent
+    fn cake_router() -> CakeRouter {
+        let provider = Arc::new(Provider::<Http>::try_from("http://127.0.0.1:1").unwrap());
+        let submitter = TxSubmitter::new(
+            Arc::new(NonceManager::new(Arc::clone(&provider), Address::zero())),
+            Arc::new(PublicRpc::new(Vec::new())),
+            ReplacementPolicy::default(),
+            None,
+        );
+        CakeRouter::new(
+            Address::repeat_byte(0xf2),
+            "./abi/cake-router.json".to_owned(),
+            provider,
+            LocalWallet::new(&mut rand::thread_rng()),
+            Arc::new(submitter),
+        )
+        .unwrap()
+    }
+
+    fn add_liquidity_eth(to: Address, method: &str, token: Address) -> Transaction {
+        let mut input = id(format!(
+            "{}(address,uint256,uint256,uint256,address,uint256)",
+            method
+        ))
+        .to_vec();
+        input.extend(encode(&[
+            Token::Address(token),
+            Token::Uint(U256::from(1000)),
+            Token::Uint(U256::from(900)),
+            Token::Uint(U256::from(5)),
+            Token::Address(Address::repeat_byte(2)),
+            Token::Uint(U256::from(99)),
+        ]));
+        Transaction {
+            to: Some(to),
+            input: input.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn liquidity_adds_of_watched_routers_match() {
+        let token = Address::repeat_byte(1);
+        let targets = TargetBook::new(vec![Target {
+            token,
+            trade: TradeSettings::new("1", 100, 1.0, 1.0, None, SwapVariant::Auto, None).unwrap(),
+            exit: None,
+            safety: None,
+        }]);
+        let cake_router = cake_router();
+        let (router, fork) = (Address::repeat_byte(0xf2), Address::repeat_byte(0xf4));
+        let fork_method = id("addLiquidityAVAX(address,uint256,uint256,uint256,address,uint256)");
+        let check = |transaction: Transaction, routers: Vec<Address>, extra: Vec<Selector>| {
+            let (cake_router, targets) = (&cake_router, &targets);
+            async move {
+                check_tx(&transaction, &routers, &extra, cake_router, targets)
+                    .await
+                    .map(|liquidity_match| liquidity_match.token)
+            }
+        };
+
+        let router_add = add_liquidity_eth(router, "addLiquidityETH", token);
+        assert_eq!(check(router_add, vec![router], vec![]).await, Some(token));
+        let fork_add = add_liquidity_eth(fork, "addLiquidityETH", token);
+        assert_eq!(check(fork_add.clone(), vec![router], vec![]).await, None);
+        assert_eq!(
+            check(fork_add, vec![router, fork], vec![]).await,
+            Some(token)
+        );
+
+        let fork_method_add = add_liquidity_eth(fork, "addLiquidityAVAX", token);
+        let routers = vec![router, fork];
+        assert_eq!(
+            check(fork_method_add.clone(), routers.clone(), vec![]).await,
+            None
+        );
+        assert_eq!(
+            check(fork_method_add, routers.clone(), vec![fork_method]).await,
+            Some(token)
+        );
+
+        // liquidity for another token
+        let other = add_liquidity_eth(router, "addLiquidityETH", Address::repeat_byte(9));
+        assert_eq!(check(other, routers, vec![fork_method]).await, None);
+    }
 }
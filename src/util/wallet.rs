@@ -0,0 +1,361 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use ethers::prelude::{Address, LocalWallet, Signer, U256};
+use serde::Deserialize;
+
+use super::error::EnvSetUpError;
+
+/// env var holding the private key when no wallet source is configured
+pub const DEFAULT_PRIVATE_KEY_ENV: &str = "BB_PRIVATE_KEY";
+
+/// env var holding the keystore password when none is configured
+pub const DEFAULT_KEYSTORE_PASSWORD_ENV: &str = "BB_KEYSTORE_PASSWORD";
+
+/// How the buy amount of a target is shared by the wallets
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BuySplit {
+    /// buy amount is split evenly
+    #[default]
+    Even,
+    /// every wallet spends the full buy amount
+    Each,
+    /// buy amount is split by wallet weights
+    Weighted,
+}
+
+/// Validated buy split with the weights of the weighted split
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SplitPlan {
+    pub split: BuySplit,
+    pub weights: Vec<u16>,
+}
+
+impl SplitPlan {
+    /// check the plan fits `wallets` wallets
+    pub fn validate(self, wallets: usize) -> Result<SplitPlan, EnvSetUpError> {
+        if self.split == BuySplit::Weighted {
+            if self.weights.len() != wallets {
+                return Err(EnvSetUpError::InvalidBuySplit(format!(
+                    "{} weights for {} wallets",
+                    self.weights.len(),
+                    wallets
+                )));
+            }
+            if self.weights.iter().all(|weight| *weight == 0) {
+                return Err(EnvSetUpError::InvalidBuySplit(
+                    "weights must not all be 0".to_owned(),
+                ));
+            }
+        }
+        Ok(self)
+    }
+
+    /// buy amount of every wallet, rounding leftovers go to the first wallet
+    pub fn amounts(&self, buy_amount: U256, wallets: usize) -> Vec<U256> {
+        let weights: Vec<U256> = match self.split {
+            BuySplit::Each => return vec![buy_amount; wallets],
+            BuySplit::Even => vec![U256::one(); wallets],
+            BuySplit::Weighted => self.weights.iter().map(|w| U256::from(*w)).collect(),
+        };
+        let total_weight = weights.iter().fold(U256::zero(), |total, w| total + w);
+        if total_weight.is_zero() {
+            return vec![U256::zero(); wallets];
+        }
+
+        let mut amounts: Vec<U256> = weights
+            .iter()
+            .map(|weight| buy_amount.saturating_mul(*weight) / total_weight)
+            .collect();
+        let spent = amounts.iter().fold(U256::zero(), |total, a| total + a);
+        if let Some(first) = amounts.first_mut() {
+            *first += buy_amount.saturating_sub(spent);
+        }
+        amounts
+    }
+}
+
+/// Where the keystore password is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordSource {
+    /// first line of a file
+    File(String),
+    Env(String),
+    /// env var when it is set, terminal prompt otherwise
+    EnvOrPrompt(String),
+    Prompt,
+}
+
+impl Default for PasswordSource {
+    fn default() -> Self {
+        PasswordSource::EnvOrPrompt(DEFAULT_KEYSTORE_PASSWORD_ENV.to_owned())
+    }
+}
+
+impl PasswordSource {
+    /// read the password, it is never logged
+    fn read(&self) -> Result<String, EnvSetUpError> {
+        match self {
+            PasswordSource::File(path) => fs::read_to_string(path)
+                .map(|content| content.lines().next().unwrap_or_default().to_owned())
+                .map_err(|e| EnvSetUpError::PasswordRead(path.to_owned(), e)),
+            PasswordSource::Env(name) => {
+                env::var(name).map_err(|e| EnvSetUpError::EnvVarNotFound(name.to_owned(), e))
+            }
+            PasswordSource::EnvOrPrompt(name) => match env::var(name) {
+                Ok(password) => Ok(password),
+                Err(_) => PasswordSource::Prompt.read(),
+            },
+            PasswordSource::Prompt => rpassword::prompt_password("Keystore password: ")
+                .map_err(|e| EnvSetUpError::PasswordRead("prompt".to_owned(), e)),
+        }
+    }
+}
+
+/// Where the bot wallets are loaded from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalletSources {
+    /// env vars holding hex private keys
+    pub private_key_envs: Vec<String>,
+    /// encrypted json keystore files
+    pub keystores: Vec<String>,
+    /// directory of encrypted json keystores
+    pub keystore_dir: Option<String>,
+    /// password of every keystore
+    pub password: PasswordSource,
+}
+
+/** Wallets the bot signs with
+ * the first one is the primary wallet, used for safety checks
+**/
+#[derive(Clone)]
+pub struct WalletSet {
+    wallets: Vec<LocalWallet>,
+}
+
+impl WalletSet {
+    /** Load every wallet of `sources`
+     * keys from env vars first, then keystore files in given order,
+     * then keystore directory files sorted by name.
+     * Keystore password is read once and only when there are keystores
+     **/
+    pub fn load(sources: &WalletSources) -> Result<WalletSet, EnvSetUpError> {
+        let mut wallets = sources
+            .private_key_envs
+            .iter()
+            .map(|name| {
+                let pvt_key = env::var(name)
+                    .map_err(|e| EnvSetUpError::EnvVarNotFound(name.to_owned(), e))?;
+                Ok(pvt_key.parse::<LocalWallet>()?)
+            })
+            .collect::<Result<Vec<LocalWallet>, EnvSetUpError>>()?;
+
+        let mut keystores: Vec<PathBuf> = sources.keystores.iter().map(PathBuf::from).collect();
+        if let Some(dir) = &sources.keystore_dir {
+            keystores.extend(keystore_files(dir)?);
+        }
+        if !keystores.is_empty() {
+            let password = sources.password.read()?;
+            for path in keystores {
+                let wallet = LocalWallet::decrypt_keystore(&path, &password)
+                    .map_err(|e| EnvSetUpError::KeystoreDecrypt(path.display().to_string(), e))?;
+                wallets.push(wallet);
+            }
+        }
+
+        if wallets.is_empty() {
+            return Err(EnvSetUpError::MissingSetting("wallets"));
+        }
+
+        // the same key loaded twice would share a nonce without a shared nonce manager
+        let mut addresses: Vec<Address> = wallets.iter().map(|w| w.address()).collect();
+        addresses.sort();
+        if let Some(pair) = addresses.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(EnvSetUpError::DuplicateWallet(pair[0]));
+        }
+
+        Ok(WalletSet { wallets })
+    }
+
+    pub fn primary(&self) -> &LocalWallet {
+        &self.wallets[0]
+    }
+
+    pub fn wallets(&self) -> &[LocalWallet] {
+        &self.wallets
+    }
+
+    pub fn len(&self) -> usize {
+        self.wallets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wallets.is_empty()
+    }
+}
+
+/// addresses only, keys never end up in logs
+impl fmt::Debug for WalletSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.wallets.iter().map(|wallet| wallet.address()))
+            .finish()
+    }
+}
+
+/// files of keystore directory sorted by name, hidden files are skipped
+fn keystore_files(dir: &str) -> Result<Vec<PathBuf>, EnvSetUpError> {
+    let entries = fs::read_dir(dir).map_err(|e| EnvSetUpError::KeystoreRead(dir.to_owned(), e))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| EnvSetUpError::KeystoreRead(dir.to_owned(), e))?
+            .path();
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if path.is_file() && !hidden {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(split: BuySplit, weights: Vec<u16>) -> SplitPlan {
+        SplitPlan { split, weights }
+    }
+
+    fn amounts(amounts: &[u64]) -> Vec<U256> {
+        amounts.iter().map(|amount| U256::from(*amount)).collect()
+    }
+
+    #[test]
+    fn buy_amount_is_split_between_wallets() {
+        let buy_amount = U256::from(100u64);
+        assert_eq!(
+            plan(BuySplit::Each, Vec::new()).amounts(buy_amount, 3),
+            amounts(&[100, 100, 100])
+        );
+        // leftover of the rounding goes to the first wallet
+        assert_eq!(
+            plan(BuySplit::Even, Vec::new()).amounts(buy_amount, 3),
+            amounts(&[34, 33, 33])
+        );
+        assert_eq!(
+            plan(BuySplit::Weighted, vec![1, 0, 3]).amounts(buy_amount, 3),
+            amounts(&[25, 0, 75])
+        );
+        assert_eq!(
+            plan(BuySplit::Weighted, vec![1, 1, 1]).amounts(U256::from(2u64), 3),
+            amounts(&[2, 0, 0])
+        );
+    }
+
+    #[test]
+    fn weights_must_fit_wallets() {
+        assert!(plan(BuySplit::Even, Vec::new()).validate(2).is_ok());
+        assert!(plan(BuySplit::Weighted, vec![1, 2]).validate(2).is_ok());
+        for weights in [vec![1], vec![0, 0]] {
+            assert!(matches!(
+                plan(BuySplit::Weighted, weights).validate(2),
+                Err(EnvSetUpError::InvalidBuySplit(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn private_keys_are_loaded_in_order() {
+        let (first, second) = (
+            LocalWallet::new(&mut rand::thread_rng()),
+            LocalWallet::new(&mut rand::thread_rng()),
+        );
+        for (name, wallet) in [
+            ("BB_TEST_KEY_FIRST", &first),
+            ("BB_TEST_KEY_SECOND", &second),
+        ] {
+            env::set_var(name, ethers::utils::hex::encode(wallet.signer().to_bytes()));
+        }
+        let sources = |names: &[&str]| WalletSources {
+            private_key_envs: names.iter().map(|name| name.to_string()).collect(),
+            ..Default::default()
+        };
+
+        let wallets =
+            WalletSet::load(&sources(&["BB_TEST_KEY_FIRST", "BB_TEST_KEY_SECOND"])).unwrap();
+        assert_eq!(wallets.len(), 2);
+        assert_eq!(wallets.primary().address(), first.address());
+        assert_eq!(wallets.wallets()[1].address(), second.address());
+
+        assert!(matches!(
+            WalletSet::load(&sources(&["BB_TEST_KEY_FIRST", "BB_TEST_KEY_FIRST"])),
+            Err(EnvSetUpError::DuplicateWallet(address)) if address == first.address()
+        ));
+        assert!(matches!(
+            WalletSet::load(&sources(&["BB_TEST_KEY_UNSET"])),
+            Err(EnvSetUpError::EnvVarNotFound(_, _))
+        ));
+        assert!(matches!(
+            WalletSet::load(&sources(&[])),
+            Err(EnvSetUpError::MissingSetting("wallets"))
+        ));
+    }
+
+    #[test]
+    fn keystore_dir_files_are_sorted_by_name() {
+        let dir = env::temp_dir().join(format!("block-bot-keystore-dir-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for name in ["b.json", "a.json", ".hidden"] {
+            fs::write(dir.join(name), "{}").unwrap();
+        }
+
+        let files = keystore_files(&dir.display().to_string()).unwrap();
+        assert_eq!(files, vec![dir.join("a.json"), dir.join("b.json")]);
+        assert!(matches!(
+            keystore_files(&dir.join("missing").display().to_string()),
+            Err(EnvSetUpError::KeystoreRead(_, _))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keystore_is_decrypted_with_password() {
+        let dir = env::temp_dir().join(format!("block-bot-keystores-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (wallet, _) =
+            LocalWallet::new_keystore(&dir, &mut rand::thread_rng(), "secret", Some("a.json"))
+                .unwrap();
+        let password_file = dir.join(".password");
+        fs::write(&password_file, "secret\nignored\n").unwrap();
+
+        let sources = WalletSources {
+            keystore_dir: Some(dir.display().to_string()),
+            password: PasswordSource::File(password_file.display().to_string()),
+            ..Default::default()
+        };
+        let wallets = WalletSet::load(&sources).unwrap();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets.primary().address(), wallet.address());
+
+        env::set_var("BB_TEST_WRONG_PASSWORD", "wrong");
+        let wrong_password = WalletSources {
+            keystores: vec![dir.join("a.json").display().to_string()],
+            password: PasswordSource::Env("BB_TEST_WRONG_PASSWORD".to_owned()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            WalletSet::load(&wrong_password),
+            Err(EnvSetUpError::KeystoreDecrypt(_, _))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use ethers::prelude::Transaction;
+use serde_json::json;
+
+use super::detect::BlockDetection;
+use super::transaction::LiquidityMatch;
+
+/** Watch mode recorder
+ * appends every matched liquidity add event as a json line to the record file
+**/
+#[derive(Debug)]
+pub struct WatchRecorder {
+    file: Mutex<File>,
+}
+
+impl WatchRecorder {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<WatchRecorder> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(WatchRecorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, transaction: &Transaction, liquidity: &LiquidityMatch) {
+        let record = json!({
+            "time": chrono::Utc::now().to_rfc3339(),
+            "tx_hash": transaction.hash,
+            "from": transaction.from,
+            "to": transaction.to,
+            "value": transaction.value,
+            "gas": transaction.gas,
+            "gas_price": transaction.gas_price,
+            "token": liquidity.token,
+            "event": liquidity.event,
+            "source": "mempool",
+        });
+
+        tracing::info!(
+            "watch mode: {} for {:?} in tx {:?}: {:?}",
+            liquidity.event.method_name(),
+            liquidity.token,
+            transaction.hash,
+            liquidity.event
+        );
+        self.write(&record);
+    }
+
+    /// liquidity add found in the logs of a confirmed block
+    pub fn record_block(&self, detection: &BlockDetection) {
+        let record = json!({
+            "time": chrono::Utc::now().to_rfc3339(),
+            "tx_hash": detection.tx_hash,
+            "block": detection.block_number,
+            "pair": detection.pair,
+            "token": detection.token,
+            "event": detection.event,
+            "amounts": [detection.amounts.0, detection.amounts.1],
+            "source": "block",
+        });
+
+        tracing::info!(
+            "watch mode: pair {} for {:?} in block {:?}, tx {:?}",
+            detection.event,
+            detection.token,
+            detection.block_number,
+            detection.tx_hash
+        );
+        self.write(&record);
+    }
+
+    fn write(&self, record: &serde_json::Value) {
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        if let Err(err) = writeln!(file, "{}", record) {
+            tracing::error!("unable to write watch record: {}", err);
+        }
+    }
+}